@@ -0,0 +1,177 @@
+//! Backend using gilrs, for controllers SDL doesn't map correctly.
+//!
+//! gilrs doesn't expose the motion sensors, so only the buttons, sticks and
+//! triggers are mapped.
+
+use std::{collections::HashMap, thread::sleep, time::Instant};
+
+use anyhow::{anyhow, Result};
+use cgmath::vec2;
+use gilrs::{Axis, Button, Event, EventType, GamepadId, Gilrs};
+use hid_gamepad_types::JoyKey;
+
+use crate::{
+    calibration::Calibration, config::settings::Settings, engine::Engine, mapping::Buttons,
+    mouse::Mouse, opts::Run, reload, trace::Trace,
+};
+
+use super::Backend;
+
+pub struct GilrsBackend {
+    gilrs: Gilrs,
+}
+
+impl GilrsBackend {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            gilrs: Gilrs::new().map_err(|e| anyhow!("can't initialize gilrs: {}", e))?,
+        })
+    }
+}
+
+impl Backend for GilrsBackend {
+    fn list_devices(&mut self) -> Result<()> {
+        let mut gamepads = self.gilrs.gamepads().peekable();
+        if gamepads.peek().is_none() {
+            println!("No controller detected");
+        } else {
+            println!("Detected controllers:");
+            for (_, gamepad) in gamepads {
+                println!(" - {}", gamepad.name());
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, opts: Run, mut settings: Settings, mut bindings: Buttons) -> Result<()> {
+        let trace = opts.trace_file.as_deref().map(Trace::create).transpose()?;
+        let new_engine = |settings: &Settings, bindings: &Buttons, name: &str| {
+            println!("New controller: {}", name);
+            let mouse = if opts.dry_run {
+                Mouse::dry_run()
+            } else {
+                Mouse::new()?
+            };
+            let mut engine = Engine::new(
+                settings.clone(),
+                bindings.clone(),
+                Calibration::empty(),
+                mouse,
+            )?;
+            if let Some(trace) = &trace {
+                engine.set_trace(trace.for_controller(name));
+            }
+            engine.connected(Instant::now())?;
+            anyhow::Ok(engine)
+        };
+
+        // Controllers already connected don't get a `Connected` event
+        let mut engines: HashMap<GamepadId, Engine> = HashMap::new();
+        for (id, gamepad) in self.gilrs.gamepads() {
+            engines.insert(id, new_engine(&settings, &bindings, gamepad.name())?);
+        }
+        if engines.is_empty() {
+            println!("Waiting for a game controller to connect...");
+        }
+
+        let mut last_tick = Instant::now();
+        let mut watcher = opts
+            .watch
+            .then(|| reload::Watcher::new(&opts.mapping_file, last_tick));
+        loop {
+            let now = Instant::now();
+            let dt = now.duration_since(last_tick);
+
+            let changed = watcher.as_mut().is_some_and(|w| w.changed(now));
+            if reload::requested() || changed {
+                if let Some((new_settings, new_bindings)) = reload::load(&opts.mapping_file) {
+                    settings = new_settings;
+                    bindings = new_bindings;
+                    for engine in engines.values_mut() {
+                        engine.reload(settings.clone(), bindings.clone());
+                    }
+                }
+            }
+
+            while let Some(Event { id, event, .. }) = self.gilrs.next_event() {
+                match event {
+                    EventType::Connected => {
+                        let name = self.gilrs.gamepad(id).name().to_owned();
+                        engines.insert(id, new_engine(&settings, &bindings, &name)?);
+                    }
+                    EventType::Disconnected => {
+                        if let Some(mut engine) = engines.remove(&id) {
+                            println!("Controller disconnected: {}", self.gilrs.gamepad(id).name());
+                            engine.disconnected(now)?;
+                        }
+                    }
+                    EventType::ButtonPressed(button, _) => {
+                        if let (Some(engine), Some(key)) =
+                            (engines.get_mut(&id), gilrs_to_sys(button))
+                        {
+                            engine.key(key, true, now);
+                        }
+                    }
+                    EventType::ButtonReleased(button, _) => {
+                        if let (Some(engine), Some(key)) =
+                            (engines.get_mut(&id), gilrs_to_sys(button))
+                        {
+                            engine.key(key, false, now);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            for (&id, engine) in engines.iter_mut() {
+                let gamepad = self.gilrs.gamepad(id);
+                // Unlike SDL, gilrs already has +y up
+                let stick = |x, y| {
+                    vec2(gamepad.value(x), gamepad.value(y))
+                        .cast::<f64>()
+                        .unwrap()
+                };
+                engine.handle_left_stick(stick(Axis::LeftStickX, Axis::LeftStickY), now, dt);
+                engine.handle_right_stick(stick(Axis::RightStickX, Axis::RightStickY), now, dt);
+
+                let trigger = |button| {
+                    gamepad
+                        .button_data(button)
+                        .map_or(0., |data| data.value() as f64)
+                };
+                engine.handle_triggers(
+                    trigger(Button::LeftTrigger2),
+                    trigger(Button::RightTrigger2),
+                    now,
+                );
+                engine.apply_actions(now)?;
+            }
+
+            last_tick = now;
+            sleep(opts.poll_interval());
+        }
+    }
+}
+
+fn gilrs_to_sys(button: Button) -> Option<JoyKey> {
+    Some(match button {
+        Button::South => JoyKey::S,
+        Button::East => JoyKey::E,
+        Button::West => JoyKey::W,
+        Button::North => JoyKey::N,
+        Button::Select => JoyKey::Minus,
+        Button::Mode => JoyKey::Home,
+        Button::Start => JoyKey::Plus,
+        Button::LeftThumb => JoyKey::L3,
+        Button::RightThumb => JoyKey::R3,
+        Button::LeftTrigger => JoyKey::L,
+        Button::RightTrigger => JoyKey::R,
+        Button::DPadUp => JoyKey::Up,
+        Button::DPadDown => JoyKey::Down,
+        Button::DPadLeft => JoyKey::Left,
+        Button::DPadRight => JoyKey::Right,
+        // Analog, read in the loop like the SDL axes
+        Button::LeftTrigger2 | Button::RightTrigger2 => return None,
+        Button::C | Button::Z | Button::Unknown => return None,
+    })
+}
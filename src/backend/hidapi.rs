@@ -1,14 +1,24 @@
-use std::time::{Duration, Instant};
+use std::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    calibration::BetterCalibration, config::settings::Settings, engine::Engine, mapping::Buttons,
-    mouse::Mouse, opts::Run,
+    calibration::{BetterCalibration, Calibration},
+    config::settings::Settings,
+    engine::Engine,
+    flick_calibration,
+    mapping::Buttons,
+    mouse::Mouse,
+    opts::{FlickCalibrate, Run},
+    reload,
+    trace::Trace,
 };
 
 use anyhow::{bail, Result};
 use enum_map::EnumMap;
 use hid_gamepad::sys::GamepadDevice;
-use hid_gamepad_types::{JoyKey, KeyStatus};
+use hid_gamepad_types::{JoyKey, KeyStatus, Report};
 use joycon::{
     hidapi::HidApi,
     joycon_sys::{
@@ -20,6 +30,19 @@ use joycon::{
 
 use super::Backend;
 
+/// How long the controller must stay still to calibrate the gyro.
+const CALIBRATION_TIME: Duration = Duration::from_secs(1);
+
+/// How long the player lights stay on once the calibration is done.
+const CALIBRATION_DONE_TIME: Duration = Duration::from_millis(500);
+
+const NINTENDO_VENDOR_ID: u16 = 0x057e;
+const JOYCON_L_PRODUCT_ID: u16 = 0x2006;
+const JOYCON_R_PRODUCT_ID: u16 = 0x2007;
+
+/// Left and right halves of a JoyCon pair.
+type JoyConPair = (Box<dyn GamepadDevice>, Box<dyn GamepadDevice>);
+
 pub struct HidapiBackend {
     api: HidApi,
 }
@@ -30,6 +53,27 @@ impl HidapiBackend {
             api: HidApi::new()?,
         })
     }
+
+    /// Open a left and a right JoyCon, if both halves are connected.
+    fn open_joycon_pair(&self) -> Result<Option<JoyConPair>> {
+        let find = |product_id| {
+            self.api.device_list().find(|info| {
+                info.vendor_id() == NINTENDO_VENDOR_ID && info.product_id() == product_id
+            })
+        };
+        match (find(JOYCON_L_PRODUCT_ID), find(JOYCON_R_PRODUCT_ID)) {
+            (Some(left), Some(right)) => {
+                let left = hid_gamepad::open_gamepad(&self.api, left)?;
+                let right = hid_gamepad::open_gamepad(&self.api, right)?;
+                Ok(left.zip(right))
+            }
+            (None, None) => Ok(None),
+            _ => {
+                println!("Only one JoyCon found, using it alone");
+                Ok(None)
+            }
+        }
+    }
 }
 
 impl Backend for HidapiBackend {
@@ -44,11 +88,35 @@ impl Backend for HidapiBackend {
         bail!("No gamepad found");
     }
 
-    fn run(&mut self, _opts: Run, settings: Settings, bindings: Buttons) -> Result<()> {
+    fn run(&mut self, opts: Run, settings: Settings, bindings: Buttons) -> Result<()> {
+        loop {
+            if opts.combine_joycons {
+                if let Some((mut left, mut right)) = self.open_joycon_pair()? {
+                    println!("Combining the left and right JoyCons");
+                    return hid_main(
+                        right.as_mut(),
+                        Some(left.as_mut()),
+                        opts,
+                        settings,
+                        bindings,
+                    );
+                }
+            }
+            for device_info in self.api.device_list() {
+                if let Some(mut gamepad) = hid_gamepad::open_gamepad(&self.api, device_info)? {
+                    return hid_main(gamepad.as_mut(), None, opts, settings, bindings);
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            self.api.refresh_devices()?;
+        }
+    }
+
+    fn flick_calibrate(&mut self, opts: &FlickCalibrate) -> Result<()> {
         loop {
             for device_info in self.api.device_list() {
                 if let Some(mut gamepad) = hid_gamepad::open_gamepad(&self.api, device_info)? {
-                    return hid_main(gamepad.as_mut(), settings, bindings);
+                    return hid_flick_calibrate(gamepad.as_mut(), opts);
                 }
             }
             std::thread::sleep(std::time::Duration::from_secs(1));
@@ -57,7 +125,171 @@ impl Backend for HidapiBackend {
     }
 }
 
-fn hid_main(gamepad: &mut dyn GamepadDevice, settings: Settings, bindings: Buttons) -> Result<()> {
+fn hid_flick_calibrate(gamepad: &mut dyn GamepadDevice, opts: &FlickCalibrate) -> Result<()> {
+    let calibration = calibrate_gyro(gamepad, None)?;
+    let mut mouse = Mouse::new()?;
+    flick_calibration::run(opts, &mut mouse, || {
+        let report = gamepad.recv()?;
+        let dt = Duration::from_secs_f64(1. / report.frequency as f64);
+        Ok(report
+            .motion
+            .iter()
+            .map(|&motion| (calibration.calibrate(motion), dt))
+            .collect())
+    })
+}
+
+/// Drive an engine with `gamepad`, merged with the left JoyCon `partner`
+/// when combining a pair.
+fn hid_main(
+    gamepad: &mut dyn GamepadDevice,
+    mut partner: Option<&mut dyn GamepadDevice>,
+    opts: Run,
+    settings: Settings,
+    bindings: Buttons,
+) -> Result<()> {
+    setup_lights(gamepad, opts.no_gyro)?;
+    if let Some(partner) = partner.as_mut() {
+        setup_lights(&mut **partner, opts.no_gyro)?;
+    }
+
+    let calibration = if opts.no_gyro {
+        Calibration::empty()
+    } else {
+        calibrate_gyro(gamepad, partner.as_deref_mut())?
+    };
+    let mouse = if opts.dry_run {
+        Mouse::dry_run()
+    } else {
+        Mouse::new()?
+    };
+    let mut engine = Engine::new(settings, bindings, calibration, mouse)?;
+    if let Some(path) = &opts.trace_file {
+        engine.set_trace(Trace::create(path)?);
+    }
+    engine.connected(Instant::now())?;
+
+    let mut last_keys = EnumMap::default();
+    let mut last_tick = Instant::now();
+    let mut watcher = opts
+        .watch
+        .then(|| reload::Watcher::new(&opts.mapping_file, last_tick));
+    let mut handle_report = |report: Report| -> Result<()> {
+        let now = Instant::now();
+        let stick_dt = now.duration_since(last_tick);
+        last_tick = now;
+
+        let changed = watcher.as_mut().is_some_and(|w| w.changed(now));
+        if reload::requested() || changed {
+            if let Some((settings, bindings)) = reload::load(&opts.mapping_file) {
+                engine.reload(settings, bindings);
+            }
+        }
+
+        diff(&mut engine, now, &last_keys, &report.keys);
+        last_keys = report.keys;
+
+        engine.handle_left_stick(report.left_joystick, now, stick_dt);
+        engine.handle_right_stick(report.right_joystick, now, stick_dt);
+
+        // Runs Buttons::tick, so hold and double click timers advance at the
+        // report rate of the controller.
+        engine.apply_actions(now)?;
+
+        if !opts.no_gyro {
+            let dt =
+                Duration::from_secs_f64(1. / report.frequency as f64 * report.motion.len() as f64);
+            engine.handle_motion_frame(&report.motion, now, dt)?;
+            engine.flush_gyro(now);
+        }
+        Ok(())
+    };
+    match partner {
+        Some(partner) => read_joycon_pair(partner, gamepad, handle_report),
+        None => loop {
+            handle_report(gamepad.recv()?)?;
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// Feed `handle` with the merged reports of a JoyCon pair.
+///
+/// Each half is read from its own thread, so a late or dropped report from
+/// one of them doesn't hold back the other.
+fn read_joycon_pair(
+    left: &mut dyn GamepadDevice,
+    right: &mut dyn GamepadDevice,
+    mut handle: impl FnMut(Report) -> Result<()>,
+) -> Result<()> {
+    // Concrete halves, so that the threads only get what is `Send`
+    let (left, right) = match (
+        left.as_any().downcast_mut::<JoyCon>(),
+        right.as_any().downcast_mut::<JoyCon>(),
+    ) {
+        (Some(left), Some(right)) => (left, right),
+        _ => bail!("Only JoyCons can be combined"),
+    };
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for (side, half) in [(Side::Left, left), (Side::Right, right)] {
+            let sender = sender.clone();
+            // Stops on error, or once the receiver is dropped
+            scope.spawn(move || loop {
+                let report = GamepadDevice::recv(half);
+                let failed = report.is_err();
+                if sender.send((side, report)).is_err() || failed {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        let mut latest = LatestReports::default();
+        for (side, report) in receiver {
+            if let Some(report) = latest.push(side, report?) {
+                handle(report)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Latest report of each half of a JoyCon pair.
+#[derive(Default)]
+struct LatestReports {
+    left: Option<Report>,
+    right: Option<Report>,
+}
+
+impl LatestReports {
+    /// Merge `report` with the latest one of the other half, once both
+    /// reported.
+    ///
+    /// The motion samples of the right half are only returned once.
+    fn push(&mut self, side: Side, report: Report) -> Option<Report> {
+        match side {
+            Side::Left => self.left = Some(report),
+            Side::Right => self.right = Some(report),
+        }
+        let left = self.left.clone()?;
+        let right = self.right.as_mut()?;
+        let motion = std::mem::take(&mut right.motion);
+        Some(merge_joycons(
+            left,
+            Report {
+                motion,
+                ..right.clone()
+            },
+        ))
+    }
+}
+
+fn setup_lights(gamepad: &mut dyn GamepadDevice, no_gyro: bool) -> Result<()> {
     if let Some(joycon) = gamepad.as_any().downcast_mut::<JoyCon>() {
         dbg!(joycon.set_home_light(light::HomeLight::new(
             0x8,
@@ -66,88 +298,233 @@ fn hid_main(gamepad: &mut dyn GamepadDevice, settings: Settings, bindings: Butto
             &[(0xf, 0xf, 0), (0x2, 0xf, 0)],
         ))?);
 
-        let battery_level = joycon.tick()?.info.battery_level();
+        if no_gyro {
+            show_battery_level(joycon)?;
+        }
+    }
+    Ok(())
+}
 
-        joycon.set_player_light(light::PlayerLights::new(
-            (battery_level >= BatteryLevel::Full).into(),
-            (battery_level >= BatteryLevel::Medium).into(),
-            (battery_level >= BatteryLevel::Low).into(),
-            if battery_level >= BatteryLevel::Low {
-                PlayerLight::On
-            } else {
-                PlayerLight::Blinking
-            },
-        ))?;
+/// Report of a JoyCon pair: the buttons of both halves, the stick of each
+/// side and the motion of the right half.
+///
+/// Each half only reports its own buttons, the SL and SR of both rails are
+/// merged.
+fn merge_joycons(left: Report, right: Report) -> Report {
+    let mut keys = right.keys;
+    for (key, &status) in left.keys.iter() {
+        if status == KeyStatus::Pressed {
+            keys[key] = status;
+        }
     }
+    Report {
+        keys,
+        left_joystick: left.left_joystick,
+        ..right
+    }
+}
 
+/// Wait for the controller to stay still, showing the progress on the player
+/// lights.
+///
+/// The reports of `partner` are dropped meanwhile, so they don't pile up.
+fn calibrate_gyro(
+    gamepad: &mut dyn GamepadDevice,
+    mut partner: Option<&mut (dyn GamepadDevice + '_)>,
+) -> Result<Calibration> {
     let mut calibrator = BetterCalibration::default();
+    let mut lit = None;
 
     println!("calibrating");
     loop {
         let report = gamepad.recv()?;
-        if calibrator.push(report.motion[0], Instant::now(), Duration::from_secs(1)) {
+        if let Some(partner) = partner.as_mut() {
+            partner.recv()?;
+        }
+        let now = Instant::now();
+        if calibrator.push(report.motion[0], now, CALIBRATION_TIME) {
             break;
         }
+        if let Some(joycon) = gamepad.as_any().downcast_mut::<JoyCon>() {
+            let progress = calibrator.progress(now, CALIBRATION_TIME);
+            let count = (progress * 4.) as usize;
+            if lit != Some(count) {
+                joycon.set_player_light(progress_lights(count))?;
+                lit = Some(count);
+            }
+        }
     }
     println!("calibrating done");
-    let mut engine = Engine::new(settings, bindings, calibrator.finish(), Mouse::new());
-
-    let mut last_keys = EnumMap::default();
-    loop {
-        let report = gamepad.recv()?;
-        let now = Instant::now();
-
-        diff(engine.buttons(), now, &last_keys, &report.keys);
-        last_keys = report.keys;
-
-        engine.handle_left_stick(report.left_joystick, now);
-        engine.handle_right_stick(report.right_joystick, now);
+    if let Some(joycon) = gamepad.as_any().downcast_mut::<JoyCon>() {
+        joycon.set_player_light(light::PlayerLights::new(
+            PlayerLight::On,
+            PlayerLight::On,
+            PlayerLight::On,
+            PlayerLight::On,
+        ))?;
+        std::thread::sleep(CALIBRATION_DONE_TIME);
+        show_battery_level(joycon)?;
+    }
+    Ok(calibrator.finish())
+}
 
-        engine.apply_actions(now);
+/// Light up to 4 player lights depending on the battery level, blinking the
+/// last one when low.
+fn show_battery_level(joycon: &mut JoyCon) -> Result<()> {
+    let battery_level = joycon.tick()?.info.battery_level();
+    joycon.set_player_light(light::PlayerLights::new(
+        (battery_level >= BatteryLevel::Full).into(),
+        (battery_level >= BatteryLevel::Medium).into(),
+        (battery_level >= BatteryLevel::Low).into(),
+        if battery_level >= BatteryLevel::Low {
+            PlayerLight::On
+        } else {
+            PlayerLight::Blinking
+        },
+    ))?;
+    Ok(())
+}
 
-        let dt = Duration::from_secs_f64(1. / report.frequency as f64 * report.motion.len() as f64);
-        engine.handle_motion_frame(&report.motion, dt);
-    }
+/// Calibration in progress: `count` lights on from the left, the next one
+/// blinking.
+fn progress_lights(count: usize) -> light::PlayerLights {
+    let light = |i| match i {
+        i if i < count => PlayerLight::On,
+        i if i == count => PlayerLight::Blinking,
+        _ => PlayerLight::Off,
+    };
+    light::PlayerLights::new(light(0), light(1), light(2), light(3))
 }
 
 macro_rules! diff {
-    ($mapping:ident, $now:ident, $old:expr, $new:expr, $key:ident) => {
+    ($engine:ident, $now:ident, $old:expr, $new:expr, $key:ident) => {
         match ($old[$key], $new[$key]) {
-            (KeyStatus::Released, KeyStatus::Pressed) => $mapping.key_down($key, $now),
-            (KeyStatus::Pressed, KeyStatus::Released) => $mapping.key_up($key, $now),
+            (KeyStatus::Released, KeyStatus::Pressed) => $engine.key($key, true, $now),
+            (KeyStatus::Pressed, KeyStatus::Released) => $engine.key($key, false, $now),
             _ => (),
         }
     };
 }
 
 fn diff(
-    mapping: &mut Buttons,
+    engine: &mut Engine,
     now: Instant,
     old: &EnumMap<JoyKey, KeyStatus>,
     new: &EnumMap<JoyKey, KeyStatus>,
 ) {
     use hid_gamepad_types::JoyKey::*;
 
-    diff!(mapping, now, old, new, Up);
-    diff!(mapping, now, old, new, Down);
-    diff!(mapping, now, old, new, Left);
-    diff!(mapping, now, old, new, Right);
-    diff!(mapping, now, old, new, L);
-    diff!(mapping, now, old, new, ZL);
-    diff!(mapping, now, old, new, SL);
-    diff!(mapping, now, old, new, SR);
-    diff!(mapping, now, old, new, L3);
-    diff!(mapping, now, old, new, R3);
-    diff!(mapping, now, old, new, Minus);
-    diff!(mapping, now, old, new, Plus);
-    diff!(mapping, now, old, new, Capture);
-    diff!(mapping, now, old, new, Home);
-    diff!(mapping, now, old, new, W);
-    diff!(mapping, now, old, new, N);
-    diff!(mapping, now, old, new, S);
-    diff!(mapping, now, old, new, E);
-    diff!(mapping, now, old, new, R);
-    diff!(mapping, now, old, new, ZR);
-    diff!(mapping, now, old, new, SL);
-    diff!(mapping, now, old, new, SR);
+    diff!(engine, now, old, new, Up);
+    diff!(engine, now, old, new, Down);
+    diff!(engine, now, old, new, Left);
+    diff!(engine, now, old, new, Right);
+    diff!(engine, now, old, new, L);
+    diff!(engine, now, old, new, ZL);
+    diff!(engine, now, old, new, SL);
+    diff!(engine, now, old, new, SR);
+    diff!(engine, now, old, new, L3);
+    diff!(engine, now, old, new, R3);
+    diff!(engine, now, old, new, Minus);
+    diff!(engine, now, old, new, Plus);
+    diff!(engine, now, old, new, Capture);
+    diff!(engine, now, old, new, Home);
+    diff!(engine, now, old, new, W);
+    diff!(engine, now, old, new, N);
+    diff!(engine, now, old, new, S);
+    diff!(engine, now, old, new, E);
+    diff!(engine, now, old, new, R);
+    diff!(engine, now, old, new, ZR);
+    diff!(engine, now, old, new, SL);
+    diff!(engine, now, old, new, SR);
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::{vec2, Vector2};
+    use hid_gamepad_types::{Acceleration, Motion, RotationSpeed};
+
+    use super::*;
+
+    fn report(
+        pressed: &[JoyKey],
+        left: Vector2<f64>,
+        right: Vector2<f64>,
+        motion: usize,
+    ) -> Report {
+        let mut keys = EnumMap::default();
+        for &key in pressed {
+            keys[key] = KeyStatus::Pressed;
+        }
+        let still = Motion {
+            rotation_speed: RotationSpeed {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            acceleration: Acceleration {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+        };
+        Report {
+            keys,
+            left_joystick: left,
+            right_joystick: right,
+            motion: vec![still; motion],
+            frequency: 66,
+        }
+    }
+
+    #[test]
+    fn merge_halves() {
+        use JoyKey::*;
+
+        let left = report(&[Up, L, SL], vec2(0.5, 0.), vec2(0.1, 0.1), 3);
+        let right = report(&[N, ZR, SR], vec2(0.1, 0.1), vec2(0., -1.), 2);
+        let merged = merge_joycons(left, right);
+
+        let pressed = [Up, N, L, ZR, SL, SR];
+        for (key, &status) in merged.keys.iter() {
+            assert_eq!(
+                status == KeyStatus::Pressed,
+                pressed.contains(&key),
+                "{:?}",
+                key
+            );
+        }
+        assert_eq!(merged.left_joystick, vec2(0.5, 0.));
+        assert_eq!(merged.right_joystick, vec2(0., -1.));
+        assert_eq!(merged.motion.len(), 2);
+    }
+
+    #[test]
+    fn latest_reports() {
+        let mut latest = LatestReports::default();
+        let none = vec2(0., 0.);
+
+        assert!(latest
+            .push(Side::Right, report(&[], none, none, 2))
+            .is_none());
+        let merged = latest
+            .push(Side::Left, report(&[JoyKey::L], none, none, 0))
+            .unwrap();
+        assert_eq!(merged.motion.len(), 2);
+        assert_eq!(merged.keys[JoyKey::L], KeyStatus::Pressed);
+
+        // The left half keeps reporting without the right one
+        let merged = latest
+            .push(Side::Left, report(&[], vec2(1., 0.), none, 0))
+            .unwrap();
+        assert!(merged.motion.is_empty());
+        assert_eq!(merged.keys[JoyKey::L], KeyStatus::Released);
+        assert_eq!(merged.left_joystick, vec2(1., 0.));
+
+        let merged = latest
+            .push(Side::Right, report(&[JoyKey::R], none, none, 1))
+            .unwrap();
+        assert_eq!(merged.motion.len(), 1);
+        assert_eq!(merged.keys[JoyKey::R], KeyStatus::Pressed);
+        assert_eq!(merged.left_joystick, vec2(1., 0.));
+    }
 }
@@ -1,4 +1,8 @@
-use crate::{config::settings::Settings, mapping::Buttons, opts::Run};
+use crate::{
+    config::settings::Settings,
+    mapping::Buttons,
+    opts::{FlickCalibrate, Run},
+};
 
 #[cfg(feature = "sdl2")]
 pub mod sdl;
@@ -6,7 +10,16 @@ pub mod sdl;
 #[cfg(feature = "hidapi")]
 pub mod hidapi;
 
+#[cfg(feature = "gilrs")]
+pub mod gilrs;
+
 pub trait Backend {
     fn list_devices(&mut self) -> anyhow::Result<()>;
     fn run(&mut self, opts: Run, settings: Settings, bindings: Buttons) -> anyhow::Result<()>;
+    fn calibrate(&mut self) -> anyhow::Result<()> {
+        anyhow::bail!("calibration is not supported by this backend")
+    }
+    fn flick_calibrate(&mut self, _opts: &FlickCalibrate) -> anyhow::Result<()> {
+        anyhow::bail!("flick calibration is not supported by this backend")
+    }
 }
@@ -1,31 +1,49 @@
 use std::{
     collections::HashMap,
+    os::raw::c_int,
+    path::Path,
     thread::sleep,
     time::{Duration, Instant},
 };
+#[cfg(not(feature = "sdl2-static"))]
+use std::{ffi::c_void, sync::OnceLock};
 
 use anyhow::{bail, Result};
-use cgmath::{vec2, Vector3};
+use cgmath::{vec2, Deg, Vector3};
 use hid_gamepad_types::{Acceleration, JoyKey, Motion, RotationSpeed};
 use sdl2::{
     self,
     controller::{Axis, Button, GameController},
     event::Event,
+    joystick::PowerLevel,
     keyboard::Keycode,
     sensor::SensorType,
-    GameControllerSubsystem, Sdl,
+    EventPump, GameControllerSubsystem, Sdl,
 };
 
 use crate::{
-    calibration::{BetterCalibration, Calibration},
+    calibration::{self, BetterCalibration, Calibration, TriggerCalibration},
     config::settings::Settings,
-    engine::Engine,
+    engine::{Engine, SessionState},
+    flick_calibration,
     mapping::Buttons,
     mouse::Mouse,
+    opts::FlickCalibrate,
+    reload,
+    trace::Trace,
 };
 
 use super::Backend;
 
+/// How long the state of a disconnected controller is kept around.
+///
+/// Wireless controllers can drop for a short time, we don't want to lose the
+/// gyro toggle or the active layers when it happens.
+const RECONNECT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Time after connection during which the triggers are sampled at rest.
+const TRIGGER_REST_TIME: Duration = Duration::from_millis(500);
+
 pub struct SDLBackend {
     sdl: Sdl,
     game_controller_system: GameControllerSubsystem,
@@ -52,6 +70,49 @@ impl SDLBackend {
             game_controller_system,
         })
     }
+
+    /// Load the SDL mappings at `path` and print the open controllers whose
+    /// layout changed.
+    ///
+    /// SDL applies the new mappings to the already open controllers, no need
+    /// to open them again.
+    fn load_controller_mappings(&self, path: &Path, controllers: &HashMap<u32, ControllerState>) {
+        let before: Vec<_> = controllers
+            .values()
+            .map(|c| c.controller.mapping())
+            .collect();
+        match self.game_controller_system.load_mappings(path) {
+            Ok(count) => println!("Loaded {} controller mappings from {:?}", count, path),
+            Err(e) => {
+                eprintln!("Can't load the controller mappings from {:?}: {}", path, e);
+                return;
+            }
+        }
+        for (c, before) in controllers.values().zip(before) {
+            if c.controller.mapping() != before {
+                println!("New layout for {}", c.controller.name());
+            }
+        }
+    }
+
+    /// Wait for a controller to connect, for the interactive commands.
+    fn wait_controller(&self, event_pump: &mut EventPump) -> GameController {
+        println!("Waiting for a game controller to connect...");
+        loop {
+            for event in event_pump.poll_iter() {
+                if let Event::ControllerDeviceAdded { which, .. } = event {
+                    match self.game_controller_system.open(which) {
+                        Ok(controller) if controller.name() != "Steam Virtual Gamepad" => {
+                            return controller;
+                        }
+                        Ok(_) => {}
+                        Err(e) => eprintln!("Can't open controller {}: {}", which, e),
+                    }
+                }
+            }
+            sleep(Duration::from_millis(10));
+        }
+    }
 }
 
 impl Backend for SDLBackend {
@@ -65,8 +126,10 @@ impl Backend for SDLBackend {
         } else {
             println!("Detected controllers:");
             for i in 0..num_joysticks {
-                let controller = self.game_controller_system.open(i)?;
-                println!(" - {}", controller.name());
+                match self.game_controller_system.open(i) {
+                    Ok(controller) => println!(" - {}", controller.name()),
+                    Err(e) => println!(" - can't open controller {}: {}", i, e),
+                }
             }
         }
         Ok(())
@@ -74,9 +137,9 @@ impl Backend for SDLBackend {
 
     fn run(
         &mut self,
-        _opts: crate::opts::Run,
-        settings: Settings,
-        bindings: Buttons,
+        opts: crate::opts::Run,
+        mut settings: Settings,
+        mut bindings: Buttons,
     ) -> anyhow::Result<()> {
         if self
             .game_controller_system
@@ -92,22 +155,63 @@ impl Backend for SDLBackend {
             .expect("can't create the SDL event pump");
 
         let mut controllers: HashMap<u32, ControllerState> = HashMap::new();
+        // Keyed by controller name, as the instance id changes on reconnection
+        let mut disconnected: HashMap<String, (Instant, SessionState)> = HashMap::new();
+        // Gyro calibrations by controller name, reused on reconnection to skip
+        // the calibration wait
+        let mut calibrations: HashMap<String, Calibration> = HashMap::new();
+
+        // Controller driving the cursor in area stick modes, the last one to
+        // push its stick. The others would teleport the cursor to their own
+        // target.
+        let mut area_owner = None;
+
+        let trace = opts.trace_file.as_deref().map(Trace::create).transpose()?;
+
+        if let Some(path) = &opts.controller_mappings {
+            self.load_controller_mappings(path, &controllers);
+        }
 
         let mut last_tick = Instant::now();
+        let mut watcher = opts
+            .watch
+            .then(|| reload::Watcher::new(&opts.mapping_file, last_tick));
 
         'running: loop {
             let now = Instant::now();
             let dt = now.duration_since(last_tick);
 
+            disconnected.retain(|_, (since, _)| now.duration_since(*since) < RECONNECT_WINDOW);
+
+            let changed = watcher.as_mut().is_some_and(|w| w.changed(now));
+            if reload::requested() || changed {
+                if let Some(path) = &opts.controller_mappings {
+                    self.load_controller_mappings(path, &controllers);
+                }
+                if let Some((new_settings, new_bindings)) = reload::load(&opts.mapping_file) {
+                    settings = new_settings;
+                    bindings = new_bindings;
+                    for controller in controllers.values_mut() {
+                        controller.engine.reload(settings.clone(), bindings.clone());
+                    }
+                }
+            }
+
             for event in event_pump.poll_iter() {
                 match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
+                    Event::Quit { .. } => break 'running,
+                    Event::KeyDown {
                         keycode: Some(Keycode::Escape),
                         ..
-                    } => break 'running,
+                    } if !opts.no_escape_quit => break 'running,
                     Event::ControllerDeviceAdded { which, .. } => {
-                        let mut controller = self.game_controller_system.open(which)?;
+                        let mut controller = match self.game_controller_system.open(which) {
+                            Ok(controller) => controller,
+                            Err(e) => {
+                                eprintln!("Can't open controller {}: {}", which, e);
+                                continue;
+                            }
+                        };
 
                         if controllers
                             .values()
@@ -121,41 +225,96 @@ impl Backend for SDLBackend {
                         }
 
                         println!("New controller: {}", controller.name());
+                        let power_level = power_level(&controller);
+                        print_power_level(&controller.name(), power_level);
 
+                        let calibration = if opts.recalibrate {
+                            None
+                        } else {
+                            calibrations.get(&controller.name()).copied()
+                        };
                         // Ignore errors, handled later
-                        let calibrator = if controller
+                        let calibrator = if opts.no_gyro {
+                            None
+                        } else if controller
                             .sensor_set_enabled(SensorType::Accelerometer, true)
                             .and(controller.sensor_set_enabled(SensorType::Gyroscope, true))
                             .is_ok()
                         {
-                            println!(
-                                "Starting calibration for {}, don't move the controller...",
-                                controller.name()
-                            );
-                            Some(BetterCalibration::default())
+                            if calibration.is_some() {
+                                println!("Reusing the calibration of {}", controller.name());
+                                None
+                            } else {
+                                println!(
+                                    "Starting calibration for {}, don't move the controller...",
+                                    controller.name()
+                                );
+                                Some(BetterCalibration::default())
+                            }
                         } else {
                             let _ = controller.set_rumble(220, 440, 100);
                             None
                         };
 
-                        let engine = Engine::new(
+                        let mouse = if opts.dry_run {
+                            Mouse::dry_run()
+                        } else {
+                            Mouse::new()?
+                        };
+                        let mut engine = Engine::new(
                             settings.clone(),
                             bindings.clone(),
-                            Calibration::empty(),
-                            Mouse::new()?,
+                            calibration.unwrap_or_else(Calibration::empty),
+                            mouse,
                         )?;
+                        if let Some(trace) = &trace {
+                            engine.set_trace(trace.for_controller(&controller.name()));
+                        }
+                        if let Some((_, state)) = disconnected.remove(&controller.name()) {
+                            engine.restore_session_state(state);
+                        }
+                        engine.connected(now)?;
                         controllers.insert(
                             controller.instance_id(),
                             ControllerState {
                                 controller,
                                 engine,
                                 calibrator,
+                                connected: now,
+                                triggers: Default::default(),
+                                gyro_timestamp: None,
+                                power_level,
                             },
                         );
                     }
                     Event::ControllerDeviceRemoved { which, .. } => {
-                        if let Some(controller) = controllers.remove(&which) {
+                        if let Some(mut controller) = controllers.remove(&which) {
                             println!("Controller disconnected: {}", controller.controller.name());
+                            // Saved first so the actions don't carry over to a
+                            // reconnection
+                            disconnected.insert(
+                                controller.controller.name(),
+                                (now, controller.engine.session_state()),
+                            );
+                            controller.engine.disconnected(now)?;
+                        }
+                    }
+                    Event::ControllerButtonDown {
+                        which,
+                        button: Button::Touchpad,
+                        ..
+                    } => {
+                        if let Some(controller) = controllers.get_mut(&which) {
+                            controller.engine.touchpad_click(true, now);
+                        }
+                    }
+                    Event::ControllerButtonUp {
+                        which,
+                        button: Button::Touchpad,
+                        ..
+                    } => {
+                        if let Some(controller) = controllers.get_mut(&which) {
+                            controller.engine.touchpad_click(false, now);
                         }
                     }
                     Event::ControllerButtonDown {
@@ -163,11 +322,10 @@ impl Backend for SDLBackend {
                         which,
                         button,
                     } => {
-                        if let Some(controller) = controllers.get_mut(&which) {
-                            controller
-                                .engine
-                                .buttons()
-                                .key_down(sdl_to_sys(button), now);
+                        if let (Some(controller), Some(key)) =
+                            (controllers.get_mut(&which), sdl_to_sys(button))
+                        {
+                            controller.engine.key(key, true, now);
                         }
                     }
                     Event::ControllerButtonUp {
@@ -175,16 +333,52 @@ impl Backend for SDLBackend {
                         which,
                         button,
                     } => {
+                        if let (Some(controller), Some(key)) =
+                            (controllers.get_mut(&which), sdl_to_sys(button))
+                        {
+                            controller.engine.key(key, false, now);
+                        }
+                    }
+                    // DualShock 4 and DualSense only have one touchpad
+                    Event::ControllerTouchpadDown {
+                        which,
+                        finger,
+                        x,
+                        y,
+                        ..
+                    }
+                    | Event::ControllerTouchpadMotion {
+                        which,
+                        finger,
+                        x,
+                        y,
+                        ..
+                    } => {
+                        if let Some(controller) = controllers.get_mut(&which) {
+                            let position = vec2(x, y).cast::<f64>().expect("can't cast f32 to f64");
+                            controller
+                                .engine
+                                .touch(finger as usize, Some(position), now);
+                        }
+                    }
+                    Event::ControllerTouchpadUp { which, finger, .. } => {
                         if let Some(controller) = controllers.get_mut(&which) {
-                            controller.engine.buttons().key_up(sdl_to_sys(button), now);
+                            controller.engine.touch(finger as usize, None, now);
                         }
                     }
                     _ => {}
                 }
             }
 
-            for controller in controllers.values_mut() {
+            area_owner = area_owner.filter(|id| controllers.contains_key(id));
+
+            for (&id, controller) in controllers.iter_mut() {
                 let c = &mut controller.controller;
+                let level = power_level(c);
+                if level != controller.power_level {
+                    print_power_level(&c.name(), level);
+                    controller.power_level = level;
+                }
                 let engine = &mut controller.engine;
                 let mut left = vec2(c.axis(Axis::LeftX), c.axis(Axis::LeftY))
                     .cast::<f64>()
@@ -195,72 +389,352 @@ impl Backend for SDLBackend {
                     .expect("can't cast i16 to f64")
                     / (i16::MAX as f64);
 
-                // In SDL, -..+ y is top..bottom
+                // In SDL, -..+ y is top..bottom, while `Stick` expects up to
+                // be +y
                 left.y = -left.y;
                 right.y = -right.y;
 
+                if engine.area_stick_active(left, right) {
+                    area_owner = Some(id);
+                }
+                engine.set_area_mouse_enabled(area_owner.is_none() || area_owner == Some(id));
                 engine.handle_left_stick(left, now, dt);
                 engine.handle_right_stick(right, now, dt);
 
-                if c.sensor_enabled(SensorType::Accelerometer)
+                let triggers = read_triggers(c);
+                if now.duration_since(controller.connected) < TRIGGER_REST_TIME {
+                    for (calibration, raw) in controller.triggers.iter_mut().zip(triggers) {
+                        calibration.push_rest(raw);
+                    }
+                }
+                engine.handle_triggers(
+                    controller.triggers[0].normalize(triggers[0]),
+                    controller.triggers[1].normalize(triggers[1]),
+                    now,
+                );
+
+                if !opts.no_gyro
+                    && c.sensor_enabled(SensorType::Accelerometer)
                     && c.sensor_enabled(SensorType::Gyroscope)
                 {
-                    let mut accel = [0.; 3];
-                    c.sensor_get_data(SensorType::Accelerometer, &mut accel)?;
-                    let acceleration = Acceleration::from(
-                        Vector3::from(accel)
-                            .cast::<f64>()
-                            .expect("can't cast f32 to f64")
-                            / 9.82,
-                    );
-                    let mut gyro = [0.; 3];
-                    c.sensor_get_data(SensorType::Gyroscope, &mut gyro)?;
-                    let rotation_speed = RotationSpeed::from(
-                        Vector3::from(gyro)
-                            .cast::<f64>()
-                            .expect("can't cast f32 to f64")
-                            / std::f64::consts::PI
-                            * 180.,
-                    );
+                    let motion = read_motion(c, settings.gyro.scale)?;
+                    let timestamp = gyro_timestamp(c);
+                    let last_timestamp =
+                        std::mem::replace(&mut controller.gyro_timestamp, timestamp);
 
                     if let Some(ref mut calibrator) = controller.calibrator {
-                        let finished = calibrator.push(
-                            Motion {
-                                rotation_speed,
-                                acceleration,
-                            },
-                            now,
-                            Duration::from_secs(2),
-                        );
+                        let finished = calibrator.push(motion, now, Duration::from_secs(2));
                         if finished {
                             println!("Calibration finished for {}", c.name());
                             let _ = c.set_rumble(220, 440, 100);
-                            engine.set_calibration(calibrator.finish());
+                            let calibration = calibrator.finish();
+                            calibrations.insert(c.name(), calibration);
+                            engine.set_calibration(calibration);
                             controller.calibrator = None;
                         }
-                    } else {
-                        engine.apply_motion(rotation_speed, acceleration, now, dt);
+                    } else if let Some(sample_dt) = sample_dt(last_timestamp, timestamp, dt) {
+                        engine.apply_motion(
+                            motion.rotation_speed,
+                            motion.acceleration,
+                            now,
+                            sample_dt,
+                        )?;
                     }
                 }
+                engine.flush_gyro(now);
                 engine.apply_actions(now)?;
+                if let Some(rumble) = engine.take_rumble() {
+                    let _ = c.set_rumble(
+                        rumble.low_frequency,
+                        rumble.high_frequency,
+                        rumble.duration.as_millis() as u32,
+                    );
+                }
             }
 
             last_tick = now;
+            sleep(opts.poll_interval());
+        }
+
+        Ok(())
+    }
+
+    fn calibrate(&mut self) -> anyhow::Result<()> {
+        let mut event_pump = self
+            .sdl
+            .event_pump()
+            .expect("can't create the SDL event pump");
+
+        let mut controller = self.wait_controller(&mut event_pump);
+        let has_sensors = controller
+            .sensor_set_enabled(SensorType::Accelerometer, true)
+            .and(controller.sensor_set_enabled(SensorType::Gyroscope, true))
+            .is_ok();
+
+        println!(
+            "Calibrating {}, put it down and don't touch the triggers...",
+            controller.name()
+        );
+        let mut calibrator = BetterCalibration::default();
+        let mut triggers = [TriggerCalibration::new(0., 0.); 2];
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(3) {
+            event_pump.pump_events();
+            for (calibration, raw) in triggers.iter_mut().zip(read_triggers(&controller)) {
+                calibration.push_rest(raw);
+            }
+            if has_sensors {
+                calibrator.push(
+                    read_motion(&controller, 1.)?,
+                    Instant::now(),
+                    Duration::from_secs(2),
+                );
+            }
+            sleep(Duration::from_millis(1));
+        }
+
+        println!("Now fully pull both triggers a few times...");
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(3) {
+            event_pump.pump_events();
+            for (calibration, raw) in triggers.iter_mut().zip(read_triggers(&controller)) {
+                calibration.push_full(raw);
+            }
             sleep(Duration::from_millis(1));
         }
 
+        if has_sensors {
+            let calibration = calibrator.finish();
+            println!("Gyro: {:?}", calibration);
+
+            println!("Lay the controller flat and turn it exactly one full turn...");
+            let mut yaw = Deg(0.);
+            let mut last = Instant::now();
+            let start = last;
+            while start.elapsed() < Duration::from_secs(5) {
+                event_pump.pump_events();
+                let now = Instant::now();
+                let motion = calibration.calibrate(read_motion(&controller, 1.)?);
+                yaw += Deg(motion.rotation_speed.y * now.duration_since(last).as_secs_f64());
+                last = now;
+                sleep(Duration::from_millis(1));
+            }
+            println!("Measured a turn of {:.0}°, expected 360°", yaw.0.abs());
+            if let Some(scale) = calibration::turn_scale(yaw) {
+                println!("Add GYRO_SCALE = {:.3} to your configuration", scale);
+            }
+        }
+        for (name, calibration) in ["ZL", "ZR"].iter().zip(triggers) {
+            println!("{}: {}", name, calibration);
+        }
+        let _ = controller.set_rumble(220, 440, 100);
         Ok(())
     }
+
+    fn flick_calibrate(&mut self, opts: &FlickCalibrate) -> anyhow::Result<()> {
+        let mut event_pump = self
+            .sdl
+            .event_pump()
+            .expect("can't create the SDL event pump");
+
+        let mut controller = self.wait_controller(&mut event_pump);
+        if controller
+            .sensor_set_enabled(SensorType::Accelerometer, true)
+            .and(controller.sensor_set_enabled(SensorType::Gyroscope, true))
+            .is_err()
+        {
+            bail!("{} has no motion sensors", controller.name());
+        }
+        println!("Calibrating {}, put it down...", controller.name());
+        let mut calibrator = BetterCalibration::default();
+        loop {
+            event_pump.pump_events();
+            let motion = read_motion(&controller, 1.)?;
+            if calibrator.push(motion, Instant::now(), Duration::from_secs(2)) {
+                break;
+            }
+            sleep(Duration::from_millis(1));
+        }
+        let calibration = calibrator.finish();
+        let _ = controller.set_rumble(220, 440, 100);
+
+        let mut mouse = Mouse::new()?;
+        let mut last = Instant::now();
+        flick_calibration::run(opts, &mut mouse, || {
+            sleep(Duration::from_millis(1));
+            event_pump.pump_events();
+            let now = Instant::now();
+            let motion = calibration.calibrate(read_motion(&controller, 1.)?);
+            let dt = now.duration_since(last);
+            last = now;
+            Ok(vec![(motion, dt)])
+        })
+    }
 }
 
 struct ControllerState {
     controller: GameController,
     engine: Engine,
     calibrator: Option<BetterCalibration>,
+    connected: Instant,
+    triggers: [TriggerCalibration; 2],
+    /// Timestamp of the last gyro sample, in µs.
+    gyro_timestamp: Option<u64>,
+    power_level: PowerLevel,
+}
+
+/// Motion in g and °/s.
+fn read_motion(controller: &GameController, gyro_scale: f64) -> Result<Motion> {
+    let mut accel = [0.; 3];
+    controller.sensor_get_data(SensorType::Accelerometer, &mut accel)?;
+    let mut gyro = [0.; 3];
+    controller.sensor_get_data(SensorType::Gyroscope, &mut gyro)?;
+    Ok(Motion {
+        acceleration: Acceleration::from(
+            Vector3::from(accel)
+                .cast::<f64>()
+                .expect("can't cast f32 to f64")
+                / 9.82,
+        ),
+        rotation_speed: RotationSpeed::from(
+            Vector3::from(gyro)
+                .cast::<f64>()
+                .expect("can't cast f32 to f64")
+                .map(f64::to_degrees)
+                * gyro_scale,
+        ),
+    })
+}
+
+/// Timestamp of the current gyro sample in µs, if the controller and the
+/// SDL version report them.
+fn gyro_timestamp(controller: &GameController) -> Option<u64> {
+    let get_sensor_data = sensor_data_with_timestamp()?;
+    let mut timestamp = 0;
+    let mut data = [0f32; 3];
+    let res = unsafe {
+        let raw = sdl2::sys::SDL_GameControllerFromInstanceID(controller.instance_id() as i32);
+        get_sensor_data(
+            raw,
+            sdl2::sys::SDL_SensorType::SDL_SENSOR_GYRO,
+            &mut timestamp,
+            data.as_mut_ptr(),
+            data.len() as c_int,
+        )
+    };
+    (res == 0 && timestamp != 0).then_some(timestamp)
+}
+
+/// `SDL_GameControllerGetSensorDataWithTimestamp`, missing from the sdl2-sys
+/// bindings.
+type GetSensorDataWithTimestamp = unsafe extern "C" fn(
+    gamecontroller: *mut sdl2::sys::SDL_GameController,
+    type_: sdl2::sys::SDL_SensorType,
+    timestamp: *mut u64,
+    data: *mut f32,
+    num_values: c_int,
+) -> c_int;
+
+/// The bundled SDL of static builds is recent enough to link it directly.
+#[cfg(feature = "sdl2-static")]
+fn sensor_data_with_timestamp() -> Option<GetSensorDataWithTimestamp> {
+    extern "C" {
+        fn SDL_GameControllerGetSensorDataWithTimestamp(
+            gamecontroller: *mut sdl2::sys::SDL_GameController,
+            type_: sdl2::sys::SDL_SensorType,
+            timestamp: *mut u64,
+            data: *mut f32,
+            num_values: c_int,
+        ) -> c_int;
+    }
+    Some(SDL_GameControllerGetSensorDataWithTimestamp)
+}
+
+/// Looked up at runtime, as it was added in SDL 2.26 and the system SDL may
+/// be older.
+#[cfg(not(feature = "sdl2-static"))]
+fn sensor_data_with_timestamp() -> Option<GetSensorDataWithTimestamp> {
+    static FUNCTION: OnceLock<Option<GetSensorDataWithTimestamp>> = OnceLock::new();
+    *FUNCTION.get_or_init(|| {
+        let version = sdl2::version::version();
+        if (version.major, version.minor) < (2, 26) {
+            return None;
+        }
+        let symbol = lookup_sdl_symbol(b"SDL_GameControllerGetSensorDataWithTimestamp\0");
+        // Safety: the symbol has this signature since SDL 2.26
+        (!symbol.is_null()).then(|| unsafe {
+            std::mem::transmute::<*mut c_void, GetSensorDataWithTimestamp>(symbol)
+        })
+    })
 }
 
-fn sdl_to_sys(button: Button) -> JoyKey {
-    match button {
+#[cfg(all(unix, not(feature = "sdl2-static")))]
+fn lookup_sdl_symbol(name: &[u8]) -> *mut c_void {
+    unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr().cast()) }
+}
+
+#[cfg(all(windows, not(feature = "sdl2-static")))]
+fn lookup_sdl_symbol(name: &[u8]) -> *mut c_void {
+    use std::os::raw::c_char;
+
+    extern "system" {
+        fn GetModuleHandleA(name: *const c_char) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+    }
+
+    unsafe {
+        let module = GetModuleHandleA(b"SDL2.dll\0".as_ptr().cast());
+        if module.is_null() {
+            return std::ptr::null_mut();
+        }
+        GetProcAddress(module, name.as_ptr().cast())
+    }
+}
+
+/// Time covered by the current gyro sample, `None` if it was already handled.
+///
+/// Without timestamps, the sample is assumed to cover the whole frame.
+fn sample_dt(last: Option<u64>, timestamp: Option<u64>, frame_dt: Duration) -> Option<Duration> {
+    match (last, timestamp) {
+        (Some(last), Some(timestamp)) if timestamp == last => None,
+        (Some(last), Some(timestamp)) if timestamp > last => {
+            Some(Duration::from_micros(timestamp - last))
+        }
+        _ => Some(frame_dt),
+    }
+}
+
+/// Battery level of the controller, missing from the sdl2 `GameController`.
+fn power_level(controller: &GameController) -> PowerLevel {
+    unsafe {
+        let raw = sdl2::sys::SDL_GameControllerFromInstanceID(controller.instance_id() as i32);
+        PowerLevel::from_ll(sdl2::sys::SDL_JoystickCurrentPowerLevel(
+            sdl2::sys::SDL_GameControllerGetJoystick(raw),
+        ))
+    }
+}
+
+fn print_power_level(name: &str, level: PowerLevel) {
+    match level {
+        PowerLevel::Unknown => {}
+        PowerLevel::Empty | PowerLevel::Low => {
+            eprintln!("Warning: the battery of {} is {:?}", name, level)
+        }
+        PowerLevel::Medium | PowerLevel::Full | PowerLevel::Wired => {
+            println!("Battery of {}: {:?}", name, level)
+        }
+    }
+}
+
+/// Raw trigger values, between 0 and 1.
+fn read_triggers(controller: &GameController) -> [f64; 2] {
+    [Axis::TriggerLeft, Axis::TriggerRight]
+        .map(|axis| controller.axis(axis) as f64 / i16::MAX as f64)
+}
+
+/// `None` for the buttons without a `JoyKey` equivalent.
+fn sdl_to_sys(button: Button) -> Option<JoyKey> {
+    Some(match button {
         Button::A => JoyKey::S,
         Button::B => JoyKey::E,
         Button::X => JoyKey::W,
@@ -276,11 +750,12 @@ fn sdl_to_sys(button: Button) -> JoyKey {
         Button::DPadDown => JoyKey::Down,
         Button::DPadLeft => JoyKey::Left,
         Button::DPadRight => JoyKey::Right,
-        Button::Misc1 => todo!(),
-        Button::Paddle1 => todo!(),
-        Button::Paddle2 => todo!(),
-        Button::Paddle3 => todo!(),
-        Button::Paddle4 => todo!(),
-        Button::Touchpad => todo!(),
-    }
+        // SDL reports the SL and SR buttons of single Joy-Cons as paddles,
+        // 2 and 4 on the left one, 3 and 1 on the right one.
+        Button::Paddle2 | Button::Paddle3 => JoyKey::SL,
+        Button::Paddle1 | Button::Paddle4 => JoyKey::SR,
+        // Handled as `VirtualKey::TouchpadClick`
+        Button::Touchpad => return None,
+        Button::Misc1 => return None,
+    })
 }
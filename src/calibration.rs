@@ -1,4 +1,4 @@
-use cgmath::{num_traits::zero, MetricSpace, Vector3};
+use cgmath::{num_traits::zero, Deg, MetricSpace, Vector3};
 use hid_gamepad_types::{Acceleration, Motion, RotationSpeed};
 use std::{
     collections::VecDeque,
@@ -21,6 +21,68 @@ impl Calibration {
     }
 }
 
+/// Highest raw value taken as a trigger at rest. Samples above come from a
+/// trigger held down while calibrating.
+const MAX_TRIGGER_REST: f64 = 0.3;
+
+/// Resting and fully pulled values of an analog trigger.
+///
+/// Triggers don't all rest at exactly 0 or reach exactly 1, so raw values are
+/// normalized before being compared with `TRIGGER_THRESHOLD`.
+#[derive(Debug, Clone, Copy)]
+pub struct TriggerCalibration {
+    rest: f64,
+    full: f64,
+}
+
+impl TriggerCalibration {
+    pub fn new(rest: f64, full: f64) -> Self {
+        Self { rest, full }
+    }
+
+    /// Record a sample taken while the trigger should be released.
+    pub fn push_rest(&mut self, raw: f64) {
+        if raw < MAX_TRIGGER_REST {
+            self.rest = self.rest.max(raw);
+        }
+    }
+
+    /// Record a sample taken while the trigger is being pulled.
+    pub fn push_full(&mut self, raw: f64) {
+        self.full = self.full.max(raw);
+    }
+
+    pub fn normalize(&self, raw: f64) -> f64 {
+        if self.full - self.rest < 0.1 {
+            return raw;
+        }
+        ((raw - self.rest) / (self.full - self.rest)).clamp(0., 1.)
+    }
+}
+
+impl Default for TriggerCalibration {
+    fn default() -> Self {
+        Self::new(0., 1.)
+    }
+}
+
+impl std::fmt::Display for TriggerCalibration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rest {:.3}, full {:.3}", self.rest, self.full)
+    }
+}
+
+/// `GYRO_SCALE` correcting a full turn measured as `measured`, if it is off by
+/// more than 5%.
+pub fn turn_scale(measured: Deg<f64>) -> Option<f64> {
+    let scale = 360. / measured.0.abs();
+    if scale.is_finite() && (scale - 1.).abs() > 0.05 {
+        Some(scale)
+    } else {
+        None
+    }
+}
+
 type Entry = Vector3<f64>;
 
 #[derive(Clone, Debug)]
@@ -137,6 +199,18 @@ impl BetterCalibration {
         finished
     }
 
+    /// How far `push` is from finishing, from 0 to 1. Moving the controller
+    /// starts over.
+    #[cfg_attr(not(feature = "hidapi"), allow(dead_code))]
+    pub fn progress(&self, now: Instant, limit: Duration) -> f64 {
+        match self.state {
+            BetterCalibrationState::Moving => 0.,
+            BetterCalibrationState::Static { start, .. } => {
+                (now.duration_since(start).as_secs_f64() / limit.as_secs_f64()).min(1.)
+            }
+        }
+    }
+
     pub fn finish(mut self) -> Calibration {
         if let BetterCalibrationState::Static { sum, count, .. } = self.state {
             if self.last_count < count {
@@ -172,3 +246,80 @@ impl Default for BetterCalibration {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trigger_normalization() {
+        let mut calibration = TriggerCalibration::new(0., 0.8);
+        calibration.push_rest(0.05);
+        calibration.push_rest(0.1);
+        calibration.push_rest(0.02);
+        // Held down while calibrating
+        calibration.push_rest(0.9);
+
+        assert_eq!(calibration.normalize(0.), 0.);
+        assert_eq!(calibration.normalize(0.1), 0.);
+        assert!((calibration.normalize(0.45) - 0.5).abs() < 1e-9);
+        assert_eq!(calibration.normalize(0.8), 1.);
+        assert_eq!(calibration.normalize(1.), 1.);
+
+        let mut calibration = TriggerCalibration::new(0., 0.);
+        for raw in [0.4, 0.9, 0.7] {
+            calibration.push_full(raw);
+        }
+        assert_eq!(calibration.normalize(0.9), 1.);
+        assert!((calibration.normalize(0.45) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn calibration_progress() {
+        let still = Motion {
+            rotation_speed: RotationSpeed {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            acceleration: Acceleration {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+        };
+        let moving = Motion {
+            rotation_speed: RotationSpeed {
+                x: 100.,
+                y: 0.,
+                z: 0.,
+            },
+            ..still
+        };
+        let limit = Duration::from_secs(1);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let mut calibrator = BetterCalibration::default();
+        assert_eq!(calibrator.progress(at(0), limit), 0.);
+        // The first sample differs from the default one, which has no gravity
+        calibrator.push(still, at(0), limit);
+        calibrator.push(still, at(0), limit);
+        assert_eq!(calibrator.progress(at(500), limit), 0.5);
+        calibrator.push(moving, at(600), limit);
+        assert_eq!(calibrator.progress(at(600), limit), 0.);
+        calibrator.push(still, at(700), limit);
+        calibrator.push(still, at(800), limit);
+        assert!(calibrator.push(still, at(1800), limit));
+        assert_eq!(calibrator.progress(at(2000), limit), 1.);
+    }
+
+    #[test]
+    fn full_turn_scale() {
+        assert_eq!(turn_scale(Deg(360.)), None);
+        assert_eq!(turn_scale(Deg(-350.)), None);
+        assert_eq!(turn_scale(Deg(180.)), Some(2.));
+        assert_eq!(turn_scale(Deg(-720.)), Some(0.5));
+        assert_eq!(turn_scale(Deg(0.)), None);
+    }
+}
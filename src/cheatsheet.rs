@@ -0,0 +1,119 @@
+//! `cheatsheet` command, summarizing the bindings of a configuration file.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use enum_map::Enum;
+
+use crate::{
+    mapping::{Action, Buttons, Layer, MapKey},
+    ClickType,
+};
+
+/// Bindings grouped by layer, one line per key with each of its events.
+pub fn cheatsheet(bindings: &Buttons) -> String {
+    let mut layers: BTreeMap<u8, Vec<(String, String)>> = BTreeMap::new();
+    for (key, layer, binding) in bindings.bindings() {
+        if let Some(summary) = summary(binding) {
+            layers
+                .entry(layer)
+                .or_default()
+                .push((key.to_string(), summary));
+        }
+    }
+    let simul: Vec<_> = bindings
+        .simul_bindings()
+        .filter_map(|([k1, k2], binding)| Some((format!("{}+{}", k1, k2), summary(binding)?)))
+        .collect();
+
+    let mut out = String::new();
+    for (layer, keys) in layers {
+        let title = if layer == 0 {
+            "Base layer".to_owned()
+        } else {
+            format!("While {} is held", MapKey::from_usize(layer.into()))
+        };
+        section(&mut out, &title, &keys);
+    }
+    if !simul.is_empty() {
+        section(&mut out, "Pressed together", &simul);
+    }
+    out
+}
+
+fn section(out: &mut String, title: &str, keys: &[(String, String)]) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(title);
+    out.push('\n');
+    for (key, summary) in keys {
+        let _ = writeln!(out, "  {:<10} {}", key, summary);
+    }
+}
+
+/// Actions of each event of `binding`, `None` if it has none.
+///
+/// Layer changes and the releases mirroring a press are left out.
+fn summary(binding: &Layer) -> Option<String> {
+    let hold = match binding.hold_delay {
+        Some(delay) => format!("hold ({:?})", delay),
+        None => "hold".to_owned(),
+    };
+    let events = [
+        ("down", &binding.on_down),
+        ("release", &binding.on_up),
+        ("tap", &binding.on_click),
+        ("double tap", &binding.on_double_click),
+        (hold.as_str(), &binding.on_hold_down),
+        ("hold release", &binding.on_hold_up),
+        ("turbo", &binding.on_turbo_down),
+    ];
+    let parts: Vec<_> = events
+        .iter()
+        .filter_map(|(event, actions)| {
+            let actions: Vec<_> = actions
+                .iter()
+                .filter_map(|action| match action {
+                    Action::Ext(a) if !matches!(a.click_type(), Some(ClickType::Release)) => {
+                        Some(a.to_string())
+                    }
+                    _ => None,
+                })
+                .collect();
+            (!actions.is_empty()).then(|| format!("{}: {}", event, actions.join(", ")))
+        })
+        .collect();
+    (!parts.is_empty()).then(|| parts.join("; "))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{self, settings::Settings};
+
+    use super::*;
+
+    #[test]
+    fn layers() {
+        let mut bindings = Buttons::new();
+        let errors = config::parse_file(
+            "S = a\nE = b c_300\nN,N = d\nW,E = GYRO_OFF\nW+N = !e/",
+            &mut Settings::default(),
+            &mut bindings,
+        );
+        assert!(errors.is_empty());
+        assert_eq!(
+            cheatsheet(&bindings),
+            "Base layer
+  N          double tap: Click Unicode('d')
+  S          down: Press Unicode('a')
+  E          tap: Click Unicode('b'); hold (300ms): Press Unicode('c')
+
+While W is held
+  E          down: Press gyro off
+
+Pressed together
+  W+N        release: Click Unicode('e')
+"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! `INCLUDE "path"` directives, spliced into the including file before
+//! parsing.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+
+/// A configuration file with its includes expanded.
+#[derive(Debug, Default)]
+pub struct Source {
+    pub content: String,
+    files: Vec<PathBuf>,
+    /// Index in `files` and line number of each line of `content`.
+    lines: Vec<(usize, usize)>,
+}
+
+impl Source {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let mut source = Source::default();
+        source.expand(path, &mut Vec::new())?;
+        Ok(source)
+    }
+
+    /// File and line number in that file of `line`, counting from 1 in
+    /// `content`.
+    pub fn origin(&self, line: usize) -> (&Path, usize) {
+        let (file, line) = self.lines[line - 1];
+        (&self.files[file], line)
+    }
+
+    /// Whether `line` comes from an included file.
+    pub fn is_included(&self, line: usize) -> bool {
+        self.lines[line - 1].0 != 0
+    }
+
+    /// `line` as shown to the user, with the file name if it was included.
+    pub fn location(&self, line: usize) -> String {
+        let (file, line_nb) = self.origin(line);
+        if self.is_included(line) {
+            format!("{} line {}", file.display(), line_nb)
+        } else {
+            format!("line {}", line_nb)
+        }
+    }
+
+    /// The configuration file and the files it includes.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    fn expand(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("opening config file {:?}", path))?;
+        if stack.contains(&canonical) {
+            bail!("{:?} includes itself", path);
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config file {:?}", path))?;
+        let file = self.files.len();
+        self.files.push(path.to_owned());
+        stack.push(canonical);
+        for (i, line) in content.lines().enumerate() {
+            match super::parse::include(line) {
+                Some(included) => {
+                    let included = path.parent().unwrap_or(Path::new("")).join(included);
+                    self.expand(&included, stack)
+                        .with_context(|| format!("line {} of {:?}", i + 1, path))?;
+                }
+                None => {
+                    self.content.push_str(line);
+                    self.content.push('\n');
+                    self.lines.push((file, i + 1));
+                }
+            }
+        }
+        stack.pop();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("gyromouse-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("common")).unwrap();
+        std::fs::write(
+            dir.join("main.txt"),
+            "S = a\nINCLUDE \"common/gyro.txt\"\nE = b",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("common/gyro.txt"),
+            "GYRO_SENS = 2\ninclude loop.txt",
+        )
+        .unwrap();
+        std::fs::write(dir.join("common/loop.txt"), "INCLUDE ../main.txt").unwrap();
+
+        let err = Source::read(&dir.join("main.txt")).unwrap_err();
+        assert!(format!("{:?}", err).contains("includes itself"));
+
+        std::fs::write(dir.join("common/loop.txt"), "N = c # no loop").unwrap();
+        let source = Source::read(&dir.join("main.txt")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            source.content,
+            "S = a\nGYRO_SENS = 2\nN = c # no loop\nE = b\n"
+        );
+        assert_eq!(source.origin(2), (dir.join("common/gyro.txt").as_path(), 1));
+        assert_eq!(source.origin(3), (dir.join("common/loop.txt").as_path(), 1));
+        assert!(!source.is_included(4));
+        assert_eq!(source.origin(4), (dir.join("main.txt").as_path(), 3));
+        assert_eq!(source.location(4), "line 3");
+        assert_eq!(
+            source.location(2),
+            format!("{} line 1", dir.join("common/gyro.txt").display())
+        );
+        assert_eq!(source.files().len(), 3);
+    }
+}
@@ -1,10 +1,19 @@
+use std::time::Duration;
+
 use crate::{
-    mapping::{Action, Buttons, Layer},
+    mapping::{Action, Buttons, ExtAction, Layer, MapKey},
     ClickType,
 };
 
-use self::{parse::Error, settings::Settings, types::*};
+use self::{
+    parse::Error,
+    settings::{Profile, Settings, DEFAULT_PROFILE},
+    types::*,
+};
+
+pub use self::include::Source;
 
+mod include;
 mod parse;
 pub mod settings;
 pub mod types;
@@ -13,67 +22,232 @@ pub fn parse_file<'a>(
     source: &'a str,
     settings: &mut Settings,
     mapping: &mut Buttons,
+) -> Vec<nom::Err<Error<'a>>> {
+    parse_with_locations(source, settings, mapping, &|line| format!("line {}", line))
+}
+
+/// Like `parse_file`, with the warnings locating lines in the file they come
+/// from.
+pub fn parse_source<'a>(
+    source: &'a Source,
+    settings: &mut Settings,
+    mapping: &mut Buttons,
+) -> Vec<nom::Err<Error<'a>>> {
+    parse_with_locations(&source.content, settings, mapping, &|line| {
+        source.location(line)
+    })
+}
+
+/// `location` describes a line number of `source` in the warnings.
+fn parse_with_locations<'a>(
+    source: &'a str,
+    settings: &mut Settings,
+    mapping: &mut Buttons,
+    location: &dyn Fn(usize) -> String,
 ) -> Vec<nom::Err<Error<'a>>> {
     let (cmds, errors) = parse::jsm_parse(source);
-    for cmd in cmds {
-        match cmd {
-            Cmd::Map(Key::Simple(key), ref actions) => map_key(mapping.get(key, 0), actions),
-            // Double click
-            Cmd::Map(Key::Chorded(k1, k2), ref actions) if k1 == k2 => {
-                // TODO: Correctly handle modifiers for double click
-                for action in actions {
-                    assert_eq!(
-                        action.event_mod, None,
-                        "event modificators not supported on double click"
-                    );
-                    push(
-                        &mut mapping.get(k1, 0).on_double_click,
-                        action,
-                        ClickType::Click,
-                    );
-                }
-            }
-            Cmd::Map(Key::Chorded(k1, k2), ref actions) => {
-                mapping
-                    .get(k1, 0)
-                    .on_down
-                    .push(Action::Layer(k1.to_layer(), true));
-                mapping
-                    .get(k1, 0)
-                    .on_up
-                    .push(Action::Layer(k1.to_layer(), false));
-                map_key(mapping.get(k2, k1.to_layer()), actions);
-            }
-            Cmd::Map(Key::Simul(_k1, _k2), ref _actions) => {
-                // TODO: Support simultaneous key presses
-                eprintln!("Warning: simultaneous keys are unsupported for now");
+    let names: Vec<String> = cmds
+        .iter()
+        .filter_map(|(_, cmd)| match cmd {
+            Cmd::Profile(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut stick_settings = Vec::new();
+    let mut profiles: Vec<(Profile, Vec<_>)> = Vec::new();
+    for (line, cmd) in cmds {
+        if let Cmd::Profile(name) = cmd {
+            let profile = Profile {
+                name,
+                settings: Settings::default(),
+                buttons: Buttons::new(),
+            };
+            profiles.push((profile, Vec::new()));
+            continue;
+        }
+        let (settings, mapping, stick_settings) = match profiles.last_mut() {
+            Some((profile, stick_settings)) => {
+                (&mut profile.settings, &mut profile.buttons, stick_settings)
             }
-            Cmd::Setting(setting) => settings.apply(setting),
-            Cmd::Reset => {
-                settings.reset();
-                mapping.reset()
+            None => (&mut *settings, &mut *mapping, &mut stick_settings),
+        };
+        apply_cmd(
+            line,
+            cmd,
+            &names,
+            settings,
+            mapping,
+            stick_settings,
+            location,
+        );
+    }
+    settings.stick.check_zones();
+    warn_unused_stick_settings(source, settings, &stick_settings, location);
+    for (profile, stick_settings) in &mut profiles {
+        profile.settings.stick.check_zones();
+        warn_unused_stick_settings(source, &profile.settings, stick_settings, location);
+    }
+    settings.profiles = profiles.into_iter().map(|(profile, _)| profile).collect();
+    errors
+}
+
+fn apply_cmd(
+    line: usize,
+    cmd: Cmd,
+    profiles: &[String],
+    settings: &mut Settings,
+    mapping: &mut Buttons,
+    stick_settings: &mut Vec<(usize, StickSetting)>,
+    location: &dyn Fn(usize) -> String,
+) {
+    match cmd {
+        Cmd::Map(Key::Simple(key), ref actions) => map_key(mapping.get(key, 0), actions),
+        // Double click
+        Cmd::Map(Key::Chorded(k1, k2), ref actions) if k1 == k2 => {
+            // TODO: Correctly handle modifiers for double click
+            for action in actions {
+                assert_eq!(
+                    action.event_mod, None,
+                    "event modificators not supported on double click"
+                );
+                push(
+                    &mut mapping.get(k1, 0).on_double_click,
+                    action,
+                    ClickType::Click,
+                );
             }
-            Cmd::Special(s) => {
-                // TODO: Support special key presses
-                eprintln!("Warning: special key {:?} is unsupported for now", s);
+        }
+        Cmd::Map(Key::Chorded(k1, k2), ref actions) => {
+            push_layer(mapping, k1);
+            map_key(mapping.get(k2, k1.to_layer()), actions);
+        }
+        Cmd::Map(Key::Simul(k1, k2), ref actions) => map_key(mapping.get_simul(k1, k2), actions),
+        Cmd::Setting(Setting::Buttons(setting)) => mapping.apply(setting),
+        Cmd::Setting(setting) => {
+            if let Setting::Stick(stick_setting) = setting {
+                stick_settings.push((line, stick_setting));
             }
+            settings.apply(setting)
+        }
+        Cmd::LayerSetting(key, setting) => {
+            push_layer(mapping, key);
+            mapping.add_layer_gyro_setting(key.to_layer(), setting.validated());
+        }
+        Cmd::Reset => {
+            settings.reset();
+            mapping.reset();
+            stick_settings.clear();
+        }
+        Cmd::Special(s) => {
+            // TODO: Support special key presses
+            eprintln!("Warning: special key {:?} is unsupported for now", s);
+        }
+        Cmd::Profile(_) => unreachable!("profiles are handled by parse_with_locations"),
+        Cmd::SwitchProfile(key, name) => {
+            let index = if name.eq_ignore_ascii_case(DEFAULT_PROFILE) {
+                Some(0)
+            } else {
+                profiles
+                    .iter()
+                    .position(|p| p.eq_ignore_ascii_case(&name))
+                    .map(|i| i + 1)
+            };
+            let action = match index {
+                Some(i) => Action::Ext(ExtAction::SwitchProfile(i)),
+                None => {
+                    eprintln!("Warning: {}: unknown profile {}", location(line), name);
+                    return;
+                }
+            };
+            let layer = match key {
+                Key::Simple(key) => &mut mapping.get(key, 0).on_down,
+                Key::Chorded(k1, k2) if k1 == k2 => &mut mapping.get(k1, 0).on_double_click,
+                Key::Chorded(k1, k2) => {
+                    push_layer(mapping, k1);
+                    &mut mapping.get(k2, k1.to_layer()).on_down
+                }
+                Key::Simul(k1, k2) => &mut mapping.get_simul(k1, k2).on_down,
+            };
+            layer.push(action);
         }
     }
-    errors
 }
 
-fn convert_action_mod(action: &JSMAction, default: ClickType) -> Option<Action> {
-    if let ActionType::Special(s) = action.action {
-        if s == SpecialKey::None {
-            return None;
-        }
+/// Activate the layer of `key` while it is down.
+fn push_layer(mapping: &mut Buttons, key: MapKey) {
+    mapping
+        .get(key, 0)
+        .on_down
+        .push(Action::Layer(key.to_layer(), true));
+    mapping
+        .get(key, 0)
+        .on_up
+        .push(Action::Layer(key.to_layer(), false));
+}
+
+fn warn_unused_stick_settings(
+    source: &str,
+    settings: &Settings,
+    stick_settings: &[(usize, StickSetting)],
+    location: &dyn Fn(usize) -> String,
+) {
+    for (line, mode) in unused_stick_settings(settings, stick_settings) {
+        let text = source.lines().nth(line - 1).unwrap_or_default().trim();
+        eprintln!(
+            "Warning: {} (\"{}\") has no effect, no stick is in {} mode",
+            location(line),
+            text,
+            mode
+        );
     }
+}
+
+/// Stick settings that don't apply to any of the final stick modes, with
+/// the mode they need.
+fn unused_stick_settings(
+    settings: &Settings,
+    stick_settings: &[(usize, StickSetting)],
+) -> Vec<(usize, &'static str)> {
+    use StickMode::*;
+
+    let modes = [
+        settings.left_stick_mode,
+        settings.right_stick_mode,
+        settings.stick.motion.mode,
+    ];
+    stick_settings
+        .iter()
+        .filter_map(|(line, setting)| {
+            let (needed, name): (&[StickMode], _) = match setting {
+                StickSetting::Aim(_) => (&[Aim], "AIM"),
+                StickSetting::Flick(_) => (&[Flick, FlickOnly, RotateOnly], "FLICK"),
+                StickSetting::Scroll(_) => (&[ScrollWheel], "SCROLL_WHEEL"),
+                StickSetting::Button(_) => (&[NoMouse], "NO_MOUSE"),
+                StickSetting::Area(_) => (&[MouseRing, MouseArea], "MOUSE_RING or MOUSE_AREA"),
+                StickSetting::Deadzone(_)
+                | StickSetting::DeadzoneCurve(_)
+                | StickSetting::FullZone(_)
+                | StickSetting::LeftFullZone(_)
+                | StickSetting::RightFullZone(_)
+                | StickSetting::MaxFrameTime(_)
+                | StickSetting::Motion(_) => return None,
+            };
+            if modes.iter().any(|mode| needed.contains(mode)) {
+                None
+            } else {
+                Some((*line, name))
+            }
+        })
+        .collect()
+}
+
+fn convert_action_mod(action: &JSMAction, default: ClickType) -> Action {
     let action_type = match action.action_mod {
         None => default,
         Some(ActionModifier::Toggle) => ClickType::Toggle,
         Some(ActionModifier::Instant) => ClickType::Click,
     };
-    Some(Action::Ext((action.action, action_type).into()))
+    Action::Ext((action.action, action_type).into())
 }
 
 fn map_key(layer: &mut Layer, actions: &[JSMAction]) {
@@ -96,6 +270,9 @@ fn map_key(layer: &mut Layer, actions: &[JSMAction]) {
                 push(&mut layer.on_click, action, ClickType::Click);
             }
             Hold => {
+                if action.hold_delay.is_some() {
+                    layer.hold_delay = action.hold_delay;
+                }
                 push(&mut layer.on_hold_down, action, ClickType::Press);
                 if action.action_mod.is_none() {
                     push(&mut layer.on_hold_up, action, ClickType::Release);
@@ -108,36 +285,552 @@ fn map_key(layer: &mut Layer, actions: &[JSMAction]) {
                 }
             }
             Release => {
-                if action.action_mod.is_none() {
-                    eprintln!("action modifier required on release event type");
-                } else {
-                    push(&mut layer.on_up, action, ClickType::Click);
-                }
+                push(&mut layer.on_up, action, ClickType::Click);
+            }
+            TapOrTurbo => {
+                push(&mut layer.on_click, action, ClickType::Click);
+                push_turbo(layer, action);
             }
             Turbo => {
-                // TODO: Implement turbo keys
-                eprintln!("Warning: Turbo event modifier is unsupported for now.");
+                // Starts as soon as the key is down
+                layer.turbo_delay = Some(Duration::ZERO);
+                push_turbo(layer, action);
             }
         }
         first = false;
     }
 }
 
-fn push(actions: &mut Vec<Action>, action: &JSMAction, default: ClickType) {
-    if let Some(action) = convert_action_mod(action, default) {
-        actions.push(action);
+fn push_turbo(layer: &mut Layer, action: &JSMAction) {
+    push(&mut layer.on_turbo_down, action, ClickType::Press);
+    if action.action_mod.is_none() {
+        push(&mut layer.on_turbo_up, action, ClickType::Release);
     }
 }
 
+fn push(actions: &mut Vec<Action>, action: &JSMAction, default: ClickType) {
+    actions.push(convert_action_mod(action, default));
+}
+
 #[cfg(test)]
 mod test {
-    use crate::config::parse::jsm_parse;
+    use std::time::{Duration, Instant};
+
+    use enigo::Key;
+    use hid_gamepad_types::JoyKey;
+
+    use crate::{
+        config::{
+            parse::jsm_parse,
+            parse_file,
+            settings::Settings,
+            types::{Cmd, GyroSetting, Setting, TouchpadMode},
+            unused_stick_settings,
+        },
+        joystick::StickSide,
+        mapping::{Buttons, ExtAction, VirtualKey},
+        ClickType,
+    };
+
+    fn parse(source: &str) -> (Settings, Buttons) {
+        let mut settings = Settings::default();
+        let mut bindings = Buttons::new();
+        let errors = parse_file(source, &mut settings, &mut bindings);
+        assert!(errors.is_empty());
+        (settings, bindings)
+    }
 
     #[test]
     fn parse_all_settings() {
         let settings_str = include_str!("all-settings-example");
+        // enigo has no numpad or stop key there
+        #[cfg(target_os = "macos")]
+        let settings_str = &settings_str
+            .lines()
+            .filter(|line| {
+                !["W = N7", "W = ADD", "W = STOP_TRACK"]
+                    .iter()
+                    .any(|key| line.starts_with(key))
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
         let (_, errors) = jsm_parse(settings_str);
         dbg!(&errors);
         assert!(errors.is_empty());
     }
+
+    #[test]
+    fn unused_stick_setting() {
+        let check = |source| {
+            let (settings, _) = parse(source);
+            let (cmds, _) = jsm_parse(source);
+            let stick_settings: Vec<_> = cmds
+                .into_iter()
+                .filter_map(|(line, cmd)| match cmd {
+                    Cmd::Setting(Setting::Stick(s)) => Some((line, s)),
+                    _ => None,
+                })
+                .collect();
+            unused_stick_settings(&settings, &stick_settings)
+        };
+        assert_eq!(
+            check("LEFT_STICK_MODE = SCROLL_WHEEL\nRIGHT_STICK_MODE = FLICK\n\nSTICK_SENS = 2\nSCROLL_SENS = 3\nFLICK_TIME = 0.2\nMOUSE_RING_RADIUS = 10"),
+            [(4, "AIM"), (7, "MOUSE_RING or MOUSE_AREA")]
+        );
+        // The final mode is used, not the one at the time of the setting
+        assert_eq!(
+            check("STICK_SENS = 2\nRIGHT_STICK_MODE = SCROLL_WHEEL"),
+            [(1, "AIM")]
+        );
+        assert!(check("STICK_SENS = 2\nSTICK_DEADZONE_INNER = 0.1").is_empty());
+    }
+
+    #[test]
+    fn layer_gyro_setting() {
+        let (_, mut bindings) = parse("ZL,GYRO_SENS = 0.5\nZR,MIN_GYRO_SENS = 0.2");
+        let now = Instant::now();
+        assert_eq!(bindings.layer_gyro_settings().count(), 0);
+
+        bindings.key_down(JoyKey::ZL, now);
+        let _ = bindings.tick(now).count();
+        assert!(matches!(
+            bindings.layer_gyro_settings().collect::<Vec<_>>()[..],
+            [GyroSetting::Sensitivity(s, None)] if *s == 0.5
+        ));
+
+        // Both layers apply, the last one pressed on top
+        bindings.key_down(JoyKey::ZR, now);
+        let _ = bindings.tick(now).count();
+        assert!(matches!(
+            bindings.layer_gyro_settings().collect::<Vec<_>>()[..],
+            [GyroSetting::Sensitivity(s, None), GyroSetting::MinSens(m, None)]
+                if *s == 0.5 && *m == 0.2
+        ));
+
+        bindings.key_up(JoyKey::ZL, now);
+        bindings.key_up(JoyKey::ZR, now);
+        let _ = bindings.tick(now).count();
+        assert_eq!(bindings.layer_gyro_settings().count(), 0);
+    }
+
+    #[test]
+    fn connection_bindings() {
+        let (_, mut bindings) = parse("ON_CONNECT = ^GYRO_OFF\nON_DISCONNECT = a");
+        let now = Instant::now();
+        let mut tap = |key| {
+            bindings.key_down(key, now);
+            bindings.key_up(key, now);
+            bindings
+                .tick(now)
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tap(VirtualKey::Connect), ["Toggle gyro off"]);
+        assert_eq!(
+            tap(VirtualKey::Disconnect),
+            ["Press Unicode('a')", "Release Unicode('a')"]
+        );
+    }
+
+    #[test]
+    fn press_times() {
+        let (_, bindings) = parse("HOLD_PRESS_TIME = 150\nDBL_PRESS_WINDOW = 0.3s");
+        assert_eq!(bindings.hold_delay, Duration::from_millis(150));
+        assert_eq!(bindings.double_click_interval, Duration::from_millis(300));
+    }
+
+    #[test]
+    fn gyro_fusion() {
+        use crate::config::types::GyroFusion;
+
+        let (settings, _) = parse("");
+        assert_eq!(settings.gyro.fusion, GyroFusion::Simple);
+        let (settings, _) = parse("GYRO_FUSION = adaptive");
+        assert_eq!(settings.gyro.fusion, GyroFusion::Adaptive);
+    }
+
+    #[test]
+    fn profiles() {
+        let (settings, mut bindings) = parse(
+            "S = a\nHOME = SWITCH_PROFILE driving\n\
+             [driving]\nS = b\nGYRO_SENS = 2\nHOME = SWITCH_PROFILE default\n\
+             [menu]\nE = SWITCH_PROFILE nothing",
+        );
+        assert_eq!(settings.gyro.sens.x, 1.);
+        let names: Vec<_> = settings.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["driving", "menu"]);
+        let mut driving = settings.profiles[0].clone();
+        assert_eq!(driving.settings.gyro.sens.x, 2.);
+
+        let now = Instant::now();
+        let press = |bindings: &mut Buttons, key: JoyKey| {
+            bindings.key_down(key, now);
+            bindings.key_up(key, now);
+            bindings
+                .tick(now)
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            press(&mut bindings, JoyKey::S),
+            ["Press Unicode('a')", "Release Unicode('a')"]
+        );
+        assert_eq!(press(&mut bindings, JoyKey::Home), ["switch to profile 1"]);
+        assert_eq!(
+            press(&mut driving.buttons, JoyKey::S),
+            ["Press Unicode('b')", "Release Unicode('b')"]
+        );
+        assert_eq!(
+            press(&mut driving.buttons, JoyKey::Home),
+            ["switch to profile 0"]
+        );
+        // Nothing carries over from the other profiles
+        let mut menu = settings.profiles[1].clone();
+        assert_eq!(menu.settings.gyro.sens.x, 1.);
+        assert!(press(&mut menu.buttons, JoyKey::S).is_empty());
+        assert!(press(&mut menu.buttons, JoyKey::E).is_empty());
+    }
+
+    #[test]
+    fn gyro_default() {
+        let (settings, _) = parse("");
+        assert!(settings.gyro.default_enabled);
+        let (settings, _) = parse("GYRO_DEFAULT = OFF\nR = GYRO_ON");
+        assert!(!settings.gyro.default_enabled);
+    }
+
+    #[test]
+    fn touchpad() {
+        let (settings, mut bindings) =
+            parse("TOUCHPAD_MODE = MOUSE\nTOUCH = a\nTOUCHPAD_CLICK = LMOUSE");
+        assert_eq!(settings.touchpad_mode, TouchpadMode::Mouse);
+        let now = Instant::now();
+        bindings.key_down(VirtualKey::Touch, now);
+        bindings.key_down(VirtualKey::TouchpadClick, now);
+        assert_eq!(
+            bindings
+                .tick(now)
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>(),
+            ["Press Unicode('a')", "Press Left"]
+        );
+    }
+
+    #[test]
+    fn deadzone_percentage() {
+        let (settings, _) = parse("STICK_DEADZONE_INNER = 15%\nSTICK_DEADZONE_OUTER = 10%");
+        assert!((settings.stick.deadzone - 0.15).abs() < 1e-9);
+        assert!((settings.stick.fullzone - 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn deadzone_out_of_range() {
+        let (settings, _) = parse("STICK_DEADZONE_INNER = -1\nSTICK_DEADZONE_OUTER = -1");
+        assert_eq!(settings.stick.deadzone, 0.);
+        assert_eq!(settings.stick.fullzone, 1.);
+    }
+
+    #[test]
+    fn deadzone_past_fullzone() {
+        let (settings, _) = parse("STICK_DEADZONE_INNER = 15\nSTICK_DEADZONE_OUTER = -1");
+        assert_eq!(settings.stick.deadzone, 0.15);
+        assert_eq!(settings.stick.fullzone, 0.9);
+
+        let (settings, _) =
+            parse("STICK_DEADZONE_INNER = 0.2\nRIGHT_STICK_DEADZONE_OUTER = 0.85\n[other]\nS = a");
+        assert_eq!(settings.stick.deadzone, 0.15);
+        assert_eq!(settings.stick.right_fullzone, None);
+
+        let (settings, _) = parse("[other]\nSTICK_DEADZONE_INNER = 0.95");
+        assert_eq!(settings.profiles[0].settings.stick.deadzone, 0.15);
+    }
+
+    #[test]
+    fn gyro_output_rate_bounds() {
+        let (settings, _) = parse("GYRO_OUTPUT_RATE = 144");
+        assert_eq!(settings.gyro.output_rate, 144.);
+        for rate in ["-1", "1e-300"] {
+            let (settings, _) = parse(&format!("GYRO_OUTPUT_RATE = {}", rate));
+            assert_eq!(settings.gyro.output_rate, 0.);
+        }
+    }
+
+    #[test]
+    fn non_positive_sens() {
+        let (settings, mut bindings) = parse(
+            "GYRO_SENS = -3 2\nSTICK_SENS = 0\nSTICK_SENS_SLOW = -10\nMIN_GYRO_SENS = 0 -1\n\
+             ZL,GYRO_SENS = -0.5",
+        );
+        // Replaced by the defaults
+        assert_eq!(settings.gyro.sens, cgmath::vec2(1., 2.));
+        assert_eq!(settings.gyro.slow_sens, cgmath::vec2(0., 0.));
+        assert_eq!(settings.stick.aim.sens_dps, 360.);
+        assert_eq!(settings.stick.aim.slow_dps, None);
+
+        let now = Instant::now();
+        bindings.key_down(JoyKey::ZL, now);
+        let _ = bindings.tick(now).count();
+        assert!(matches!(
+            bindings.layer_gyro_settings().collect::<Vec<_>>()[..],
+            [GyroSetting::Sensitivity(s, None)] if *s == 1.
+        ));
+    }
+
+    #[test]
+    fn stick_response_curve() {
+        use crate::config::types::StickResponseCurve;
+
+        let curve = |source| parse(source).0.stick.aim.response_curve;
+        assert_eq!(curve(""), StickResponseCurve::Linear);
+        assert_eq!(curve("STICK_POWER = 2"), StickResponseCurve::Power(2.));
+        assert_eq!(
+            curve("STICK_RESPONSE_CURVE = power 1.5"),
+            StickResponseCurve::Power(1.5)
+        );
+        assert_eq!(
+            curve("STICK_RESPONSE_CURVE = SCURVE 0.3"),
+            StickResponseCurve::SCurve { knee: 0.3 }
+        );
+        // A knee at the edge would divide by zero
+        assert_eq!(
+            curve("STICK_RESPONSE_CURVE = SCURVE 1"),
+            StickResponseCurve::SCurve { knee: 0.5 }
+        );
+        assert_eq!(
+            curve("STICK_POWER = 3\nSTICK_RESPONSE_CURVE = LINEAR"),
+            StickResponseCurve::Linear
+        );
+    }
+
+    #[test]
+    fn per_side_fullzone() {
+        let (settings, _) = parse(
+            "STICK_DEADZONE_OUTER = 0.1\nLEFT_STICK_DEADZONE_OUTER = 20%\nSTICK_DEADZONE_OUTER = 0.05",
+        );
+        assert!((settings.stick.fullzone(StickSide::Left) - 0.8).abs() < 1e-9);
+        assert!((settings.stick.fullzone(StickSide::Right) - 0.95).abs() < 1e-9);
+        // The motion stick input is already rescaled by its own deadzones
+        assert!((settings.stick.fullzone(StickSide::Motion) - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn gyro_cm360() {
+        let (settings, _) = parse("GYRO_CM360 = 45.72 800");
+        // 18 inches at 800 DPI is 14400 counts, 40 counts per degree
+        assert!((settings.gyro.natural_sens(&settings.mouse) - 40.).abs() < 1e-9);
+
+        let (settings, _) =
+            parse("REAL_WORLD_CALIBRATION = 10\nIN_GAME_SENS = 2\nGYRO_CM360 = 45.72 800");
+        assert!((settings.gyro.natural_sens(&settings.mouse) - 2.).abs() < 1e-9);
+
+        let (settings, _) = parse("IN_GAME_SENS = 2");
+        assert_eq!(settings.gyro.natural_sens(&settings.mouse), 1.);
+
+        for source in [
+            "IN_GAME_SENS = 0",
+            "REAL_WORLD_CALIBRATION = -1",
+            "GYRO_CM360 = 0 800",
+            "GYRO_CM360 = 45.72 inf",
+        ] {
+            let errors = parse_file(source, &mut Settings::default(), &mut Buttons::new());
+            assert_eq!(errors.len(), 1, "{}", source);
+        }
+    }
+
+    #[test]
+    fn none_blocks_lower_layer() {
+        let (_, mut bindings) = parse("S = a\nZL,S = NONE");
+        let now = Instant::now();
+
+        bindings.key_down(JoyKey::ZL, now);
+        bindings.key_down(JoyKey::S, now);
+        bindings.key_up(JoyKey::S, now);
+        assert!(bindings.tick(now).all(|a| matches!(a, ExtAction::None)));
+
+        bindings.key_up(JoyKey::ZL, now);
+        bindings.key_down(JoyKey::S, now);
+        assert!(matches!(
+            bindings.tick(now).collect::<Vec<_>>()[..],
+            [ExtAction::KeyPress(Key::Unicode('a'), ClickType::Press)]
+        ));
+    }
+
+    #[test]
+    fn per_key_hold_delay() {
+        let (_, mut bindings) = parse("S = a b_300\nE = a b");
+        let t0 = Instant::now();
+        let hold = |bindings: &mut Buttons, ms| {
+            let actions: Vec<_> = bindings.tick(t0 + Duration::from_millis(ms)).collect();
+            matches!(
+                actions[..],
+                [ExtAction::KeyPress(Key::Unicode('b'), ClickType::Press)]
+            )
+        };
+
+        bindings.key_down(JoyKey::S, t0);
+        bindings.key_down(JoyKey::E, t0);
+        assert!(hold(&mut bindings, 150));
+        assert!(!hold(&mut bindings, 299));
+        assert!(hold(&mut bindings, 300));
+    }
+
+    #[test]
+    fn simultaneous_keys() {
+        let (_, bindings) = parse("S+E = c\nS = a\nE = b");
+        let t0 = Instant::now() + Duration::from_secs(1);
+        let ms = |ms| t0 + Duration::from_millis(ms);
+        let keys = |bindings: &mut Buttons, ms| {
+            bindings
+                .tick(ms)
+                .map(|a| match a {
+                    ExtAction::KeyPress(Key::Unicode(c), ClickType::Press) => {
+                        c.to_ascii_uppercase()
+                    }
+                    ExtAction::KeyPress(Key::Unicode(c), ClickType::Release) => c,
+                    _ => panic!("unexpected action {:?}", a),
+                })
+                .collect::<String>()
+        };
+
+        // Both within the window, released when the first key goes up
+        let mut b = bindings.clone();
+        b.key_down(JoyKey::S, t0);
+        assert_eq!(keys(&mut b, ms(10)), "");
+        b.key_down(JoyKey::E, ms(30));
+        assert_eq!(keys(&mut b, ms(30)), "C");
+        b.key_up(JoyKey::E, ms(100));
+        assert_eq!(keys(&mut b, ms(100)), "c");
+        b.key_up(JoyKey::S, ms(150));
+        assert_eq!(keys(&mut b, ms(150)), "");
+
+        // Too late, both keys act on their own
+        let mut b = bindings.clone();
+        b.key_down(JoyKey::S, t0);
+        assert_eq!(keys(&mut b, ms(60)), "A");
+        b.key_down(JoyKey::E, ms(70));
+        b.key_up(JoyKey::S, ms(80));
+        b.key_up(JoyKey::E, ms(200));
+        assert_eq!(keys(&mut b, ms(200)), "aBb");
+
+        // Tap shorter than the window
+        let mut b = bindings;
+        b.key_down(JoyKey::E, t0);
+        b.key_up(JoyKey::E, ms(20));
+        assert_eq!(keys(&mut b, ms(20)), "Bb");
+    }
+
+    #[test]
+    fn tap_or_turbo() {
+        let (_, mut bindings) = parse("S = a'+");
+        let t0 = Instant::now();
+        let ms = |ms| t0 + Duration::from_millis(ms);
+        let clicks = |actions: Vec<ExtAction>| {
+            actions
+                .iter()
+                .filter(|a| matches!(a, ExtAction::KeyPress(Key::Unicode('a'), ClickType::Click)))
+                .count()
+        };
+        let presses = |actions: Vec<ExtAction>| {
+            actions
+                .iter()
+                .filter(|a| matches!(a, ExtAction::KeyPress(Key::Unicode('a'), ClickType::Press)))
+                .count()
+        };
+
+        // Tap
+        bindings.key_down(JoyKey::S, t0);
+        assert_eq!(clicks(bindings.tick(ms(50)).collect()), 0);
+        bindings.key_up(JoyKey::S, ms(50));
+        assert_eq!(clicks(bindings.tick(ms(50)).collect()), 1);
+
+        // Hold, pressing at the hold delay then every turbo period
+        bindings.key_down(JoyKey::S, ms(1000));
+        let mut fired = 0;
+        for t in (1000..=1300).step_by(10) {
+            fired += presses(bindings.tick(ms(t)).collect());
+        }
+        assert_eq!(fired, 3);
+        bindings.key_up(JoyKey::S, ms(1300));
+        assert_eq!(clicks(bindings.tick(ms(1300)).collect()), 0);
+    }
+
+    #[test]
+    fn turbo() {
+        let (_, mut bindings) = parse("S = a+\nTURBO_PERIOD = 100ms");
+        let t0 = Instant::now();
+        let actions = |bindings: &mut Buttons, ms| {
+            bindings
+                .tick(t0 + Duration::from_millis(ms))
+                .map(|a| match a {
+                    ExtAction::KeyPress(Key::Unicode('a'), ClickType::Press) => 'P',
+                    ExtAction::KeyPress(Key::Unicode('a'), ClickType::Release) => 'R',
+                    _ => panic!("unexpected action {:?}", a),
+                })
+                .collect::<String>()
+        };
+
+        bindings.key_down(JoyKey::S, t0);
+        let mut fired = String::new();
+        for t in (0..=120).step_by(10) {
+            fired += &actions(&mut bindings, t);
+        }
+        assert_eq!(fired, "PRP");
+
+        // Releasing in the pressed half releases the key
+        bindings.key_up(JoyKey::S, t0 + Duration::from_millis(125));
+        assert_eq!(actions(&mut bindings, 125), "R");
+    }
+
+    #[test]
+    fn turbo_keeps_hold_delay() {
+        let t0 = Instant::now();
+        let keys = |bindings: &mut Buttons, ms| {
+            bindings
+                .tick(t0 + Duration::from_millis(ms))
+                .filter_map(|a| match a {
+                    ExtAction::KeyPress(Key::Unicode(c), ClickType::Press | ClickType::Click) => {
+                        Some(c)
+                    }
+                    _ => None,
+                })
+                .collect::<String>()
+        };
+
+        // The tap still fires while the turbo runs
+        let (_, mut bindings) = parse("S = a b+\nTURBO_PERIOD = 100ms");
+        bindings.key_down(JoyKey::S, t0);
+        assert_eq!(keys(&mut bindings, 0), "b");
+        bindings.key_up(JoyKey::S, t0 + Duration::from_millis(50));
+        assert_eq!(keys(&mut bindings, 50), "a");
+
+        // The hold waits for the hold delay
+        let (_, mut bindings) = parse("S = a+ b\nHOLD_PRESS_TIME = 150ms\nTURBO_PERIOD = 1s");
+        bindings.key_down(JoyKey::S, t0);
+        assert_eq!(keys(&mut bindings, 0), "a");
+        assert_eq!(keys(&mut bindings, 100), "");
+        assert_eq!(keys(&mut bindings, 150), "b");
+    }
+
+    #[test]
+    fn start_and_release_on_same_line() {
+        let (_, mut bindings) = parse("S = a\\ b/");
+        let now = Instant::now();
+
+        bindings.key_down(JoyKey::S, now);
+        let actions: Vec<_> = bindings.tick(now).collect();
+        assert!(matches!(
+            actions[..],
+            [ExtAction::KeyPress(Key::Unicode('a'), ClickType::Press)]
+        ));
+
+        bindings.key_up(JoyKey::S, now);
+        let actions: Vec<_> = bindings.tick(now).collect();
+        assert!(matches!(
+            actions[..],
+            [
+                ExtAction::KeyPress(Key::Unicode('a'), ClickType::Release),
+                ExtAction::KeyPress(Key::Unicode('b'), ClickType::Click),
+            ]
+        ));
+    }
 }
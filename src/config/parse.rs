@@ -5,11 +5,9 @@ use cgmath::Deg;
 use hid_gamepad_types::JoyKey;
 use nom::{
     branch::alt,
-    character::{
-        complete::{line_ending, not_line_ending, satisfy, space0, space1},
-        is_alphanumeric,
-    },
-    combinator::{eof, map, opt, peek, value},
+    bytes::complete::{take_till1, take_while1},
+    character::complete::{char, line_ending, not_line_ending, satisfy, space0, space1},
+    combinator::{eof, map, map_opt, opt, peek, value},
     multi::separated_list1,
     number::complete::double,
     IResult, Parser,
@@ -30,12 +28,15 @@ pub type Input<'a> = &'a str;
 pub type Error<'a> = ErrorTree<Input<'a>>;
 pub type IRes<'a, O> = IResult<Input<'a>, O, Error<'a>>;
 
-pub fn jsm_parse(input: Input) -> (Vec<Cmd>, Vec<nom::Err<Error>>) {
+/// Parse a configuration file, returning the commands with their line number.
+pub fn jsm_parse(input: Input) -> (Vec<(usize, Cmd)>, Vec<nom::Err<Error>>) {
     let mut errors = Vec::new();
+    let mut line_number = 0;
 
-    let line = |input| -> IRes<'_, Option<Cmd>> {
+    let line = |input| -> IRes<'_, Option<(usize, Cmd)>> {
+        line_number += 1;
         match alt((empty_line, line))(input) {
-            Ok(ok) => Ok(ok),
+            Ok((rest, cmd)) => Ok((rest, cmd.map(|cmd| (line_number, cmd)))),
             Err(e) => {
                 errors.push(e);
                 let res: IRes<'_, &str> = not_line_ending(input);
@@ -52,6 +53,22 @@ pub fn jsm_parse(input: Input) -> (Vec<Cmd>, Vec<nom::Err<Error>>) {
     (cmds, errors)
 }
 
+/// Path of an `INCLUDE "path"` line, the quotes being optional without
+/// spaces in the path.
+pub fn include(input: &str) -> Option<&str> {
+    let (input, _) = space0::<_, Error>(input).ok()?;
+    let (input, _) = tag_no_case::<_, _, Error>("INCLUDE")(input).ok()?;
+    let (_, path) = alt((
+        take_till1(|c| c == '"').delimited_by(tag("\"")),
+        take_till1(|c: char| c.is_whitespace() || c == '#'),
+    ))
+    .preceded_by(space1)
+    .terminated(empty_line)
+    .parse(input)
+    .ok()?;
+    Some(path)
+}
+
 fn empty_line(input: Input) -> IRes<'_, Option<Cmd>> {
     let (input, _) = space0(input)?;
     let (input, _) = opt(comment)(input)?;
@@ -89,11 +106,17 @@ fn keys(input: Input) -> IRes<'_, Key> {
     alt((simul, chorded, simple))(input)
 }
 
+/// A key with its modifiers.
+///
+/// The modifier characters are also keys: `^` and `!` are modifiers only when
+/// directly followed by the key, and the key is always the first character
+/// after them, so `+` is the plus key, `++` its turbo and `^^` its toggle.
 fn action(input: Input) -> IRes<'_, JSMAction> {
     let (input, action_mod) = opt(alt((
         value(ActionModifier::Toggle, tag("^")),
         value(ActionModifier::Instant, tag("!")),
-    )))
+    ))
+    .terminated(peek(satisfy(|c| !c.is_whitespace()))))
     .context("modifier")
     .parse(input)?;
     let (input, action) = alt((
@@ -106,17 +129,25 @@ fn action(input: Input) -> IRes<'_, JSMAction> {
     .context("action")
     .parse(input)?;
     let (input, event_mod) = opt(alt((
+        // Before the tap modifier, which is a prefix
+        value(EventModifier::TapOrTurbo, tag("'+")),
         value(EventModifier::Tap, tag("'")),
         value(EventModifier::Hold, tag("_")),
         value(EventModifier::Start, tag("\\")),
         value(EventModifier::Release, tag("/")),
         value(EventModifier::Turbo, tag("+")),
     )))(input)?;
+    let (input, hold_delay) = if event_mod == Some(EventModifier::Hold) {
+        opt(map(nom::character::complete::u64, Duration::from_millis))(input)?
+    } else {
+        (input, None)
+    };
     Ok((
         input,
         JSMAction {
             action_mod,
             event_mod,
+            hold_delay,
             action,
         },
     ))
@@ -136,7 +167,7 @@ fn setting(input: Input) -> IRes<'_, Setting> {
     alt((
         f64_setting("TRIGGER_THRESHOLD", Setting::TriggerThreshold),
         trigger_mode,
-        gyro_setting,
+        map(gyro_setting, Setting::Gyro),
         stick_mode_setting("LEFT_STICK_MODE", Setting::LeftStickMode),
         stick_mode_setting("RIGHT_STICK_MODE", Setting::RightStickMode),
         stick_mode_setting("MOTION_STICK_MODE", |v| {
@@ -149,9 +180,36 @@ fn setting(input: Input) -> IRes<'_, Setting> {
         }),
         map(stick_setting, Setting::Stick),
         map(mouse_setting, Setting::Mouse),
+        map(buttons_setting, Setting::Buttons),
+        dpad_mode,
+        touchpad_mode,
     ))(input)
 }
 
+fn dpad_mode(input: Input) -> IRes<Setting> {
+    let (input, _) = tag_no_case("DPAD_MODE")(input)?;
+    let (input, mode) = alt((
+        value(DpadMode::Buttons, tag_no_case("BUTTONS")),
+        value(DpadMode::Scroll, tag_no_case("SCROLL")),
+    ))
+    .preceded_by(equal_with_space)
+    .cut()
+    .parse(input)?;
+    Ok((input, Setting::DpadMode(mode)))
+}
+
+fn touchpad_mode(input: Input) -> IRes<Setting> {
+    let (input, _) = tag_no_case("TOUCHPAD_MODE")(input)?;
+    let (input, mode) = alt((
+        value(TouchpadMode::None, tag_no_case("NONE")),
+        value(TouchpadMode::Mouse, tag_no_case("MOUSE")),
+    ))
+    .preceded_by(equal_with_space)
+    .cut()
+    .parse(input)?;
+    Ok((input, Setting::TouchpadMode(mode)))
+}
+
 fn u32_setting<Output>(
     tag: &'static str,
     value_map: impl Fn(u32) -> Output,
@@ -177,6 +235,101 @@ fn f64_setting<Output>(
     }
 }
 
+/// Like `f64_setting`, but rejects zero, negative and infinite values.
+fn positive_f64_setting<Output>(
+    tag: &'static str,
+    value_map: impl Fn(f64) -> Output,
+) -> impl FnMut(Input) -> IRes<'_, Output> {
+    move |input| {
+        let (input, _) = tag_no_case(tag)(input)?;
+        let (input, val) = positive_double
+            .preceded_by(equal_with_space)
+            .cut()
+            .parse(input)?;
+        Ok((input, value_map(val)))
+    }
+}
+
+fn positive_double(input: Input) -> IRes<f64> {
+    map_opt(double, |val| (val > 0. && val.is_finite()).then_some(val))(input)
+}
+
+/// A value between 0 and 1, or a percentage with a `%` suffix.
+fn ratio_setting<Output>(
+    tag: &'static str,
+    value_map: impl Fn(f64) -> Output,
+) -> impl FnMut(Input) -> IRes<'_, Output> {
+    move |input| {
+        let (input, _) = tag_no_case(tag)(input)?;
+        let (input, val) = equal_with_space.precedes(double).cut().parse(input)?;
+        let (input, percent) = opt(char('%'))(input)?;
+        let val = if percent.is_some() { val / 100. } else { val };
+        Ok((input, value_map(val)))
+    }
+}
+
+fn bool_setting<Output>(
+    tag: &'static str,
+    value_map: impl Fn(bool) -> Output,
+) -> impl FnMut(Input) -> IRes<'_, Output> {
+    move |input| {
+        let (input, _) = tag_no_case(tag)(input)?;
+        let (input, val) = alt((
+            value(true, tag_no_case("ON")),
+            value(false, tag_no_case("OFF")),
+        ))
+        .preceded_by(equal_with_space)
+        .cut()
+        .parse(input)?;
+        Ok((input, value_map(val)))
+    }
+}
+
+fn duration_setting<Output>(
+    tag: &'static str,
+    value_map: impl Fn(Duration) -> Output,
+) -> impl FnMut(Input) -> IRes<'_, Output> {
+    move |input| {
+        let (input, _) = tag_no_case(tag)(input)?;
+        let (input, val) = duration.preceded_by(equal_with_space).cut().parse(input)?;
+        Ok((input, value_map(val)))
+    }
+}
+
+/// Like `duration_setting`, but in milliseconds without suffix, as JSM does
+/// for these settings.
+fn ms_duration_setting<Output>(
+    tag: &'static str,
+    value_map: impl Fn(Duration) -> Output,
+) -> impl FnMut(Input) -> IRes<'_, Output> {
+    move |input| {
+        let (input, _) = tag_no_case(tag)(input)?;
+        let (input, val) = (|i| scaled_duration(i, 1e-3))
+            .preceded_by(equal_with_space)
+            .cut()
+            .parse(input)?;
+        Ok((input, value_map(val)))
+    }
+}
+
+/// A positive duration, in seconds unless suffixed by `ms` or `s`.
+fn duration(input: Input) -> IRes<Duration> {
+    scaled_duration(input, 1.)
+}
+
+/// A positive duration, in `default_scale` seconds without suffix.
+///
+/// Negative, infinite or too large values are rejected.
+fn scaled_duration(input: Input, default_scale: f64) -> IRes<Duration> {
+    map_opt(
+        double.and(opt(alt((
+            value(1e-3, tag_no_case("ms")),
+            value(1., tag_no_case("s")),
+        )))),
+        |(val, scale)| Duration::try_from_secs_f64(val * scale.unwrap_or(default_scale)).ok(),
+    )(input)
+}
+
 fn double_f64_setting<Output>(
     tag: &'static str,
     value_map: impl Fn(f64, Option<f64>) -> Output,
@@ -191,40 +344,74 @@ fn double_f64_setting<Output>(
 
 fn stick_setting(input: Input) -> IRes<'_, StickSetting> {
     alt((
-        f64_setting("STICK_DEADZONE_INNER", StickSetting::Deadzone),
-        f64_setting("STICK_DEADZONE_OUTER", |v| StickSetting::FullZone(1. - v)),
-        f64_setting("MOTION_DEADZONE_INNER", |v| {
-            StickSetting::Motion(MotionStickSetting::Deadzone(Deg(v)))
-        }),
-        f64_setting("MOTION_DEADZONE_OUTER", |v| {
-            StickSetting::Motion(MotionStickSetting::Fullzone(Deg(1. - v)))
-        }),
-        f64_setting("STICK_SENS", |v| {
-            StickSetting::Aim(AimStickSetting::Sens(v))
-        }),
-        f64_setting("STICK_POWER", |v| {
-            StickSetting::Aim(AimStickSetting::Power(v))
-        }),
+        alt((
+            ratio_setting("STICK_DEADZONE_INNER", StickSetting::Deadzone),
+            stick_deadzone_curve,
+            ratio_setting("STICK_DEADZONE_OUTER", |v| StickSetting::FullZone(1. - v)),
+            ratio_setting("LEFT_STICK_DEADZONE_OUTER", |v| {
+                StickSetting::LeftFullZone(1. - v)
+            }),
+            ratio_setting("RIGHT_STICK_DEADZONE_OUTER", |v| {
+                StickSetting::RightFullZone(1. - v)
+            }),
+            duration_setting("STICK_MAX_FRAME_TIME", StickSetting::MaxFrameTime),
+        )),
+        alt((
+            f64_setting("MOTION_DEADZONE_INNER", |v| {
+                StickSetting::Motion(MotionStickSetting::Deadzone(Deg(v)))
+            }),
+            f64_setting("MOTION_DEADZONE_OUTER", |v| {
+                StickSetting::Motion(MotionStickSetting::Fullzone(Deg(1. - v)))
+            }),
+            f64_setting("LEAN_THRESHOLD", |v| {
+                StickSetting::Motion(MotionStickSetting::LeanThreshold(Deg(v)))
+            }),
+            f64_setting("FACE_THRESHOLD", |v| {
+                StickSetting::Motion(MotionStickSetting::FaceThreshold(Deg(v)))
+            }),
+        )),
+        alt((
+            f64_setting("STICK_SENS_SLOW", |v| {
+                StickSetting::Aim(AimStickSetting::SensSlow(v))
+            }),
+            f64_setting("STICK_SENS_FAST", |v| {
+                StickSetting::Aim(AimStickSetting::SensFast(v))
+            }),
+            f64_setting("STICK_SENS", |v| {
+                StickSetting::Aim(AimStickSetting::Sens(v))
+            }),
+            f64_setting("STICK_POWER", |v| {
+                StickSetting::Aim(AimStickSetting::Power(v))
+            }),
+            stick_response_curve,
+        )),
         setting_invert("LEFT_STICK_AXIS", |x, y| {
             StickSetting::Aim(AimStickSetting::LeftAxis(x, y))
         }),
         setting_invert("RIGHT_STICK_AXIS", |x, y| {
             StickSetting::Aim(AimStickSetting::RightAxis(x, y))
         }),
+        // Before MOTION_STICK_AXIS, which is a prefix
+        motion_stick_axis_map,
         setting_invert("MOTION_STICK_AXIS", |x, y| {
             StickSetting::Motion(MotionStickSetting::Axis(x, y))
         }),
-        f64_setting("STICK_ACCELERATION_RATE", |v| {
-            StickSetting::Aim(AimStickSetting::AccelerationRate(v))
-        }),
-        f64_setting("STICK_ACCELERATION_CAP", |v| {
-            StickSetting::Aim(AimStickSetting::AccelerationCap(v))
-        }),
+        alt((
+            f64_setting("STICK_ACCELERATION_RATE", |v| {
+                StickSetting::Aim(AimStickSetting::AccelerationRate(v))
+            }),
+            f64_setting("STICK_ACCELERATION_CAP", |v| {
+                StickSetting::Aim(AimStickSetting::AccelerationCap(v))
+            }),
+            bool_setting("STICK_ACCELERATION", |v| {
+                StickSetting::Aim(AimStickSetting::Acceleration(v))
+            }),
+        )),
         f64_setting("FLICK_TIME_EXPONENT", |v| {
             StickSetting::Flick(FlickStickSetting::Exponent(v))
         }),
-        f64_setting("FLICK_TIME", |v| {
-            StickSetting::Flick(FlickStickSetting::FlickTime(Duration::from_secs_f64(v)))
+        duration_setting("FLICK_TIME", |v| {
+            StickSetting::Flick(FlickStickSetting::FlickTime(v))
         }),
         f64_setting("FLICK_DEADZONE_ANGLE", |v| {
             StickSetting::Flick(FlickStickSetting::ForwardDeadzoneArc(Deg(v * 2.)))
@@ -232,6 +419,12 @@ fn stick_setting(input: Input) -> IRes<'_, StickSetting> {
         f64_setting("SCROLL_SENS", |v| {
             StickSetting::Scroll(ScrollStickSetting::Sens(Deg(v)))
         }),
+        f64_setting("WASD_DIAGONAL_TOLERANCE", |v| {
+            StickSetting::Button(ButtonStickSetting::DiagonalTolerance(Deg(v)))
+        }),
+        ratio_setting("STICK_BUTTON_THRESHOLD", |v| {
+            StickSetting::Button(ButtonStickSetting::Threshold(v))
+        }),
         u32_setting("SCREEN_RESOLUTION_X", |v| {
             StickSetting::Area(AreaStickSetting::ScreenResolutionX(v))
         }),
@@ -241,6 +434,23 @@ fn stick_setting(input: Input) -> IRes<'_, StickSetting> {
         u32_setting("MOUSE_RING_RADIUS", |v| {
             StickSetting::Area(AreaStickSetting::Radius(v))
         }),
+        alt((
+            duration_setting("AIM_STICK_SMOOTH_TIME", |v| {
+                StickSetting::Aim(AimStickSetting::SmoothTime(v))
+            }),
+            duration_setting("AREA_STICK_SMOOTH_TIME", |v| {
+                StickSetting::Area(AreaStickSetting::SmoothTime(v))
+            }),
+            duration_setting("SCROLL_STICK_SMOOTH_TIME", |v| {
+                StickSetting::Scroll(ScrollStickSetting::SmoothTime(v))
+            }),
+            duration_setting("AREA_REL_SMOOTH", |v| {
+                StickSetting::Area(AreaStickSetting::RelSmoothTime(v))
+            }),
+            duration_setting("MOUSE_RING_SMOOTH", |v| {
+                StickSetting::Area(AreaStickSetting::RingSmoothTime(v))
+            }),
+        )),
     ))(input)
 }
 
@@ -267,6 +477,35 @@ fn setting_invert<O>(
     }
 }
 
+fn motion_stick_axis_map(input: Input) -> IRes<StickSetting> {
+    let (input, _) = tag_no_case("MOTION_STICK_AXIS_MAP")(input)?;
+    let (input, x) = tilt_source
+        .preceded_by(equal_with_space)
+        .cut()
+        .parse(input)?;
+    let (input, y) = tilt_source.preceded_by(space1).cut().parse(input)?;
+    Ok((
+        input,
+        StickSetting::Motion(MotionStickSetting::AxisMap(x, y)),
+    ))
+}
+
+/// `ROLL` or `PITCH`, inverted if prefixed by `-`.
+fn tilt_source(input: Input) -> IRes<TiltSource> {
+    let (input, inverted) = opt(char('-'))(input)?;
+    let (input, axis) = alt((
+        value(TiltAxis::Roll, tag_no_case("ROLL")),
+        value(TiltAxis::Pitch, tag_no_case("PITCH")),
+    ))(input)?;
+    Ok((
+        input,
+        TiltSource {
+            axis,
+            inverted: inverted.is_some(),
+        },
+    ))
+}
+
 fn ring_mode_setting<O>(
     tag: &'static str,
     value_map: impl Fn(RingMode) -> O,
@@ -284,26 +523,39 @@ fn ring_mode_setting<O>(
     }
 }
 
-fn gyro_setting(input: Input) -> IRes<'_, Setting> {
-    map(
+fn gyro_setting(input: Input) -> IRes<GyroSetting> {
+    alt((
+        double_f64_setting("GYRO_SENS", GyroSetting::Sensitivity),
+        double_f64_setting("MIN_GYRO_SENS", GyroSetting::MinSens),
+        f64_setting("MIN_GYRO_THRESHOLD", GyroSetting::MinThreshold),
+        double_f64_setting("MAX_GYRO_SENS", GyroSetting::MaxSens),
+        f64_setting("MAX_GYRO_THRESHOLD", GyroSetting::MaxThreshold),
+        alt((gyro_space, gyro_fusion)),
+        f64_setting("GYRO_CUTOFF_SPEED", GyroSetting::CutoffSpeed),
+        f64_setting("GYRO_CUTOFF_RECOVERY", GyroSetting::CutoffRecovery),
         alt((
-            double_f64_setting("GYRO_SENS", GyroSetting::Sensitivity),
-            double_f64_setting("MIN_GYRO_SENS", GyroSetting::MinSens),
-            f64_setting("MIN_GYRO_THRESHOLD", GyroSetting::MinThreshold),
-            double_f64_setting("MAX_GYRO_SENS", GyroSetting::MaxSens),
-            f64_setting("MAX_GYRO_THRESHOLD", GyroSetting::MaxThreshold),
-            gyro_space,
-            f64_setting("GYRO_CUTOFF_SPEED", GyroSetting::CutoffSpeed),
-            f64_setting("GYRO_CUTOFF_RECOVERY", GyroSetting::CutoffRecovery),
             f64_setting("GYRO_SMOOTH_THRESHOLD", GyroSetting::SmoothThreshold),
-            f64_setting("GYRO_SMOOTH_TIME", |secs| {
-                GyroSetting::SmoothTime(Duration::from_secs_f64(secs))
-            }),
-            setting_invert("GYRO_AXIS_X", |v1, _v2| GyroSetting::InvertX(v1)),
-            setting_invert("GYRO_AXIS_Y", |v1, _v2| GyroSetting::InvertY(v1)),
+            duration_setting("GYRO_SMOOTH_TIME", GyroSetting::SmoothTime),
+            gyro_smooth_mode,
+            f64_setting("GYRO_SMOOTH_PREDICTION", GyroSetting::SmoothPrediction),
+            u32_setting("GYRO_SMOOTH_MAX_SAMPLES", GyroSetting::SmoothMaxSamples),
         )),
-        Setting::Gyro,
-    )(input)
+        f64_setting("GYRO_MOTION_THRESHOLD", GyroSetting::MotionThreshold),
+        // Before GYRO_OUTPUT, which is a prefix
+        f64_setting("GYRO_OUTPUT_RATE", GyroSetting::OutputRate),
+        gyro_output,
+        gyro_accel_curve,
+        f64_setting("GYRO_SCROLL_SENS", GyroSetting::ScrollSens),
+        f64_setting("GYRO_SCALE", GyroSetting::Scale),
+        gyro_cm360,
+        alt((
+            bool_setting("GYRO_TOGGLE_RUMBLE", GyroSetting::ToggleRumble),
+            bool_setting("GYRO_DEFAULT", GyroSetting::Default),
+        )),
+        f64_setting("GYRO_TRACKBALL_DECAY", GyroSetting::TrackBallDecay),
+        setting_invert("GYRO_AXIS_X", |v1, _v2| GyroSetting::InvertX(v1)),
+        setting_invert("GYRO_AXIS_Y", |v1, _v2| GyroSetting::InvertY(v1)),
+    ))(input)
 }
 
 fn gyro_space(input: Input) -> IRes<'_, GyroSetting> {
@@ -321,6 +573,97 @@ fn gyro_space(input: Input) -> IRes<'_, GyroSetting> {
     Ok((input, GyroSetting::Space(space)))
 }
 
+fn gyro_fusion(input: Input) -> IRes<GyroSetting> {
+    let (input, _) = tag_no_case("GYRO_FUSION")(input)?;
+    let (input, fusion) = alt((
+        value(GyroFusion::Simple, tag_no_case("SIMPLE")),
+        value(GyroFusion::Adaptive, tag_no_case("ADAPTIVE")),
+    ))
+    .preceded_by(equal_with_space)
+    .cut()
+    .parse(input)?;
+    Ok((input, GyroSetting::Fusion(fusion)))
+}
+
+fn gyro_cm360(input: Input) -> IRes<GyroSetting> {
+    let (input, _) = tag_no_case("GYRO_CM360")(input)?;
+    let (input, (cm, dpi)) = equal_with_space
+        .precedes(positive_double.terminated(space1).and(positive_double))
+        .cut()
+        .parse(input)?;
+    Ok((input, GyroSetting::Cm360(cm, dpi)))
+}
+
+fn gyro_smooth_mode(input: Input) -> IRes<GyroSetting> {
+    let (input, _) = tag_no_case("GYRO_SMOOTH_MODE")(input)?;
+    let (input, mode) = alt((
+        value(GyroSmoothMode::Average, tag_no_case("AVERAGE")),
+        value(GyroSmoothMode::Predictive, tag_no_case("PREDICTIVE")),
+    ))
+    .preceded_by(equal_with_space)
+    .cut()
+    .parse(input)?;
+    Ok((input, GyroSetting::SmoothMode(mode)))
+}
+
+fn gyro_output(input: Input) -> IRes<GyroSetting> {
+    let (input, _) = tag_no_case("GYRO_OUTPUT")(input)?;
+    let (input, output) = alt((
+        value(GyroOutput::Mouse, tag_no_case("MOUSE")),
+        value(GyroOutput::Scroll, tag_no_case("SCROLL")),
+    ))
+    .preceded_by(equal_with_space)
+    .cut()
+    .parse(input)?;
+    Ok((input, GyroSetting::Output(output)))
+}
+
+fn stick_response_curve(input: Input) -> IRes<StickSetting> {
+    let (input, _) = tag_no_case("STICK_RESPONSE_CURVE")(input)?;
+    let (input, curve) = alt((
+        value(StickResponseCurve::Linear, tag_no_case("LINEAR")),
+        double
+            .preceded_by(tag_no_case("POWER").terminated(space1))
+            .map(StickResponseCurve::Power),
+        double
+            .preceded_by(tag_no_case("SCURVE").terminated(space1))
+            .map(|knee| StickResponseCurve::SCurve { knee }),
+    ))
+    .preceded_by(equal_with_space)
+    .cut()
+    .parse(input)?;
+    Ok((
+        input,
+        StickSetting::Aim(AimStickSetting::ResponseCurve(curve)),
+    ))
+}
+
+fn stick_deadzone_curve(input: Input) -> IRes<StickSetting> {
+    let (input, _) = tag_no_case("STICK_DEADZONE_CURVE")(input)?;
+    let (input, curve) = alt((
+        value(DeadzoneCurve::Linear, tag_no_case("LINEAR")),
+        value(DeadzoneCurve::Smoothstep, tag_no_case("SMOOTHSTEP")),
+        value(DeadzoneCurve::Exponential, tag_no_case("EXPONENTIAL")),
+    ))
+    .preceded_by(equal_with_space)
+    .cut()
+    .parse(input)?;
+    Ok((input, StickSetting::DeadzoneCurve(curve)))
+}
+
+fn gyro_accel_curve(input: Input) -> IRes<GyroSetting> {
+    let (input, _) = tag_no_case("GYRO_ACCEL_CURVE")(input)?;
+    let (input, curve) = alt((
+        value(GyroAccelCurve::Linear, tag_no_case("LINEAR")),
+        value(GyroAccelCurve::Smoothstep, tag_no_case("SMOOTHSTEP")),
+        value(GyroAccelCurve::Exponential, tag_no_case("EXPONENTIAL")),
+    ))
+    .preceded_by(equal_with_space)
+    .cut()
+    .parse(input)?;
+    Ok((input, GyroSetting::AccelCurve(curve)))
+}
+
 fn stick_mode_setting<O>(
     tag: &'static str,
     value_map: impl Fn(StickMode) -> O,
@@ -361,7 +704,7 @@ fn trigger_mode(input: Input) -> IRes<'_, Setting> {
     .preceded_by(equal_with_space)
     .cut()
     .parse(input)?;
-    if key == "ZR_MODE" {
+    if key.eq_ignore_ascii_case("ZR_MODE") {
         Ok((input, Setting::ZRMode(mode)))
     } else {
         Ok((input, Setting::ZLMode(mode)))
@@ -369,8 +712,9 @@ fn trigger_mode(input: Input) -> IRes<'_, Setting> {
 }
 fn mouse_setting(input: Input) -> IRes<MouseSetting> {
     alt((
-        f64_setting("REAL_WORLD_CALIBRATION", MouseSetting::RealWorldCalibration),
-        f64_setting("IN_GAME_SENS", MouseSetting::InGameSens),
+        positive_f64_setting("REAL_WORLD_CALIBRATION", MouseSetting::RealWorldCalibration),
+        positive_f64_setting("IN_GAME_SENS", MouseSetting::InGameSens),
+        f64_setting("MOUSE_MIN_MOVE", MouseSetting::MinMove),
         value(
             MouseSetting::CounterOSSpeed(true),
             tag_no_case("COUNTER_OS_MOUSE_SPEED"),
@@ -382,6 +726,24 @@ fn mouse_setting(input: Input) -> IRes<MouseSetting> {
     ))(input)
 }
 
+fn buttons_setting(input: Input) -> IRes<ButtonsSetting> {
+    alt((
+        duration_setting("DEBOUNCE_TIME", ButtonsSetting::DebounceTime),
+        duration_setting("SIM_PRESS_WINDOW", ButtonsSetting::SimPressWindow),
+        duration_setting("TURBO_PERIOD", ButtonsSetting::TurboPeriod),
+        ms_duration_setting("HOLD_PRESS_TIME", ButtonsSetting::HoldPressTime),
+        ms_duration_setting("DBL_PRESS_WINDOW", ButtonsSetting::DblPressWindow),
+    ))(input)
+}
+
+/// Gyro setting only active while a key is held, like `ZL,GYRO_SENS = 0.5`.
+fn layer_setting(input: Input) -> IRes<Cmd> {
+    let (input, key) = mapkey(input)?;
+    let (input, _) = tag(",").delimited_by(space0).parse(input)?;
+    let (input, setting) = gyro_setting(input)?;
+    Ok((input, Cmd::LayerSetting(key, setting)))
+}
+
 fn equal_with_space(input: Input) -> IRes<'_, ()> {
     let (input, _) = space0(input)?;
     let (input, _) = tag("=").cut().parse(input)?;
@@ -391,15 +753,42 @@ fn equal_with_space(input: Input) -> IRes<'_, ()> {
 
 fn cmd(input: Input) -> IRes<'_, Cmd> {
     alt((
-        map(special, Cmd::Special),
+        // Before the special keys, GYRO_TRACKBALL being a prefix of
+        // GYRO_TRACKBALL_DECAY
         map(setting, Cmd::Setting),
+        map(special, Cmd::Special),
+        layer_setting,
         value(Cmd::Reset, tag_no_case("RESET_MAPPINGS")),
+        profile,
+        // Before the bindings, which cut after the `=`
+        switch_profile,
         binding.context("key binding"),
     ))
     .cut()
     .parse(input)
 }
 
+fn profile_name(input: Input) -> IRes<String> {
+    let (input, name) = take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-')
+        .context("profile name")
+        .parse(input)?;
+    Ok((input, name.to_owned()))
+}
+
+fn profile(input: Input) -> IRes<Cmd> {
+    let (input, _) = tag("[")(input)?;
+    let (input, name) = profile_name.terminated(tag("]")).cut().parse(input)?;
+    Ok((input, Cmd::Profile(name)))
+}
+
+fn switch_profile(input: Input) -> IRes<Cmd> {
+    let (input, key) = keys(input)?;
+    let (input, _) = equal_with_space(input)?;
+    let (input, _) = tag_no_case("SWITCH_PROFILE")(input)?;
+    let (input, name) = profile_name.preceded_by(space1).cut().parse(input)?;
+    Ok((input, Cmd::SwitchProfile(key, name)))
+}
+
 fn comment(input: Input) -> IRes<'_, ()> {
     let (input, _) = tag("#")(input)?;
     let (input, _) = not_line_ending(input)?;
@@ -457,28 +846,53 @@ fn virtkey(input: Input) -> IRes<'_, VirtualKey> {
         value(VirtualKey::MLeft, tag_no_case("MLeft")),
         value(VirtualKey::MRight, tag_no_case("MRight")),
         value(VirtualKey::MRing, tag_no_case("MRing")),
+        alt((
+            value(VirtualKey::GyroMove, tag_no_case("GYRO_MOVE")),
+            value(VirtualKey::LeanLeft, tag_no_case("LEAN_LEFT")),
+            value(VirtualKey::LeanRight, tag_no_case("LEAN_RIGHT")),
+            value(VirtualKey::FaceUp, tag_no_case("FACE_UP")),
+            value(VirtualKey::FaceDown, tag_no_case("FACE_DOWN")),
+            // Tried before the ZL and ZR joykeys, which are prefixes
+            value(VirtualKey::ZLFull, tag_no_case("ZLF")),
+            value(VirtualKey::ZRFull, tag_no_case("ZRF")),
+            value(VirtualKey::Connect, tag_no_case("ON_CONNECT")),
+            value(VirtualKey::Disconnect, tag_no_case("ON_DISCONNECT")),
+            // Tried before TOUCH, which is a prefix
+            value(VirtualKey::TouchpadClick, tag_no_case("TOUCHPAD_CLICK")),
+            value(VirtualKey::Touch, tag_no_case("TOUCH")),
+        )),
     ))(input)
 }
 
 fn keyboardkey(input: Input) -> IRes<'_, enigo::Key> {
     use enigo::Key::*;
     let char_parse = |input| {
-        satisfy(|c| is_alphanumeric(c as u8))(input)
-            .map(|(i, x)| (i, Unicode(x)))
-            .map_err(|_: nom::Err<ErrorTree<Input<'_>>>| {
-                nom::Err::Error(ErrorTree::from_tag(input, "a keyboard letter"))
-            })
+        // `#` starts a comment and `"` a JSM console command
+        satisfy(|c| c.is_ascii_alphanumeric() || (c.is_ascii_punctuation() && !"#\"".contains(c)))(
+            input,
+        )
+        .map(|(i, x)| (i, Unicode(x)))
+        .map_err(|_: nom::Err<ErrorTree<Input<'_>>>| {
+            nom::Err::Error(ErrorTree::from_tag(input, "a keyboard letter"))
+        })
     };
     let key_parse = |key, tag| value(key, tag_no_case(tag));
     alt((
+        // The generic modifiers are the left side ones
+        alt((
+            key_parse(LEFT_ALT, "alt"),
+            key_parse(LEFT_ALT, "lalt"),
+            key_parse(RIGHT_ALT, "ralt"),
+            key_parse(LControl, "Control"),
+            key_parse(LControl, "LControl"),
+            key_parse(RControl, "RControl"),
+            key_parse(LShift, "Shift"),
+            key_parse(LShift, "LShift"),
+            key_parse(RShift, "RShift"),
+        )),
         alt((
-            key_parse(Alt, "alt"),
-            //TODO: proper lalt and ralt
-            key_parse(Alt, "lalt"),
-            key_parse(Alt, "ralt"),
             key_parse(Backspace, "backspace"),
             key_parse(CapsLock, "capslock"),
-            key_parse(Control, "Control"),
             key_parse(Delete, "Delete"),
             key_parse(DownArrow, "down"),
             key_parse(End, "End"),
@@ -508,15 +922,149 @@ fn keyboardkey(input: Input) -> IRes<'_, enigo::Key> {
             key_parse(PageUp, "PageUp"),
             key_parse(Return, "Enter"),
             key_parse(RightArrow, "right"),
-            key_parse(Shift, "Shift"),
             key_parse(Space, "Space"),
             key_parse(Tab, "Tab"),
             key_parse(UpArrow, "up"),
-            char_parse,
         )),
+        alt((
+            key_parse(VolumeUp, "Volume_Up"),
+            key_parse(VolumeDown, "Volume_Down"),
+            key_parse(VolumeMute, "Mute"),
+            key_parse(MediaNextTrack, "Next_Track"),
+            key_parse(MediaPrevTrack, "Prev_Track"),
+            key_parse(MediaPlayPause, "Play_Pause"),
+        )),
+        system_key,
+        numpad_key,
+        char_parse,
+    ))(input)
+}
+
+#[cfg(target_os = "windows")]
+const LEFT_ALT: enigo::Key = enigo::Key::LMenu;
+// `LMenu` is the context menu key there
+#[cfg(all(unix, not(target_os = "macos")))]
+const LEFT_ALT: enigo::Key = enigo::Key::Alt;
+#[cfg(target_os = "macos")]
+const LEFT_ALT: enigo::Key = enigo::Key::Option;
+
+#[cfg(target_os = "windows")]
+const RIGHT_ALT: enigo::Key = enigo::Key::RMenu;
+// XK_Alt_R
+#[cfg(all(unix, not(target_os = "macos")))]
+const RIGHT_ALT: enigo::Key = enigo::Key::Other(0xffea);
+#[cfg(target_os = "macos")]
+const RIGHT_ALT: enigo::Key = enigo::Key::ROption;
+
+#[cfg(target_os = "windows")]
+const SCROLL_LOCK: enigo::Key = enigo::Key::Scroll;
+#[cfg(all(unix, not(target_os = "macos")))]
+const SCROLL_LOCK: enigo::Key = enigo::Key::ScrollLock;
+
+#[cfg(target_os = "windows")]
+const MENU: enigo::Key = enigo::Key::Apps;
+// XK_Menu
+#[cfg(all(unix, not(target_os = "macos")))]
+const MENU: enigo::Key = enigo::Key::Other(0xff67);
+
+/// Keys not available on every platform.
+#[cfg(not(target_os = "macos"))]
+fn system_key(input: Input) -> IRes<enigo::Key> {
+    use enigo::Key::*;
+    let key_parse = |key, tag| value(key, tag_no_case(tag));
+    alt((
+        key_parse(Insert, "Insert"),
+        key_parse(Numlock, "NumLock"),
+        key_parse(Numlock, "Num_Lock"),
+        key_parse(Pause, "Pause"),
+        key_parse(Pause, "Break"),
+        key_parse(Print, "PrintScreen"),
+        key_parse(Print, "Print_Screen"),
+        key_parse(SCROLL_LOCK, "ScrollLock"),
+        key_parse(SCROLL_LOCK, "Scroll_Lock"),
+        key_parse(MENU, "Menu"),
+        key_parse(MENU, "Context"),
+        key_parse(MediaStop, "Stop_Track"),
+    ))(input)
+}
+
+#[cfg(target_os = "macos")]
+fn system_key(input: Input) -> IRes<enigo::Key> {
+    Err(nom::Err::Error(ErrorTree::from_tag(input, "a system key")))
+}
+
+#[cfg(target_os = "windows")]
+mod numpad {
+    use enigo::Key::{self, *};
+
+    pub const DIGITS: [Key; 10] = [
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    ];
+    pub const ADD: Key = Add;
+    pub const SUBTRACT: Key = Subtract;
+    pub const MULTIPLY: Key = Multiply;
+    pub const DIVIDE: Key = Divide;
+    pub const DECIMAL: Key = Decimal;
+}
+
+// XK_KP_* keysyms
+#[cfg(all(unix, not(target_os = "macos")))]
+mod numpad {
+    use enigo::Key::{self, Other};
+
+    pub const DIGITS: [Key; 10] = [
+        Other(0xffb0),
+        Other(0xffb1),
+        Other(0xffb2),
+        Other(0xffb3),
+        Other(0xffb4),
+        Other(0xffb5),
+        Other(0xffb6),
+        Other(0xffb7),
+        Other(0xffb8),
+        Other(0xffb9),
+    ];
+    pub const ADD: Key = Other(0xffab);
+    pub const SUBTRACT: Key = Other(0xffad);
+    pub const MULTIPLY: Key = Other(0xffaa);
+    pub const DIVIDE: Key = Other(0xffaf);
+    pub const DECIMAL: Key = Other(0xffae);
+}
+
+/// Numpad keys, `N0` to `N9` like JSM or `NUMPAD0` to `NUMPAD9`.
+#[cfg(not(target_os = "macos"))]
+fn numpad_key(input: Input) -> IRes<enigo::Key> {
+    let key_parse = |key, tag| value(key, tag_no_case(tag));
+    alt((
+        map(
+            satisfy(|c| c.is_ascii_digit())
+                .preceded_by(alt((tag_no_case("Numpad"), tag_no_case("N")))),
+            |digit| numpad::DIGITS[digit as usize - '0' as usize],
+        ),
+        key_parse(numpad::ADD, "Add"),
+        key_parse(numpad::SUBTRACT, "Subtract"),
+        key_parse(numpad::MULTIPLY, "Multiply"),
+        key_parse(numpad::DIVIDE, "Divide"),
+        key_parse(numpad::DECIMAL, "Decimal"),
     ))(input)
 }
 
+#[cfg(target_os = "macos")]
+fn numpad_key(input: Input) -> IRes<enigo::Key> {
+    Err(nom::Err::Error(ErrorTree::from_tag(input, "a numpad key")))
+}
+
+#[cfg(not(target_os = "macos"))]
+const BACK_BUTTON: enigo::Button = enigo::Button::Back;
+#[cfg(not(target_os = "macos"))]
+const FORWARD_BUTTON: enigo::Button = enigo::Button::Forward;
+
+// enigo doesn't support the side buttons on macOS
+#[cfg(target_os = "macos")]
+const BACK_BUTTON: enigo::Button = enigo::Button::Left;
+#[cfg(target_os = "macos")]
+const FORWARD_BUTTON: enigo::Button = enigo::Button::Left;
+
 fn mousekey(input: Input) -> IRes<'_, enigo::Button> {
     use enigo::Button::*;
     let key_parse = |key, tag| value(key, tag_no_case(tag));
@@ -524,9 +1072,8 @@ fn mousekey(input: Input) -> IRes<'_, enigo::Button> {
         key_parse(Left, "LMouse"),
         key_parse(Middle, "MMouse"),
         key_parse(Right, "RMouse"),
-        // TODO: fix https://github.com/enigo-rs/enigo/issues/110
-        key_parse(Left, "BMouse"),
-        key_parse(Left, "FMouse"),
+        key_parse(BACK_BUTTON, "BMouse"),
+        key_parse(FORWARD_BUTTON, "FMouse"),
         key_parse(ScrollUp, "scrollup"),
         key_parse(ScrollDown, "scrolldown"),
         key_parse(ScrollLeft, "scrollleft"),
@@ -544,6 +1091,9 @@ fn special(input: Input) -> IRes<'_, SpecialKey> {
         parse(GyroInvertX(true), "gyro_inv_x"),
         parse(GyroInvertY(true), "gyro_inv_y"),
         parse(GyroTrackBall(true), "gyro_trackball"),
+        parse(InvertYToggle, "invert_y_toggle"),
+        parse(ToggleActive, "toggle_active"),
+        parse(GyroRatchet, "gyro_ratchet"),
     ))(input)
 }
 
@@ -558,3 +1108,159 @@ fn gamepadkey(input: Input) -> IRes<'_, virtual_gamepad::Key> {
         parse(Y, "X_Y"),
     ))(input)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn system_keys() {
+        use enigo::Key::*;
+        for (tag, key) in [
+            ("INSERT", Insert),
+            ("NUM_LOCK", Numlock),
+            ("PAUSE", Pause),
+            ("BREAK", Pause),
+            ("PRINTSCREEN", Print),
+            ("SCROLL_LOCK", SCROLL_LOCK),
+            ("MENU", MENU),
+            ("CONTEXT", MENU),
+        ] {
+            let (rest, parsed) = keyboardkey(tag).unwrap();
+            assert_eq!(rest, "");
+            assert_eq!(parsed, key, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn modifier_sides() {
+        use enigo::Key::*;
+        #[cfg(target_os = "windows")]
+        let (left_alt, right_alt) = (LMenu, RMenu);
+        #[cfg(all(unix, not(target_os = "macos")))]
+        let (left_alt, right_alt) = (Alt, Other(0xffea));
+        #[cfg(target_os = "macos")]
+        let (left_alt, right_alt) = (Option, ROption);
+        for (tag, key) in [
+            ("ALT", left_alt),
+            ("LALT", left_alt),
+            ("RALT", right_alt),
+            ("CONTROL", LControl),
+            ("LCONTROL", LControl),
+            ("RCONTROL", RControl),
+            ("SHIFT", LShift),
+            ("LSHIFT", LShift),
+            ("RSHIFT", RShift),
+        ] {
+            let (rest, parsed) = keyboardkey(tag).unwrap();
+            assert_eq!(rest, "");
+            assert_eq!(parsed, key, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn modifier_characters() {
+        use enigo::Key::Unicode;
+        use ActionModifier::*;
+        use EventModifier::*;
+        for (source, key, action_mod, event_mod) in [
+            ("+", '+', None, None),
+            ("++", '+', None, Some(Turbo)),
+            ("a+", 'a', None, Some(Turbo)),
+            ("'", '\'', None, None),
+            ("''+", '\'', None, Some(TapOrTurbo)),
+            ("_", '_', None, None),
+            ("__", '_', None, Some(Hold)),
+            ("\\", '\\', None, None),
+            ("//", '/', None, Some(Release)),
+            ("^", '^', None, None),
+            ("^^", '^', Some(Toggle), None),
+            ("^a", 'a', Some(Toggle), None),
+            ("!", '!', None, None),
+            ("!!\\", '!', Some(Instant), Some(Start)),
+        ] {
+            let (rest, parsed) = action(source).unwrap();
+            assert_eq!(rest, "", "{}", source);
+            assert!(
+                matches!(parsed.action, ActionType::Key(Unicode(c)) if c == key),
+                "{}",
+                source
+            );
+            assert_eq!(parsed.action_mod, action_mod, "{}", source);
+            assert_eq!(parsed.event_mod, event_mod, "{}", source);
+        }
+    }
+
+    #[test]
+    fn media_and_symbol_keys() {
+        use enigo::Key::*;
+        for (tag, key) in [
+            ("VOLUME_UP", VolumeUp),
+            ("VOLUME_DOWN", VolumeDown),
+            ("MUTE", VolumeMute),
+            ("NEXT_TRACK", MediaNextTrack),
+            ("PREV_TRACK", MediaPrevTrack),
+            ("PLAY_PAUSE", MediaPlayPause),
+            (";", Unicode(';')),
+            ("'", Unicode('\'')),
+            (",", Unicode(',')),
+            (".", Unicode('.')),
+            ("/", Unicode('/')),
+            ("\\", Unicode('\\')),
+            ("[", Unicode('[')),
+            ("]", Unicode(']')),
+            ("+", Unicode('+')),
+            ("-", Unicode('-')),
+            ("`", Unicode('`')),
+        ] {
+            let (rest, parsed) = keyboardkey(tag).unwrap();
+            assert_eq!(rest, "");
+            assert_eq!(parsed, key, "{}", tag);
+        }
+        assert!(keyboardkey("#").is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn numpad_keys() {
+        for (tag, key) in [
+            ("N0", numpad::DIGITS[0]),
+            ("n7", numpad::DIGITS[7]),
+            ("NUMPAD5", numpad::DIGITS[5]),
+            ("ADD", numpad::ADD),
+            ("SUBTRACT", numpad::SUBTRACT),
+            ("MULTIPLY", numpad::MULTIPLY),
+            ("DIVIDE", numpad::DIVIDE),
+            ("DECIMAL", numpad::DECIMAL),
+            ("STOP_TRACK", enigo::Key::MediaStop),
+        ] {
+            let (rest, parsed) = keyboardkey(tag).unwrap();
+            assert_eq!(rest, "");
+            assert_eq!(parsed, key, "{}", tag);
+        }
+        // Still the letter
+        assert_eq!(keyboardkey("N ").unwrap(), (" ", enigo::Key::Unicode('N')));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn mouse_side_buttons() {
+        assert_eq!(mousekey("BMOUSE").unwrap(), ("", enigo::Button::Back));
+        assert_eq!(mousekey("FMouse").unwrap(), ("", enigo::Button::Forward));
+    }
+
+    #[test]
+    fn duration_units() {
+        let ms100 = Duration::from_millis(100);
+        assert_eq!(duration("100ms").unwrap(), ("", ms100));
+        assert_eq!(duration("0.1").unwrap(), ("", ms100));
+        assert_eq!(duration("0.1s").unwrap(), ("", ms100));
+        assert_eq!(duration("2").unwrap(), ("", Duration::from_secs(2)));
+        assert!(duration("-1").is_err());
+        assert!(duration("inf").is_err());
+        assert!(duration("1e30").is_err());
+        assert!(scaled_duration("-5", 1e-3).is_err());
+        assert!(scaled_duration("1e300", 1e-3).is_err());
+    }
+}
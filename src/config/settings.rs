@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use cgmath::{vec2, Deg, Vector2, Zero};
 
-use crate::joystick::*;
+use crate::{joystick::*, mapping::Buttons};
 
 use super::types::*;
 
@@ -15,10 +15,24 @@ pub struct Settings {
     pub left_ring_mode: RingMode,
     pub right_ring_mode: RingMode,
     pub trigger_threshold: f64,
-    // TODO: Support alternative trigger modes
     pub zl_mode: TriggerMode,
     pub zr_mode: TriggerMode,
+    pub dpad_mode: DpadMode,
+    pub touchpad_mode: TouchpadMode,
     pub mouse: MouseSettings,
+    /// The `[name]` sections of the configuration.
+    pub profiles: Vec<Profile>,
+}
+
+/// Name of the unnamed part of the configuration, for `SWITCH_PROFILE`.
+pub const DEFAULT_PROFILE: &str = "DEFAULT";
+
+/// Configuration of a `[name]` section, independent of the rest of the file.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub settings: Settings,
+    pub buttons: Buttons,
 }
 
 impl Default for Settings {
@@ -33,7 +47,10 @@ impl Default for Settings {
             trigger_threshold: 0.5,
             zl_mode: TriggerMode::NoFull,
             zr_mode: TriggerMode::NoFull,
+            dpad_mode: DpadMode::Buttons,
+            touchpad_mode: TouchpadMode::None,
             mouse: MouseSettings::default(),
+            profiles: Vec::new(),
         }
     }
 }
@@ -41,7 +58,7 @@ impl Default for Settings {
 impl Settings {
     pub fn apply(&mut self, setting: Setting) {
         match setting {
-            Setting::Gyro(s) => self.gyro.apply(s),
+            Setting::Gyro(s) => self.gyro.apply(s.validated()),
             Setting::Stick(s) => self.stick.apply(s),
             Setting::LeftStickMode(m) => self.left_stick_mode = m,
             Setting::RightStickMode(m) => self.right_stick_mode = m,
@@ -50,7 +67,12 @@ impl Settings {
             Setting::TriggerThreshold(t) => self.trigger_threshold = t,
             Setting::ZLMode(m) => self.zl_mode = m,
             Setting::ZRMode(m) => self.zr_mode = m,
+            Setting::DpadMode(m) => self.dpad_mode = m,
+            Setting::TouchpadMode(m) => self.touchpad_mode = m,
             Setting::Mouse(m) => self.mouse.apply(m),
+            Setting::Buttons(_) => {
+                unreachable!("button settings are applied to the bindings by apply_cmd")
+            }
         }
     }
 
@@ -72,27 +94,62 @@ impl Settings {
 
     fn new_stick(&self, mode: StickMode) -> Box<dyn Stick> {
         match mode {
-            StickMode::Aim => Box::new(CameraStick::new()),
+            StickMode::Aim => Box::new(CameraStick::new(self.stick_smoother(mode))),
             StickMode::Flick | StickMode::FlickOnly | StickMode::RotateOnly => {
                 let flick = mode != StickMode::RotateOnly;
                 let rotate = mode != StickMode::FlickOnly;
                 Box::new(FlickStick::new(flick, rotate))
             }
-            StickMode::MouseRing => Box::new(AreaStick::ring()),
-            StickMode::MouseArea => Box::new(AreaStick::area()),
+            StickMode::MouseRing => Box::new(AreaStick::ring(
+                self.stick_smoother(mode),
+                StickSmoother::new(self.stick.area.ring_smooth_time),
+            )),
+            StickMode::MouseArea => Box::new(AreaStick::area(
+                self.stick_smoother(mode),
+                StickSmoother::new(self.stick.area.rel_smooth_time),
+            )),
             StickMode::NoMouse => Box::new(ButtonStick::new(self.left_ring_mode)),
-            StickMode::ScrollWheel => Box::new(ScrollStick::new()),
+            StickMode::ScrollWheel => Box::new(ScrollStick::new(self.stick_smoother(mode))),
         }
     }
+
+    /// Smoothing time constant of the stick input in this mode.
+    pub fn stick_smooth_time(&self, mode: StickMode) -> Duration {
+        match mode {
+            StickMode::Aim => self.stick.aim.smooth_time,
+            StickMode::MouseRing | StickMode::MouseArea => self.stick.area.smooth_time,
+            StickMode::ScrollWheel => self.stick.scroll.smooth_time,
+            StickMode::Flick
+            | StickMode::FlickOnly
+            | StickMode::RotateOnly
+            | StickMode::NoMouse => Duration::ZERO,
+        }
+    }
+
+    fn stick_smoother(&self, mode: StickMode) -> StickSmoother {
+        StickSmoother::new(self.stick_smooth_time(mode))
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct StickSettings {
     pub deadzone: f64,
+    pub deadzone_curve: DeadzoneCurve,
     pub fullzone: f64,
+    /// Overrides `fullzone` for the left stick.
+    pub left_fullzone: Option<f64>,
+    /// Overrides `fullzone` for the right stick.
+    pub right_fullzone: Option<f64>,
+    /// Longest frame duration fed to the sticks.
+    ///
+    /// The first frame after a controller connects can see a very large
+    /// `dt` because of the loop startup, which would make speed based sticks
+    /// like `CameraStick` jump.
+    pub max_frame_time: Duration,
     pub aim: AimStickSettings,
     pub flick: FlickStickSettings,
     pub scroll: ScrollStickSettings,
+    pub button: ButtonStickSettings,
     pub area: AreaStickSettings,
     pub motion: MotionStickSettings,
 }
@@ -101,10 +158,15 @@ impl Default for StickSettings {
     fn default() -> Self {
         Self {
             deadzone: 0.15,
+            deadzone_curve: DeadzoneCurve::Linear,
             fullzone: 0.9,
+            left_fullzone: None,
+            right_fullzone: None,
+            max_frame_time: Duration::from_millis(50),
             aim: Default::default(),
             flick: Default::default(),
             scroll: Default::default(),
+            button: Default::default(),
             area: Default::default(),
             motion: Default::default(),
         }
@@ -114,36 +176,189 @@ impl Default for StickSettings {
 impl StickSettings {
     fn apply(&mut self, setting: StickSetting) {
         match setting {
-            StickSetting::Deadzone(d) => self.deadzone = d,
-            StickSetting::FullZone(d) => self.fullzone = d,
+            StickSetting::Deadzone(d) => self.deadzone = clamp_ratio("STICK_DEADZONE_INNER", d),
+            StickSetting::DeadzoneCurve(c) => self.deadzone_curve = c,
+            StickSetting::FullZone(d) => {
+                self.fullzone = 1. - clamp_ratio("STICK_DEADZONE_OUTER", 1. - d)
+            }
+            StickSetting::LeftFullZone(d) => {
+                self.left_fullzone = Some(1. - clamp_ratio("LEFT_STICK_DEADZONE_OUTER", 1. - d))
+            }
+            StickSetting::RightFullZone(d) => {
+                self.right_fullzone = Some(1. - clamp_ratio("RIGHT_STICK_DEADZONE_OUTER", 1. - d))
+            }
+            StickSetting::MaxFrameTime(t) => self.max_frame_time = t,
             StickSetting::Aim(s) => self.aim.apply(s),
             StickSetting::Flick(s) => self.flick.apply(s),
             StickSetting::Scroll(s) => self.scroll.apply(s),
+            StickSetting::Button(s) => self.button.apply(s),
             StickSetting::Area(s) => self.area.apply(s),
             StickSetting::Motion(s) => self.motion.apply(s),
         }
     }
+
+    /// Inner deadzone of `side`.
+    ///
+    /// The motion stick applies its own deadzones in degrees, so its input
+    /// has none left.
+    pub fn deadzone(&self, side: StickSide) -> f64 {
+        match side {
+            StickSide::Left | StickSide::Right => self.deadzone,
+            StickSide::Motion => 0.,
+        }
+    }
+
+    /// Reset the deadzones if the inner one of a stick reaches its outer one,
+    /// which leaves no range to move in.
+    pub fn check_zones(&mut self) {
+        for (side, name) in [(StickSide::Left, "left"), (StickSide::Right, "right")] {
+            let fullzone = self.fullzone(side);
+            if self.deadzone >= fullzone {
+                eprintln!(
+                    "Warning: the {} stick inner deadzone ({}) must end before its outer \
+                     deadzone starts ({}). Using the default deadzones.",
+                    name, self.deadzone, fullzone
+                );
+                let default = Self::default();
+                self.deadzone = default.deadzone;
+                self.fullzone = default.fullzone;
+                self.left_fullzone = default.left_fullzone;
+                self.right_fullzone = default.right_fullzone;
+                return;
+            }
+        }
+    }
+
+    /// Outer deadzone of `side`, falling back to the global one.
+    pub fn fullzone(&self, side: StickSide) -> f64 {
+        match side {
+            StickSide::Left => self.left_fullzone,
+            StickSide::Right => self.right_fullzone,
+            // Full tilt is normalized to 1, keep a margin for the rounding
+            StickSide::Motion => return 1. - 1e-9,
+        }
+        .unwrap_or(self.fullzone)
+    }
+
+    /// Amplitude of `side` rescaled between the deadzones, from 0 to 1 and
+    /// following `deadzone_curve`.
+    pub fn amp_zones(&self, amp: f64, side: StickSide) -> f64 {
+        let deadzone = self.deadzone(side);
+        let linear = ((amp - deadzone) / (self.fullzone(side) - deadzone)).clamp(0., 1.);
+        match self.deadzone_curve {
+            DeadzoneCurve::Linear => linear,
+            DeadzoneCurve::Smoothstep => linear * linear * (3. - 2. * linear),
+            DeadzoneCurve::Exponential => {
+                const STEEPNESS: f64 = 3.;
+                ((STEEPNESS * linear).exp() - 1.) / (STEEPNESS.exp() - 1.)
+            }
+        }
+    }
+}
+
+fn clamp_ratio(name: &str, value: f64) -> f64 {
+    if (0. ..=1.).contains(&value) {
+        value
+    } else {
+        let clamped = value.clamp(0., 1.);
+        eprintln!(
+            "Warning: {} must be between 0 and 1 or a percentage, got {}. Using {}.",
+            name, value, clamped
+        );
+        clamped
+    }
+}
+
+/// Lowest non zero `GYRO_OUTPUT_RATE`, in Hz. Lower rates would hold the
+/// movement for too long to be useful.
+const MIN_OUTPUT_RATE: f64 = 1.;
+
+/// Sensitivities must be positive, inverting an axis has its own settings.
+/// Other values are replaced by `default` with a warning. A default of 0
+/// means unset, so 0 is then accepted.
+fn check_sens(name: &str, value: f64, default: f64) -> f64 {
+    if value > 0. || value == default {
+        value
+    } else {
+        eprintln!(
+            "Warning: {} must be positive, got {}. Using the default.",
+            name, value
+        );
+        default
+    }
+}
+
+impl GyroSetting {
+    /// Fix the sensitivities, with a warning.
+    ///
+    /// Done once when parsing, as layer settings are applied at every frame.
+    pub fn validated(self) -> Self {
+        let default = GyroSettings::default();
+        let sens = |name, x, y: Option<f64>, default: Vector2<f64>| {
+            (
+                check_sens(name, x, default.x),
+                y.map(|y| check_sens(name, y, default.y)),
+            )
+        };
+        match self {
+            GyroSetting::Sensitivity(x, y) => {
+                let (x, y) = sens("GYRO_SENS", x, y, default.sens);
+                GyroSetting::Sensitivity(x, y)
+            }
+            GyroSetting::MinSens(x, y) => {
+                let (x, y) = sens("MIN_GYRO_SENS", x, y, default.slow_sens);
+                GyroSetting::MinSens(x, y)
+            }
+            GyroSetting::MaxSens(x, y) => {
+                let (x, y) = sens("MAX_GYRO_SENS", x, y, default.fast_sens);
+                GyroSetting::MaxSens(x, y)
+            }
+            GyroSetting::OutputRate(rate) if rate < MIN_OUTPUT_RATE && rate != 0. => {
+                eprintln!(
+                    "Warning: GYRO_OUTPUT_RATE must be 0 or at least {}Hz, got {}. Outputting \
+                     every frame.",
+                    MIN_OUTPUT_RATE, rate
+                );
+                GyroSetting::OutputRate(0.)
+            }
+            s => s,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct AimStickSettings {
     pub sens_dps: f64,
-    pub power: f64,
+    /// Overrides `sens_dps` just out of the deadzone, blending to `fast_dps`
+    /// at full tilt.
+    pub slow_dps: Option<f64>,
+    /// Overrides `sens_dps` at full tilt.
+    pub fast_dps: Option<f64>,
+    pub response_curve: StickResponseCurve,
     pub left_axis: Vector2<InvertMode>,
     pub right_axis: Vector2<InvertMode>,
+    /// `STICK_ACCELERATION = OFF` ignores the rate and cap below.
+    ///
+    /// Acceleration is off by default all the same, with a zero rate.
+    pub acceleration: bool,
     pub acceleration_rate: f64,
     pub acceleration_cap: f64,
+    pub smooth_time: Duration,
 }
 
 impl Default for AimStickSettings {
     fn default() -> Self {
         Self {
             sens_dps: 360.,
-            power: 1.,
+            slow_dps: None,
+            fast_dps: None,
+            response_curve: StickResponseCurve::Linear,
             left_axis: vec2(InvertMode::Normal, InvertMode::Normal),
             right_axis: vec2(InvertMode::Normal, InvertMode::Normal),
+            acceleration: true,
             acceleration_rate: 0.,
             acceleration_cap: 1000000.,
+            smooth_time: Duration::ZERO,
         }
     }
 }
@@ -151,20 +366,53 @@ impl Default for AimStickSettings {
 impl AimStickSettings {
     fn apply(&mut self, setting: AimStickSetting) {
         match setting {
-            AimStickSetting::Sens(s) => self.sens_dps = s,
-            AimStickSetting::Power(s) => self.power = s,
+            AimStickSetting::Sens(s) => {
+                self.sens_dps = check_sens("STICK_SENS", s, Self::default().sens_dps)
+            }
+            // Unset by default, following `STICK_SENS`
+            AimStickSetting::SensSlow(s) => {
+                self.slow_dps = Some(check_sens("STICK_SENS_SLOW", s, 0.)).filter(|&s| s > 0.)
+            }
+            AimStickSetting::SensFast(s) => {
+                self.fast_dps = Some(check_sens("STICK_SENS_FAST", s, 0.)).filter(|&s| s > 0.)
+            }
+            AimStickSetting::Power(s) => self.response_curve = StickResponseCurve::Power(s),
+            AimStickSetting::ResponseCurve(StickResponseCurve::SCurve { knee })
+                if !(knee > 0. && knee < 1.) =>
+            {
+                eprintln!(
+                    "Warning: the STICK_RESPONSE_CURVE knee must be between 0 and 1 excluded, \
+                     got {}. Using 0.5.",
+                    knee
+                );
+                self.response_curve = StickResponseCurve::SCurve { knee: 0.5 };
+            }
+            AimStickSetting::ResponseCurve(c) => self.response_curve = c,
             AimStickSetting::LeftAxis(v1, v2) => self.left_axis = vec2(v1, v2.unwrap_or(v1)),
             AimStickSetting::RightAxis(v1, v2) => self.right_axis = vec2(v1, v2.unwrap_or(v1)),
+            AimStickSetting::Acceleration(b) => self.acceleration = b,
             AimStickSetting::AccelerationRate(s) => self.acceleration_rate = s,
             AimStickSetting::AccelerationCap(s) => self.acceleration_cap = s,
+            AimStickSetting::SmoothTime(t) => self.smooth_time = t,
         }
     }
+
+    /// Sensitivity at `amp`, the tilt rescaled between the deadzones.
+    pub fn sens_at(&self, amp: f64) -> f64 {
+        let slow = self.slow_dps.unwrap_or(self.sens_dps);
+        let fast = self.fast_dps.unwrap_or(self.sens_dps);
+        slow + (fast - slow) * amp
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct FlickStickSettings {
     pub flick_time: Duration,
+    /// Flicks of `angle` take `FLICK_TIME * (angle / 180°) ^ (1 + exponent)`,
+    /// so 0 is proportional to the angle and -1 is always `FLICK_TIME`.
     pub exponent: f64,
+    /// Pushing the stick less than this angle away from forward starts
+    /// rotating without flicking first.
     pub forward_deadzone_arc: Deg<f64>,
 }
 
@@ -191,11 +439,15 @@ impl FlickStickSettings {
 #[derive(Debug, Clone)]
 pub struct ScrollStickSettings {
     pub sens: Deg<f64>,
+    pub smooth_time: Duration,
 }
 
 impl Default for ScrollStickSettings {
     fn default() -> Self {
-        Self { sens: Deg(10.) }
+        Self {
+            sens: Deg(10.),
+            smooth_time: Duration::ZERO,
+        }
     }
 }
 
@@ -203,6 +455,38 @@ impl ScrollStickSettings {
     fn apply(&mut self, setting: ScrollStickSetting) {
         match setting {
             ScrollStickSetting::Sens(s) => self.sens = s,
+            ScrollStickSetting::SmoothTime(t) => self.smooth_time = t,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ButtonStickSettings {
+    /// Width of the zone around each diagonal where both directions are
+    /// pressed. Outside of it, only the dominant direction is.
+    pub diagonal_tolerance: Deg<f64>,
+    /// Tilt needed to press a direction, instead of the stick deadzone.
+    pub threshold: Option<f64>,
+}
+
+impl Default for ButtonStickSettings {
+    fn default() -> Self {
+        Self {
+            diagonal_tolerance: Deg(30.),
+            threshold: None,
+        }
+    }
+}
+
+impl ButtonStickSettings {
+    fn apply(&mut self, setting: ButtonStickSetting) {
+        match setting {
+            ButtonStickSetting::DiagonalTolerance(d) => {
+                self.diagonal_tolerance = Deg(d.0.clamp(0., 90.))
+            }
+            ButtonStickSetting::Threshold(t) => {
+                self.threshold = Some(clamp_ratio("STICK_BUTTON_THRESHOLD", t))
+            }
         }
     }
 }
@@ -211,6 +495,11 @@ impl ScrollStickSettings {
 pub struct AreaStickSettings {
     pub screen_resolution: Vector2<u32>,
     pub screen_radius: u32,
+    pub smooth_time: Duration,
+    /// Smoothing of the relative cursor movement in `MOUSE_AREA` mode.
+    pub rel_smooth_time: Duration,
+    /// Gliding of the cursor toward its position in `MOUSE_RING` mode.
+    pub ring_smooth_time: Duration,
 }
 
 impl Default for AreaStickSettings {
@@ -218,6 +507,9 @@ impl Default for AreaStickSettings {
         Self {
             screen_resolution: vec2(1920, 1080),
             screen_radius: 50,
+            smooth_time: Duration::ZERO,
+            rel_smooth_time: Duration::ZERO,
+            ring_smooth_time: Duration::ZERO,
         }
     }
 }
@@ -228,6 +520,9 @@ impl AreaStickSettings {
             AreaStickSetting::ScreenResolutionX(r) => self.screen_resolution.x = r,
             AreaStickSetting::ScreenResolutionY(r) => self.screen_resolution.y = r,
             AreaStickSetting::Radius(r) => self.screen_radius = r,
+            AreaStickSetting::SmoothTime(t) => self.smooth_time = t,
+            AreaStickSetting::RelSmoothTime(t) => self.rel_smooth_time = t,
+            AreaStickSetting::RingSmoothTime(t) => self.ring_smooth_time = t,
         }
     }
 }
@@ -239,6 +534,12 @@ pub struct MotionStickSettings {
     pub deadzone: Deg<f64>,
     pub fullzone: Deg<f64>,
     pub axis: Vector2<InvertMode>,
+    /// Tilts used for the X and Y axes of the stick.
+    pub axis_map: Vector2<TiltSource>,
+    /// Roll above which `LEAN_LEFT` or `LEAN_RIGHT` is pressed.
+    pub lean_threshold: Deg<f64>,
+    /// Angle from flat below which `FACE_UP` or `FACE_DOWN` is pressed.
+    pub face_threshold: Deg<f64>,
 }
 
 impl Default for MotionStickSettings {
@@ -249,6 +550,18 @@ impl Default for MotionStickSettings {
             deadzone: Deg(15.),
             fullzone: Deg(45.),
             axis: vec2(InvertMode::Normal, InvertMode::Normal),
+            axis_map: vec2(
+                TiltSource {
+                    axis: TiltAxis::Roll,
+                    inverted: false,
+                },
+                TiltSource {
+                    axis: TiltAxis::Pitch,
+                    inverted: false,
+                },
+            ),
+            lean_threshold: Deg(15.),
+            face_threshold: Deg(20.),
         }
     }
 }
@@ -259,8 +572,17 @@ pub struct GyroSettings {
     ///
     /// <http://gyrowiki.jibbsmart.com/blog:good-gyro-controls-part-1:the-gyro-is-a-mouse#toc5>
     pub sens: Vector2<f64>,
+    /// Mouse counts for a full in-game turn, from `GYRO_CM360`.
+    ///
+    /// When set, the sensitivities are relative to a 1:1 mapping between
+    /// controller and in-game rotation, see `natural_sens`.
+    pub counts_per_360: Option<f64>,
     pub invert: (bool, bool),
+    /// Multiplier of the raw gyro speed, for controllers reporting it in
+    /// another unit.
+    pub scale: f64,
     pub space: GyroSpace,
+    pub fusion: GyroFusion,
     /// Stabilize slow movements
     ///
     /// <http://gyrowiki.jibbsmart.com/blog:good-gyro-controls-part-1:the-gyro-is-a-mouse#toc9>
@@ -271,6 +593,12 @@ pub struct GyroSettings {
     /// Rotations smaller than this will be smoothed over a small period of time.
     pub smooth_threshold: f64,
     pub smooth_time: Duration,
+    pub smooth_mode: GyroSmoothMode,
+    /// How much of the smoothing lag `GyroSmoothMode::Predictive` compensates.
+    pub smooth_prediction: f64,
+    /// Bound of the smoothing window in samples, on top of `smooth_time`, so
+    /// high rate sensors don't average hundreds of samples per frame.
+    pub smooth_max_samples: usize,
     /// Enables acceleration.
     ///
     /// <http://gyrowiki.jibbsmart.com/blog:good-gyro-controls-part-1:the-gyro-is-a-mouse#toc7>
@@ -278,28 +606,58 @@ pub struct GyroSettings {
     pub slow_sens: Vector2<f64>,
     pub fast_threshold: f64,
     pub fast_sens: Vector2<f64>,
+    /// Shape of the transition between `slow_sens` and `fast_sens`.
+    pub accel_curve: GyroAccelCurve,
+    /// Rotation speed in degrees per second above which `GYRO_MOVE` is pressed.
+    pub motion_threshold: f64,
+    pub output: GyroOutput,
+    /// Mouse movements per second, accumulating the sensor samples in
+    /// between. Zero outputs every sample.
+    pub output_rate: f64,
+    /// Scroll ticks per degree of movement when `output` is `Scroll`.
+    pub scroll_sens: f64,
+    /// Rumble when the gyro is enabled or disabled.
+    pub toggle_rumble: bool,
+    /// Rate at which the speed decays with `GYRO_TRACKBALL`, per second.
+    pub trackball_decay: f64,
+    /// Whether the gyro is enabled when the controller connects.
+    pub default_enabled: bool,
 }
 
 impl Default for GyroSettings {
     fn default() -> Self {
         Self {
             sens: vec2(1., 1.),
+            counts_per_360: None,
             invert: (false, false),
+            scale: 1.,
             space: GyroSpace::PlayerTurn,
+            fusion: GyroFusion::Simple,
             cutoff_speed: 0.,
             cutoff_recovery: 0.,
             smooth_threshold: 0.,
             smooth_time: Duration::from_millis(125),
+            smooth_mode: GyroSmoothMode::Average,
+            smooth_prediction: 1.,
+            smooth_max_samples: 256,
             slow_sens: Vector2::zero(),
             slow_threshold: 0.,
             fast_sens: Vector2::zero(),
             fast_threshold: 0.,
+            accel_curve: GyroAccelCurve::Linear,
+            motion_threshold: 30.,
+            output: GyroOutput::Mouse,
+            output_rate: 0.,
+            scroll_sens: 0.1,
+            toggle_rumble: false,
+            trackball_decay: 1.,
+            default_enabled: true,
         }
     }
 }
 
 impl GyroSettings {
-    fn apply(&mut self, setting: GyroSetting) {
+    pub fn apply(&mut self, setting: GyroSetting) {
         match setting {
             GyroSetting::Sensitivity(x, y) => {
                 self.sens = vec2(x, y.unwrap_or(x));
@@ -313,12 +671,43 @@ impl GyroSettings {
             }
             GyroSetting::MaxThreshold(s) => self.fast_threshold = s,
             GyroSetting::Space(s) => self.space = s,
+            GyroSetting::Fusion(f) => self.fusion = f,
             GyroSetting::InvertX(b) => self.invert.0 = b == InvertMode::Inverted,
             GyroSetting::InvertY(b) => self.invert.1 = b == InvertMode::Inverted,
             GyroSetting::CutoffSpeed(s) => self.cutoff_speed = s,
             GyroSetting::CutoffRecovery(s) => self.cutoff_recovery = s,
             GyroSetting::SmoothThreshold(s) => self.smooth_threshold = s,
             GyroSetting::SmoothTime(s) => self.smooth_time = s,
+            GyroSetting::Scale(s) => self.scale = s,
+            GyroSetting::Cm360(cm, dpi) => self.counts_per_360 = Some(cm / 2.54 * dpi),
+            GyroSetting::ToggleRumble(r) => self.toggle_rumble = r,
+            GyroSetting::TrackBallDecay(d) => self.trackball_decay = d.max(0.),
+            GyroSetting::Default(enabled) => self.default_enabled = enabled,
+            GyroSetting::SmoothMode(m) => self.smooth_mode = m,
+            GyroSetting::SmoothPrediction(p) => self.smooth_prediction = p,
+            GyroSetting::SmoothMaxSamples(n) => self.smooth_max_samples = n.max(1) as usize,
+            GyroSetting::MotionThreshold(s) => self.motion_threshold = s,
+            GyroSetting::Output(o) => self.output = o,
+            GyroSetting::OutputRate(r) => self.output_rate = r,
+            GyroSetting::AccelCurve(c) => self.accel_curve = c,
+            GyroSetting::ScrollSens(s) => self.scroll_sens = s,
+        }
+    }
+
+    /// Multiplier applied on top of the sensitivities.
+    ///
+    /// `mouse_move_relative` emits `REAL_WORLD_CALIBRATION * IN_GAME_SENS`
+    /// counts per degree, while the game turns by one degree every
+    /// `counts_per_360 / 360` counts, so with `counts_per_360` set:
+    ///
+    /// ```text
+    /// counts_per_360 = cm / 2.54 * dpi
+    /// natural_sens = counts_per_360 / (360 * REAL_WORLD_CALIBRATION * IN_GAME_SENS)
+    /// ```
+    pub fn natural_sens(&self, mouse: &MouseSettings) -> f64 {
+        match self.counts_per_360 {
+            Some(counts) => counts / (360. * mouse.real_world_calibration * mouse.in_game_sens),
+            None => 1.,
         }
     }
 }
@@ -328,6 +717,10 @@ pub struct MouseSettings {
     pub counter_os_speed: bool,
     pub real_world_calibration: f64,
     pub in_game_sens: f64,
+    /// Resting input producing less than this many pixels is held back.
+    pub min_move: f64,
+    /// Invert the vertical mouse movement, on top of the per source inversions.
+    pub invert_y: bool,
 }
 
 impl Default for MouseSettings {
@@ -336,6 +729,8 @@ impl Default for MouseSettings {
             counter_os_speed: false,
             real_world_calibration: 1.,
             in_game_sens: 1.,
+            min_move: 0.,
+            invert_y: false,
         }
     }
 }
@@ -344,11 +739,14 @@ impl MouseSettings {
     fn apply(&mut self, setting: MouseSetting) {
         match setting {
             MouseSetting::CounterOSSpeed(c) => {
-                println!("Warning: counter os speed not supported");
+                if cfg!(not(windows)) {
+                    println!("Warning: counter os speed is only needed on Windows");
+                }
                 self.counter_os_speed = c;
             }
             MouseSetting::RealWorldCalibration(c) => self.real_world_calibration = c,
             MouseSetting::InGameSens(s) => self.in_game_sens = s,
+            MouseSetting::MinMove(m) => self.min_move = m,
         }
     }
 }
@@ -360,6 +758,9 @@ impl MotionStickSettings {
             MotionStickSetting::Deadzone(m) => self.deadzone = m,
             MotionStickSetting::Fullzone(m) => self.fullzone = m,
             MotionStickSetting::Axis(v1, v2) => self.axis = vec2(v1, v2.unwrap_or(v1)),
+            MotionStickSetting::AxisMap(x, y) => self.axis_map = vec2(x, y),
+            MotionStickSetting::LeanThreshold(t) => self.lean_threshold = t,
+            MotionStickSetting::FaceThreshold(t) => self.face_threshold = t,
         }
     }
 }
@@ -23,12 +23,16 @@ pub enum EventModifier {
     Start,
     Release,
     Turbo,
+    /// Tap on a short press, turbo once held.
+    TapOrTurbo,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct JSMAction {
     pub action_mod: Option<ActionModifier>,
     pub event_mod: Option<EventModifier>,
+    /// Hold delay of the binding, from a `_` modifier followed by a number of milliseconds.
+    pub hold_delay: Option<Duration>,
     pub action: ActionType,
 }
 
@@ -46,13 +50,15 @@ impl From<(ActionType, ClickType)> for ExtAction {
         match a {
             ActionType::Key(k) => ExtAction::KeyPress(k, b),
             ActionType::Mouse(k) => ExtAction::MousePress(k, b),
+            ActionType::Special(SpecialKey::None) => ExtAction::None,
             ActionType::Special(SpecialKey::GyroOn) => ExtAction::GyroOn(b),
             ActionType::Special(SpecialKey::GyroOff) => ExtAction::GyroOff(b),
-            ActionType::Special(s) => {
-                // TODO: Handle every special key.
-                eprintln!("Warning: special key {:?} is unimplemented", s);
-                ExtAction::None
-            }
+            ActionType::Special(SpecialKey::GyroInvertX(_)) => ExtAction::GyroInvertX(b),
+            ActionType::Special(SpecialKey::GyroInvertY(_)) => ExtAction::GyroInvertY(b),
+            ActionType::Special(SpecialKey::InvertYToggle) => ExtAction::InvertY(b),
+            ActionType::Special(SpecialKey::ToggleActive) => ExtAction::ToggleActive(b),
+            ActionType::Special(SpecialKey::GyroRatchet) => ExtAction::GyroRatchet(b),
+            ActionType::Special(SpecialKey::GyroTrackBall(_)) => ExtAction::GyroTrackBall(b),
             #[cfg(feature = "vgamepad")]
             ActionType::Gamepad(k) => ExtAction::GamepadKeyPress(k, b),
         }
@@ -74,6 +80,9 @@ pub enum SpecialKey {
     GyroInvertX(bool),
     GyroInvertY(bool),
     GyroTrackBall(bool),
+    InvertYToggle,
+    ToggleActive,
+    GyroRatchet,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -102,10 +111,15 @@ pub enum StickMode {
 #[derive(Debug, Copy, Clone)]
 pub enum StickSetting {
     Deadzone(f64),
+    DeadzoneCurve(DeadzoneCurve),
     FullZone(f64),
+    LeftFullZone(f64),
+    RightFullZone(f64),
+    MaxFrameTime(Duration),
     Aim(AimStickSetting),
     Flick(FlickStickSetting),
     Scroll(ScrollStickSetting),
+    Button(ButtonStickSetting),
     Area(AreaStickSetting),
     Motion(MotionStickSetting),
 }
@@ -113,11 +127,16 @@ pub enum StickSetting {
 #[derive(Debug, Copy, Clone)]
 pub enum AimStickSetting {
     Sens(f64),
+    SensSlow(f64),
+    SensFast(f64),
     Power(f64),
+    ResponseCurve(StickResponseCurve),
     LeftAxis(InvertMode, Option<InvertMode>),
     RightAxis(InvertMode, Option<InvertMode>),
+    Acceleration(bool),
     AccelerationRate(f64),
     AccelerationCap(f64),
+    SmoothTime(Duration),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -130,6 +149,13 @@ pub enum FlickStickSetting {
 #[derive(Debug, Copy, Clone)]
 pub enum ScrollStickSetting {
     Sens(Deg<f64>),
+    SmoothTime(Duration),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum ButtonStickSetting {
+    DiagonalTolerance(Deg<f64>),
+    Threshold(f64),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -137,6 +163,9 @@ pub enum AreaStickSetting {
     ScreenResolutionX(u32),
     ScreenResolutionY(u32),
     Radius(u32),
+    SmoothTime(Duration),
+    RelSmoothTime(Duration),
+    RingSmoothTime(Duration),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -146,6 +175,22 @@ pub enum MotionStickSetting {
     Deadzone(Deg<f64>),
     Fullzone(Deg<f64>),
     Axis(InvertMode, Option<InvertMode>),
+    AxisMap(TiltSource, TiltSource),
+    LeanThreshold(Deg<f64>),
+    FaceThreshold(Deg<f64>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TiltAxis {
+    Roll,
+    Pitch,
+}
+
+/// Controller tilt feeding a motion stick axis.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TiltSource {
+    pub axis: TiltAxis,
+    pub inverted: bool,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -156,12 +201,27 @@ pub enum GyroSetting {
     MaxSens(f64, Option<f64>),
     MaxThreshold(f64),
     Space(GyroSpace),
+    Fusion(GyroFusion),
     InvertX(InvertMode),
     InvertY(InvertMode),
     CutoffSpeed(f64),
     CutoffRecovery(f64),
     SmoothThreshold(f64),
     SmoothTime(Duration),
+    SmoothMode(GyroSmoothMode),
+    SmoothPrediction(f64),
+    SmoothMaxSamples(u32),
+    MotionThreshold(f64),
+    Output(GyroOutput),
+    AccelCurve(GyroAccelCurve),
+    ScrollSens(f64),
+    Scale(f64),
+    /// Mouse distance in cm for a full in-game turn, and the mouse DPI.
+    Cm360(f64, f64),
+    OutputRate(f64),
+    ToggleRumble(bool),
+    TrackBallDecay(f64),
+    Default(bool),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -169,9 +229,72 @@ pub enum MouseSetting {
     CounterOSSpeed(bool),
     RealWorldCalibration(f64),
     InGameSens(f64),
+    MinMove(f64),
 }
 
+/// Button timings, applied to `Buttons` instead of `Settings`.
 #[derive(Debug, Copy, Clone)]
+pub enum ButtonsSetting {
+    DebounceTime(Duration),
+    SimPressWindow(Duration),
+    TurboPeriod(Duration),
+    HoldPressTime(Duration),
+    DblPressWindow(Duration),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GyroSmoothMode {
+    /// Rolling average over `GYRO_SMOOTH_TIME`.
+    Average,
+    /// Rolling average, extrapolated to compensate for its lag.
+    Predictive,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GyroAccelCurve {
+    Linear,
+    Smoothstep,
+    Exponential,
+}
+
+/// Ramp of the stick amplitude between the inner and outer deadzones.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeadzoneCurve {
+    Linear,
+    Smoothstep,
+    Exponential,
+}
+
+/// Speed of the `AIM` stick mode depending on the amplitude, once out of
+/// the deadzones.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StickResponseCurve {
+    Linear,
+    /// Amplitude to the given exponent, like `STICK_POWER`.
+    Power(f64),
+    /// Slow around the center and fast at the edge, switching at `knee`.
+    SCurve {
+        knee: f64,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GyroOutput {
+    Mouse,
+    Scroll,
+}
+
+/// What the touchpad fingers do, besides the `TOUCH` binding.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TouchpadMode {
+    /// Nothing.
+    None,
+    /// The first finger down places the cursor, the touchpad covering the
+    /// whole screen.
+    Mouse,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GyroSpace {
     Local,
     WorldTurn,
@@ -180,6 +303,14 @@ pub enum GyroSpace {
     PlayerLean,
 }
 
+/// How the gravity direction is tracked for the world and player spaces.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GyroFusion {
+    Simple,
+    /// Trusts the accelerometer less while the controller shakes.
+    Adaptive,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum Setting {
     Gyro(GyroSetting),
@@ -192,6 +323,19 @@ pub enum Setting {
     RightRingMode(RingMode),
     Stick(StickSetting),
     Mouse(MouseSetting),
+    Buttons(ButtonsSetting),
+    DpadMode(DpadMode),
+    TouchpadMode(TouchpadMode),
+}
+
+/// How the dpad buttons are handled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DpadMode {
+    /// Through their own bindings.
+    Buttons,
+    /// Together as a scroll wheel, up and down scrolling vertically, left and
+    /// right horizontally.
+    Scroll,
 }
 
 #[derive(Debug, Clone)]
@@ -199,7 +343,12 @@ pub enum Cmd {
     Map(Key, Vec<JSMAction>),
     Special(SpecialKey),
     Setting(Setting),
+    LayerSetting(MapKey, GyroSetting),
     Reset,
+    /// Start of a `[name]` profile section.
+    Profile(String),
+    /// `KEY = SWITCH_PROFILE name`.
+    SwitchProfile(Key, String),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
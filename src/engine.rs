@@ -1,53 +1,137 @@
 use std::{
+    collections::HashSet,
     ops::DerefMut,
     time::{Duration, Instant},
 };
 
-use cgmath::Vector2;
-use enigo::{Direction, Keyboard as _, Mouse as _};
-use hid_gamepad_types::{Acceleration, Motion, RotationSpeed};
+use cgmath::{vec2, Deg, ElementWise, InnerSpace, Rad, Vector2, Vector3, Zero};
+use enigo::{Axis, Button, Direction, Key};
+use hid_gamepad_types::{Acceleration, JoyKey, Motion, RotationSpeed};
 
 use crate::{
     calibration::Calibration,
-    config::{settings::Settings, types::GyroSpace},
+    config::{
+        settings::{
+            GyroSettings, MotionStickSettings, MouseSettings, Profile, Settings, DEFAULT_PROFILE,
+        },
+        types::{DpadMode, GyroFusion, GyroOutput, GyroSpace, StickMode, TouchpadMode},
+    },
     gyromouse::GyroMouse,
     joystick::{Stick, StickSide},
-    mapping::{Buttons, ExtAction},
+    mapping::{Buttons, ExtAction, VirtualKey},
     motion_stick::MotionStick,
     mouse::{Mouse, MouseMovement},
     space_mapper::{
-        self, LocalSpace, PlayerSpace, SensorFusion, SimpleFusion, SpaceMapper, WorldSpace,
+        self, AdaptativeFusion, LocalSpace, PlayerLeanSpace, PlayerSpace, SensorFusion,
+        SimpleFusion, SpaceMapper, WorldLeanSpace, WorldSpace,
     },
+    trace::Trace,
+    trigger::Trigger,
     ClickType,
 };
 
+/// Rumble pulse requested by the engine, played by the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rumble {
+    pub low_frequency: u16,
+    pub high_frequency: u16,
+    pub duration: Duration,
+}
+
+/// Short and sharp, when the gyro gets enabled.
+const GYRO_ON_RUMBLE: Rumble = Rumble {
+    low_frequency: 0,
+    high_frequency: 40000,
+    duration: Duration::from_millis(80),
+};
+
+/// Longer and duller, when the gyro gets disabled.
+const GYRO_OFF_RUMBLE: Rumble = Rumble {
+    low_frequency: 40000,
+    high_frequency: 0,
+    duration: Duration::from_millis(150),
+};
+
+/// Runtime state kept across a controller reconnection.
+#[derive(Debug, Clone)]
+pub struct SessionState {
+    gyro_enabled: bool,
+    layers: Vec<u8>,
+}
+
 pub struct Engine {
     settings: Settings,
+    /// Gyro settings with the ones of the active layers applied, along with
+    /// the layers they were computed for.
+    layered_gyro: Option<(Vec<u8>, GyroSettings)>,
+    /// Profiles of the configuration, the unnamed part first.
+    profiles: Vec<Profile>,
+    /// Index of the active profile.
+    profile: usize,
     left_stick: Box<dyn Stick>,
     right_stick: Box<dyn Stick>,
     motion_stick: MotionStick,
     buttons: Buttons,
     mouse: Mouse,
     gyro: Gyro,
+    /// Cleared when another controller owns the cursor in area stick modes.
+    area_mouse_enabled: bool,
+    /// Output of the area sticks while `area_mouse_enabled` is cleared. They
+    /// keep following the stick so they don't jump once enabled again.
+    muted_mouse: Mouse,
+    /// Buffer for the samples of `handle_motion_frame`, kept to avoid an
+    /// allocation per frame.
+    motion_samples: Vec<(Motion, Duration)>,
+    /// Set by `TOGGLE_ACTIVE`, suspends every output except the toggle itself.
+    paused: bool,
+    rumble: Option<Rumble>,
+    trace: Option<Trace>,
+    dpad_scroll: DpadScroll,
+    orientation: OrientationKeys,
+    touchpad: Touchpad,
+    triggers: [Trigger; 2],
+    /// Keys held down by a press or a `^` toggle binding.
+    held_keys: HashSet<Key>,
+    /// Mouse buttons held down by a press or a `^` toggle binding.
+    held_buttons: HashSet<Button>,
     #[cfg(feature = "vgamepad")]
     gamepad: Option<Box<dyn virtual_gamepad::Backend>>,
 }
 
 impl Engine {
     pub fn new(
-        settings: Settings,
+        mut settings: Settings,
         buttons: Buttons,
         calibration: Calibration,
         mouse: Mouse,
     ) -> anyhow::Result<Self> {
+        let profiles = take_profiles(&mut settings, &buttons);
         Ok(Engine {
+            profiles,
+            profile: 0,
             left_stick: settings.new_left_stick(),
             right_stick: settings.new_right_stick(),
             motion_stick: MotionStick::new(&settings),
             buttons,
             mouse,
             gyro: Gyro::new(&settings, calibration),
+            area_mouse_enabled: true,
+            muted_mouse: Mouse::muted(),
+            motion_samples: Vec::new(),
+            paused: false,
+            rumble: None,
+            trace: None,
+            dpad_scroll: DpadScroll::default(),
+            orientation: OrientationKeys::default(),
+            touchpad: Touchpad::default(),
+            triggers: [
+                Trigger::new(JoyKey::ZL, VirtualKey::ZLFull),
+                Trigger::new(JoyKey::ZR, VirtualKey::ZRFull),
+            ],
+            held_keys: HashSet::new(),
+            held_buttons: HashSet::new(),
             settings,
+            layered_gyro: None,
             #[cfg(feature = "vgamepad")]
             // TODO: Conditional virtual gamepad creation
             // Only create if option is enabled
@@ -62,41 +146,183 @@ impl Engine {
         })
     }
 
-    pub fn buttons(&mut self) -> &mut Buttons {
-        &mut self.buttons
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    /// Update a physical button, like `Buttons::key` but traced.
+    pub fn key(&mut self, key: JoyKey, pressed: bool, now: Instant) {
+        if let Some(trace) = &self.trace {
+            trace.button(now, key, pressed);
+        }
+        if self.settings.dpad_mode == DpadMode::Scroll && self.dpad_scroll.key(key, pressed) {
+            return;
+        }
+        self.buttons.key(key, pressed, now);
+    }
+
+    /// Update a touchpad finger, with `position` from 0 to 1 and +y down,
+    /// `None` once lifted.
+    pub fn touch(&mut self, finger: usize, position: Option<Vector2<f64>>, now: Instant) {
+        let was_touching = self.touchpad.touching();
+        let primary = self.touchpad.update(finger, position.is_some());
+        if self.touchpad.touching() != was_touching {
+            self.buttons
+                .key(VirtualKey::Touch, self.touchpad.touching(), now);
+        }
+        if self.paused || self.settings.touchpad_mode != TouchpadMode::Mouse || !primary {
+            return;
+        }
+        if let Some(position) = position {
+            let resolution = self.settings.stick.area.screen_resolution.cast::<f64>();
+            let location = position.mul_element_wise(resolution.expect("can't cast u32 to f64"));
+            self.mouse
+                .mouse_move_absolute_pixel(location.cast::<i32>().expect("can't cast f64 to i32"));
+        }
+    }
+
+    pub fn touchpad_click(&mut self, pressed: bool, now: Instant) {
+        self.buttons.key(VirtualKey::TouchpadClick, pressed, now);
+    }
+
+    /// Run the `ON_CONNECT` bindings, once the engine is set up.
+    pub fn connected(&mut self, now: Instant) -> anyhow::Result<()> {
+        self.tap(VirtualKey::Connect, now)
+    }
+
+    /// Run the `ON_DISCONNECT` bindings, before dropping the engine.
+    pub fn disconnected(&mut self, now: Instant) -> anyhow::Result<()> {
+        self.tap(VirtualKey::Disconnect, now)
+    }
+
+    fn tap(&mut self, key: VirtualKey, now: Instant) -> anyhow::Result<()> {
+        self.buttons.key(key, true, now);
+        self.buttons.key(key, false, now);
+        self.apply_actions(now)
+    }
+
+    /// Whether an area stick is pushed, asking to drive the cursor.
+    pub fn area_stick_active(&self, left: Vector2<f64>, right: Vector2<f64>) -> bool {
+        let deadzone = self.settings.stick.deadzone;
+        (is_area_mode(self.settings.left_stick_mode) && left.magnitude() > deadzone)
+            || (is_area_mode(self.settings.right_stick_mode) && right.magnitude() > deadzone)
+    }
+
+    pub fn set_area_mouse_enabled(&mut self, enabled: bool) {
+        self.area_mouse_enabled = enabled;
     }
 
     pub fn handle_left_stick(&mut self, stick: Vector2<f64>, now: Instant, dt: Duration) {
+        if let Some(trace) = &self.trace {
+            trace.stick(now, "left", stick);
+        }
+        if self.paused {
+            return;
+        }
+        let mouse = if !self.area_mouse_enabled && is_area_mode(self.settings.left_stick_mode) {
+            &mut self.muted_mouse
+        } else {
+            &mut self.mouse
+        };
+        let dt = dt.min(self.settings.stick.max_frame_time);
         self.left_stick.handle(
             stick,
             StickSide::Left,
             &self.settings,
             &mut self.buttons,
-            &mut self.mouse,
+            mouse,
             now,
             dt,
         );
     }
 
     pub fn handle_right_stick(&mut self, stick: Vector2<f64>, now: Instant, dt: Duration) {
+        if let Some(trace) = &self.trace {
+            trace.stick(now, "right", stick);
+        }
+        if self.paused {
+            return;
+        }
+        let mouse = if !self.area_mouse_enabled && is_area_mode(self.settings.right_stick_mode) {
+            &mut self.muted_mouse
+        } else {
+            &mut self.mouse
+        };
+        let dt = dt.min(self.settings.stick.max_frame_time);
         self.right_stick.handle(
             stick,
             StickSide::Right,
             &self.settings,
             &mut self.buttons,
-            &mut self.mouse,
+            mouse,
             now,
             dt,
         );
     }
 
+    /// Update ZL and ZR from normalized analog trigger values.
+    pub fn handle_triggers(&mut self, left: f64, right: f64, now: Instant) {
+        if let Some(trace) = &self.trace {
+            trace.triggers(now, left, right);
+        }
+        let threshold = self.settings.trigger_threshold;
+        let [zl, zr] = &mut self.triggers;
+        zl.update(
+            left,
+            threshold,
+            self.settings.zl_mode,
+            &mut self.buttons,
+            now,
+        );
+        zr.update(
+            right,
+            threshold,
+            self.settings.zr_mode,
+            &mut self.buttons,
+            now,
+        );
+    }
+
     pub fn apply_actions(&mut self, now: Instant) -> anyhow::Result<()> {
         #[cfg(feature = "vgamepad")]
         let mut gamepad_pressed = false;
+        let gyro_enabled = self.gyro.enabled;
+        if !self.paused {
+            let ticks = self.dpad_scroll.tick(now);
+            if ticks.x != 0 {
+                self.mouse.scroll(ticks.x, Axis::Horizontal)?;
+            }
+            if ticks.y != 0 {
+                self.mouse.scroll(ticks.y, Axis::Vertical)?;
+            }
+        }
+        let mut switch_profile = None;
         for action in self.buttons.tick(now) {
-            let verbose = false;
-            if verbose {
-                println!("Action: {}", action);
+            if let Some(trace) = &self.trace {
+                trace.action(now, &action);
+            }
+            // Releases still go through so keys and gyro modes held when
+            // pausing don't get stuck
+            if self.paused
+                && !matches!(
+                    action,
+                    ExtAction::ToggleActive(_)
+                        | ExtAction::KeyPress(_, ClickType::Release)
+                        | ExtAction::MousePress(_, ClickType::Release)
+                        | ExtAction::GyroOn(ClickType::Release)
+                        | ExtAction::GyroOff(ClickType::Release)
+                        | ExtAction::GyroInvertX(ClickType::Release)
+                        | ExtAction::GyroInvertY(ClickType::Release)
+                        | ExtAction::GyroRatchet(ClickType::Release)
+                        | ExtAction::GyroTrackBall(ClickType::Release)
+                        | ExtAction::InvertY(ClickType::Release)
+                )
+            {
+                continue;
+            }
+            // Keys and mouse buttons are logged by the sink when emitted
+            if !matches!(action, ExtAction::KeyPress(..) | ExtAction::MousePress(..)) {
+                self.mouse.action(&action);
             }
             match action {
                 ExtAction::GyroOn(ClickType::Press) | ExtAction::GyroOff(ClickType::Release) => {
@@ -111,31 +337,95 @@ impl Engine {
                 ExtAction::GyroOn(ClickType::Click) | ExtAction::GyroOff(ClickType::Click) => {
                     eprintln!("Warning: event type Click has no effect on gyro on/off");
                 }
-                ExtAction::KeyPress(c, ClickType::Click) => {
-                    self.mouse.enigo().key(c, Direction::Click)?
+                ExtAction::GyroInvertX(ClickType::Press) => self.gyro.invert.0 = true,
+                ExtAction::GyroInvertX(ClickType::Release) => self.gyro.invert.0 = false,
+                ExtAction::GyroInvertX(ClickType::Toggle) => {
+                    self.gyro.invert.0 = !self.gyro.invert.0;
+                }
+                ExtAction::GyroInvertY(ClickType::Press) => self.gyro.invert.1 = true,
+                ExtAction::GyroInvertY(ClickType::Release) => self.gyro.invert.1 = false,
+                ExtAction::GyroInvertY(ClickType::Toggle) => {
+                    self.gyro.invert.1 = !self.gyro.invert.1;
+                }
+                ExtAction::GyroInvertX(ClickType::Click)
+                | ExtAction::GyroInvertY(ClickType::Click) => {
+                    eprintln!("Warning: event type Click has no effect on gyro invert");
                 }
+                // Held relative to the configured inversion, latched with `^`
+                ExtAction::InvertY(ClickType::Press) => {
+                    self.settings.mouse.invert_y =
+                        !self.profiles[self.profile].settings.mouse.invert_y;
+                }
+                ExtAction::InvertY(ClickType::Release) => {
+                    self.settings.mouse.invert_y =
+                        self.profiles[self.profile].settings.mouse.invert_y;
+                }
+                ExtAction::InvertY(ClickType::Toggle) => {
+                    self.settings.mouse.invert_y = !self.settings.mouse.invert_y;
+                }
+                ExtAction::GyroRatchet(ClickType::Press) => self.gyro.ratcheting = true,
+                ExtAction::GyroRatchet(ClickType::Release) => self.gyro.ratcheting = false,
+                ExtAction::GyroRatchet(ClickType::Toggle) => {
+                    self.gyro.ratcheting = !self.gyro.ratcheting;
+                }
+                ExtAction::GyroRatchet(ClickType::Click) => {
+                    eprintln!("Warning: event type Click has no effect on gyro ratchet");
+                }
+                ExtAction::GyroTrackBall(ClickType::Press) => self.gyro.trackball = true,
+                ExtAction::GyroTrackBall(ClickType::Release) => self.gyro.trackball = false,
+                ExtAction::GyroTrackBall(ClickType::Toggle) => {
+                    self.gyro.trackball = !self.gyro.trackball;
+                }
+                ExtAction::GyroTrackBall(ClickType::Click) => {
+                    eprintln!("Warning: event type Click has no effect on gyro trackball");
+                }
+                ExtAction::InvertY(ClickType::Click) => {
+                    eprintln!("Warning: event type Click has no effect on invert y");
+                }
+                ExtAction::ToggleActive(ClickType::Release) => {}
+                ExtAction::ToggleActive(_) => {
+                    self.paused = !self.paused;
+                    if self.paused {
+                        println!("Gyromouse paused");
+                    } else {
+                        println!("Gyromouse resumed");
+                    }
+                }
+                ExtAction::KeyPress(c, ClickType::Click) => self.mouse.key(c, Direction::Click)?,
                 ExtAction::KeyPress(c, ClickType::Press) => {
-                    self.mouse.enigo().key(c, Direction::Press)?
+                    self.held_keys.insert(c);
+                    self.mouse.key(c, Direction::Press)?
                 }
                 ExtAction::KeyPress(c, ClickType::Release) => {
-                    self.mouse.enigo().key(c, Direction::Release)?
+                    self.held_keys.remove(&c);
+                    self.mouse.key(c, Direction::Release)?
                 }
-                ExtAction::KeyPress(_, ClickType::Toggle) => {
-                    // TODO: Implement key press toggle
-                    eprintln!("Warning: key press toggle is not implemented");
+                ExtAction::KeyPress(c, ClickType::Toggle) => {
+                    if self.held_keys.remove(&c) {
+                        self.mouse.key(c, Direction::Release)?
+                    } else {
+                        self.held_keys.insert(c);
+                        self.mouse.key(c, Direction::Press)?
+                    }
                 }
                 ExtAction::MousePress(c, ClickType::Click) => {
-                    self.mouse.enigo().button(c, Direction::Click)?
+                    self.mouse.button(c, Direction::Click)?
                 }
                 ExtAction::MousePress(c, ClickType::Press) => {
-                    self.mouse.enigo().button(c, Direction::Press)?
+                    self.held_buttons.insert(c);
+                    self.mouse.button(c, Direction::Press)?
                 }
                 ExtAction::MousePress(c, ClickType::Release) => {
-                    self.mouse.enigo().button(c, Direction::Release)?
+                    self.held_buttons.remove(&c);
+                    self.mouse.button(c, Direction::Release)?
                 }
-                ExtAction::MousePress(_, ClickType::Toggle) => {
-                    // TODO: Implement mouse click toggle
-                    eprintln!("Warning: mouse click toggle is not implemented");
+                ExtAction::MousePress(c, ClickType::Toggle) => {
+                    if self.held_buttons.remove(&c) {
+                        self.mouse.button(c, Direction::Release)?
+                    } else {
+                        self.held_buttons.insert(c);
+                        self.mouse.button(c, Direction::Press)?
+                    }
                 }
                 #[cfg(feature = "vgamepad")]
                 ExtAction::GamepadKeyPress(key, ClickType::Press) => {
@@ -153,25 +443,50 @@ impl Engine {
                 }
                 #[cfg(feature = "vgamepad")]
                 ExtAction::GamepadKeyPress(_, _) => todo!(),
+                // Applied once the actions of the current bindings are done
+                ExtAction::SwitchProfile(i) => switch_profile = Some(i),
                 ExtAction::None => {}
             }
         }
+        if let Some(i) = switch_profile {
+            self.switch_profile(i);
+        }
         #[cfg(feature = "vgamepad")]
         if let Some(gamepad) = &mut self.gamepad {
             if gamepad_pressed {
                 gamepad.push()?;
             }
         }
+        if self.settings.gyro.toggle_rumble && self.gyro.enabled != gyro_enabled {
+            self.rumble = Some(if self.gyro.enabled {
+                GYRO_ON_RUMBLE
+            } else {
+                GYRO_OFF_RUMBLE
+            });
+        }
         Ok(())
     }
 
+    /// Output the gyro movement accumulated with `GYRO_OUTPUT_RATE`.
+    pub fn flush_gyro(&mut self, now: Instant) {
+        if self.paused {
+            return;
+        }
+        self.gyro.flush(&self.settings.mouse, &mut self.mouse, now);
+    }
+
+    /// Rumble to play on the controller, if any.
+    pub fn take_rumble(&mut self) -> Option<Rumble> {
+        self.rumble.take()
+    }
+
     pub fn apply_motion(
         &mut self,
         rotation_speed: RotationSpeed,
         acceleration: Acceleration,
         now: Instant,
         dt: Duration,
-    ) {
+    ) -> anyhow::Result<()> {
         self.handle_motion_frame(
             &[Motion {
                 rotation_speed,
@@ -182,10 +497,77 @@ impl Engine {
         )
     }
 
-    pub fn handle_motion_frame(&mut self, motions: &[Motion], now: Instant, dt: Duration) {
-        self.gyro
-            .handle_frame(&self.settings, motions, &mut self.mouse, dt);
-        self.handle_motion_stick(now, dt);
+    /// Handle the samples received during `dt`, assumed evenly spaced.
+    pub fn handle_motion_frame(
+        &mut self,
+        motions: &[Motion],
+        now: Instant,
+        dt: Duration,
+    ) -> anyhow::Result<()> {
+        let sample_dt = dt / motions.len().max(1) as u32;
+        let mut samples = std::mem::take(&mut self.motion_samples);
+        samples.clear();
+        samples.extend(motions.iter().map(|&m| (m, sample_dt)));
+        let result = self.handle_timed_motion_frame(&samples, now);
+        self.motion_samples = samples;
+        result
+    }
+
+    /// Handle samples along with the time elapsed since the previous one,
+    /// when the backend knows when each one was measured.
+    pub fn handle_timed_motion_frame(
+        &mut self,
+        samples: &[(Motion, Duration)],
+        now: Instant,
+    ) -> anyhow::Result<()> {
+        if let Some(trace) = &self.trace {
+            for (motion, _) in samples {
+                trace.motion(now, motion);
+            }
+        }
+        if self.paused {
+            return Ok(());
+        }
+        self.update_layered_gyro();
+        let gyro_settings = match &self.layered_gyro {
+            Some((_, gyro_settings)) => gyro_settings,
+            None => &self.settings.gyro,
+        };
+        self.gyro.handle_frame(
+            gyro_settings,
+            &self.settings.mouse,
+            samples,
+            &mut self.buttons,
+            &mut self.mouse,
+            now,
+        )?;
+        self.orientation.update(
+            self.gyro.sensor_fusion.up_vector(),
+            &self.settings.stick.motion,
+            &mut self.buttons,
+            now,
+        );
+        self.handle_motion_stick(now, samples.iter().map(|&(_, dt)| dt).sum());
+        Ok(())
+    }
+
+    /// Recompute the layered gyro settings if the active layers changed since
+    /// the last frame.
+    fn update_layered_gyro(&mut self) {
+        let layers = self.buttons.current_layers();
+        if matches!(&self.layered_gyro, Some((old, _)) if old == layers) {
+            return;
+        }
+        let mut gyro_settings = self.settings.gyro;
+        for setting in self.buttons.layer_gyro_settings() {
+            gyro_settings.apply(*setting);
+        }
+        let old = match &self.layered_gyro {
+            Some((_, old)) => old,
+            None => &self.settings.gyro,
+        };
+        self.gyro.configure(old, &gyro_settings);
+        self.layered_gyro = Some((layers.to_vec(), gyro_settings));
     }
 
     fn handle_motion_stick(&mut self, now: Instant, dt: Duration) {
@@ -202,62 +584,1341 @@ impl Engine {
     pub fn set_calibration(&mut self, calibration: Calibration) {
         self.gyro.calibration = calibration;
     }
+
+    pub fn session_state(&self) -> SessionState {
+        SessionState {
+            gyro_enabled: self.gyro.enabled,
+            layers: self.buttons.current_layers().to_vec(),
+        }
+    }
+
+    pub fn restore_session_state(&mut self, state: SessionState) {
+        self.gyro.enabled = state.gyro_enabled;
+        self.buttons.set_current_layers(state.layers);
+    }
+
+    /// Swap in a new configuration without rebuilding the engine.
+    ///
+    /// The active profile is kept if it still exists.
+    pub fn reload(&mut self, mut settings: Settings, buttons: Buttons) {
+        let profiles = take_profiles(&mut settings, &buttons);
+        let name = &self.profiles[self.profile].name;
+        self.profile = profiles
+            .iter()
+            .position(|p| p.name.eq_ignore_ascii_case(name))
+            .unwrap_or(0);
+        self.profiles = profiles;
+        let profile = self.profiles[self.profile].clone();
+        self.load(profile.settings, profile.buttons);
+    }
+
+    fn switch_profile(&mut self, index: usize) {
+        let profile = self.profiles[index].clone();
+        println!("Switching to profile {}", profile.name);
+        self.profile = index;
+        self.load(profile.settings, profile.buttons);
+        // The old bindings may have been the ones disabling the active layers
+        self.buttons.set_current_layers(vec![0]);
+    }
+
+    /// Replace the active settings and bindings.
+    ///
+    /// The gyro calibration, sensor fusion and session state are kept. Sticks
+    /// are only rebuilt if their mode or smoothing changed, so they don't lose
+    /// their state.
+    fn load(&mut self, settings: Settings, buttons: Buttons) {
+        let state = self.session_state();
+        let old = &self.settings;
+        let ring_changed = settings.left_ring_mode != old.left_ring_mode;
+        let changed = |mode, old_mode| {
+            ring_changed
+                || mode != old_mode
+                || settings.stick_smooth_time(mode) != old.stick_smooth_time(old_mode)
+                || (mode == StickMode::MouseArea
+                    && settings.stick.area.rel_smooth_time != old.stick.area.rel_smooth_time)
+                || (mode == StickMode::MouseRing
+                    && settings.stick.area.ring_smooth_time != old.stick.area.ring_smooth_time)
+        };
+        if changed(settings.left_stick_mode, old.left_stick_mode) {
+            self.left_stick = settings.new_left_stick();
+        }
+        if changed(settings.right_stick_mode, old.right_stick_mode) {
+            self.right_stick = settings.new_right_stick();
+        }
+        if changed(settings.stick.motion.mode, old.stick.motion.mode) {
+            self.motion_stick = MotionStick::new(&settings);
+        }
+        let old_gyro = match &self.layered_gyro {
+            Some((_, layered)) => layered,
+            None => &old.gyro,
+        };
+        self.gyro.configure(old_gyro, &settings.gyro);
+        self.settings = settings;
+        self.layered_gyro = None;
+        self.buttons = buttons;
+        self.restore_session_state(state);
+        // The new bindings don't know they are down, so would never release
+        // them
+        self.release_held();
+    }
+
+    /// Release the keys and mouse buttons held by a binding.
+    ///
+    /// Keys get stuck when `buttons` is replaced while a binding holds them,
+    /// by a reload or a profile switch, so `load` calls this. `RESET_MAPPINGS`
+    /// and `Buttons::reset` don't need to: they only run while parsing, before
+    /// any key can be down.
+    fn release_held(&mut self) {
+        for key in std::mem::take(&mut self.held_keys) {
+            if let Err(e) = self.mouse.key(key, Direction::Release) {
+                eprintln!("Error releasing {:?}: {}", key, e);
+            }
+        }
+        for button in std::mem::take(&mut self.held_buttons) {
+            if let Err(e) = self.mouse.button(button, Direction::Release) {
+                eprintln!("Error releasing {:?}: {}", button, e);
+            }
+        }
+    }
 }
 
+impl Drop for Engine {
+    /// Don't leave the OS with a key held down when exiting or when the
+    /// controller disconnects.
+    fn drop(&mut self) {
+        self.release_held();
+    }
+}
+
+/// Interval between scroll ticks while a dpad button is held with
+/// `DPAD_MODE = SCROLL`.
+const DPAD_SCROLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Split the `[name]` profiles out of `settings`, with the unnamed part first.
+fn take_profiles(settings: &mut Settings, buttons: &Buttons) -> Vec<Profile> {
+    let named = std::mem::take(&mut settings.profiles);
+    std::iter::once(Profile {
+        name: DEFAULT_PROFILE.to_owned(),
+        settings: settings.clone(),
+        buttons: buttons.clone(),
+    })
+    .chain(named)
+    .collect()
+}
+
+/// Aggregates the dpad buttons into a scroll direction.
+#[derive(Debug, Default)]
+struct DpadScroll {
+    up: bool,
+    down: bool,
+    left: bool,
+    right: bool,
+    /// When to scroll again, `None` if nothing is held.
+    next: Option<Instant>,
+}
+
+impl DpadScroll {
+    /// Returns false if `key` is not a dpad button.
+    fn key(&mut self, key: JoyKey, pressed: bool) -> bool {
+        match key {
+            JoyKey::Up => self.up = pressed,
+            JoyKey::Down => self.down = pressed,
+            JoyKey::Left => self.left = pressed,
+            JoyKey::Right => self.right = pressed,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Scroll ticks to emit. The first press scrolls right away, then it
+    /// repeats every `DPAD_SCROLL_INTERVAL`.
+    fn tick(&mut self, now: Instant) -> Vector2<i32> {
+        let axis = |pos: bool, neg: bool| pos as i32 - neg as i32;
+        // Up scrolls up, which is negative for enigo
+        let direction = vec2(axis(self.right, self.left), axis(self.down, self.up));
+        if direction == Vector2::zero() {
+            self.next = None;
+            return direction;
+        }
+        match self.next {
+            Some(next) if now < next => Vector2::zero(),
+            _ => {
+                self.next = Some(now + DPAD_SCROLL_INTERVAL);
+                direction
+            }
+        }
+    }
+}
+
+/// Margin past the orientation thresholds needed to release the keys, so
+/// they don't flicker around the threshold.
+const ORIENTATION_HYSTERESIS: Deg<f64> = Deg(5.);
+
+/// Fingers on the touchpad.
+#[derive(Debug, Default)]
+struct Touchpad {
+    /// In touch order, the first one drives the cursor.
+    fingers: Vec<usize>,
+}
+
+impl Touchpad {
+    /// Returns whether `finger` drives the cursor.
+    fn update(&mut self, finger: usize, down: bool) -> bool {
+        if !down {
+            self.fingers.retain(|&f| f != finger);
+        } else if !self.fingers.contains(&finger) {
+            self.fingers.push(finger);
+        }
+        self.fingers.first() == Some(&finger)
+    }
+
+    fn touching(&self) -> bool {
+        !self.fingers.is_empty()
+    }
+}
+
+/// Presses the orientation virtual keys from the sensor fusion up vector.
+#[derive(Debug, Default)]
+struct OrientationKeys {
+    lean_left: bool,
+    lean_right: bool,
+    face_up: bool,
+    face_down: bool,
+}
+
+impl OrientationKeys {
+    fn update(
+        &mut self,
+        up_vector: Vector3<f64>,
+        settings: &MotionStickSettings,
+        buttons: &mut Buttons,
+        now: Instant,
+    ) {
+        // +y goes out of the face of the controller, and the right side
+        // goes up when leaning left.
+        let up_vector = up_vector.normalize();
+        let roll = Deg::from(Rad(up_vector.x.clamp(-1., 1.).asin()));
+        let from_face_up = Deg::from(Rad(up_vector.y.clamp(-1., 1.).acos()));
+        let from_face_down = Deg(180.) - from_face_up;
+
+        let keys = [
+            (
+                &mut self.lean_left,
+                roll - settings.lean_threshold,
+                VirtualKey::LeanLeft,
+            ),
+            (
+                &mut self.lean_right,
+                -roll - settings.lean_threshold,
+                VirtualKey::LeanRight,
+            ),
+            (
+                &mut self.face_up,
+                settings.face_threshold - from_face_up,
+                VirtualKey::FaceUp,
+            ),
+            (
+                &mut self.face_down,
+                settings.face_threshold - from_face_down,
+                VirtualKey::FaceDown,
+            ),
+        ];
+        for (pressed, margin, key) in keys {
+            // NaN, from a zero up vector before the first frame, releases
+            *pressed = if *pressed {
+                margin > -ORIENTATION_HYSTERESIS
+            } else {
+                margin > Deg(0.)
+            };
+            buttons.key(key, *pressed, now);
+        }
+    }
+}
+
+/// Convert fractional scroll ticks to whole ones, keeping the remainder for
+/// the next frames.
+fn accumulate_scroll(accumulator: &mut Vector2<f64>, delta: Vector2<f64>) -> Vector2<i32> {
+    let sum = *accumulator + delta;
+    let rounded = vec2(sum.x.round(), sum.y.round());
+    *accumulator = sum - rounded;
+    rounded.cast().expect("scroll delta out of range")
+}
+
+fn new_sensor_fusion(fusion: GyroFusion) -> Box<dyn SensorFusion> {
+    match fusion {
+        GyroFusion::Simple => Box::new(SimpleFusion::new()),
+        GyroFusion::Adaptive => Box::new(AdaptativeFusion::new()),
+    }
+}
+
+fn new_space_mapper(space: GyroSpace) -> Box<dyn SpaceMapper> {
+    match space {
+        GyroSpace::Local => Box::new(LocalSpace::default()),
+        GyroSpace::WorldTurn => Box::new(WorldSpace::default()),
+        GyroSpace::WorldLean => Box::new(WorldLeanSpace),
+        GyroSpace::PlayerTurn => Box::new(PlayerSpace::default()),
+        GyroSpace::PlayerLean => Box::new(PlayerLeanSpace::default()),
+    }
+}
+
+/// Stick modes setting the cursor position instead of moving it.
+fn is_area_mode(mode: StickMode) -> bool {
+    matches!(mode, StickMode::MouseArea | StickMode::MouseRing)
+}
+
+/// Rotation speed in degrees per second below which `GYRO_TRACKBALL` keeps
+/// the previous movement going.
+const TRACKBALL_REST_SPEED: f64 = 1.;
+
 pub struct Gyro {
     enabled: bool,
+    /// While set, the orientation keeps being tracked but nothing is output,
+    /// like lifting a mouse.
+    ratcheting: bool,
+    /// While set, the movement keeps going and slowly decays once the
+    /// controller stops rotating.
+    trackball: bool,
+    /// Output speed in degrees per second, kept going with `trackball`.
+    trackball_speed: Vector2<f64>,
+    /// Set by `GYRO_INV_X` and `GYRO_INV_Y`, inverting the axes on top of
+    /// `GYRO_AXIS_X` and `GYRO_AXIS_Y`.
+    invert: (bool, bool),
     calibration: Calibration,
     sensor_fusion: Box<dyn SensorFusion>,
     space_mapper: Box<dyn SpaceMapper>,
     gyromouse: GyroMouse,
+    scroll_accumulator: Vector2<f64>,
+    /// Movement waiting for the next output with `GYRO_OUTPUT_RATE`.
+    pending: MouseMovement,
+    /// `GYRO_OUTPUT_RATE` of the last frame, which may come from a layer.
+    output_rate: f64,
+    next_output: Option<Instant>,
 }
 
 impl Gyro {
     pub fn new(settings: &Settings, calibration: Calibration) -> Gyro {
         Gyro {
-            enabled: true,
+            enabled: settings.gyro.default_enabled,
+            ratcheting: false,
+            trackball: false,
+            trackball_speed: Vector2::zero(),
+            invert: (false, false),
             calibration,
-            sensor_fusion: Box::new(SimpleFusion::new()),
-            space_mapper: match settings.gyro.space {
-                GyroSpace::Local => Box::new(LocalSpace::default()),
-                GyroSpace::WorldTurn => Box::new(WorldSpace::default()),
-                GyroSpace::WorldLean => todo!("World Lean is unimplemented for now"),
-                GyroSpace::PlayerTurn => Box::new(PlayerSpace::default()),
-                GyroSpace::PlayerLean => todo!("Player Lean is unimplemented for now"),
-            },
+            sensor_fusion: new_sensor_fusion(settings.gyro.fusion),
+            space_mapper: new_space_mapper(settings.gyro.space),
             gyromouse: GyroMouse::default(),
+            scroll_accumulator: Vector2::zero(),
+            pending: MouseMovement::zero(),
+            output_rate: 0.,
+            next_output: None,
+        }
+    }
+
+    /// Rebuild the space mapper and sensor fusion if they differ between `old`
+    /// and `new`.
+    fn configure(&mut self, old: &GyroSettings, new: &GyroSettings) {
+        if new.space != old.space {
+            self.space_mapper = new_space_mapper(new.space);
+        }
+        if new.fusion != old.fusion {
+            self.sensor_fusion = new_sensor_fusion(new.fusion);
+        }
+    }
+
+    /// Send the movement accumulated with `GYRO_OUTPUT_RATE`, once per output
+    /// period. Called by the backend loop, independently of the sensor rate.
+    pub fn flush(&mut self, settings: &MouseSettings, mouse: &mut Mouse, now: Instant) {
+        if self.output_rate > 0. {
+            if matches!(self.next_output, Some(next) if now < next) {
+                return;
+            }
+            self.next_output = Some(now + Duration::from_secs_f64(1. / self.output_rate));
+        }
+        let pending = std::mem::replace(&mut self.pending, MouseMovement::zero());
+        if pending.to_vec_deg() != Vector2::zero() {
+            mouse.mouse_move_relative(settings, pending);
         }
     }
 
     pub fn handle_frame(
         &mut self,
-        settings: &Settings,
-        motions: &[Motion],
+        settings: &GyroSettings,
+        mouse_settings: &MouseSettings,
+        samples: &[(Motion, Duration)],
+        buttons: &mut Buttons,
         mouse: &mut Mouse,
-        dt: Duration,
-    ) {
+        now: Instant,
+    ) -> anyhow::Result<()> {
         const SMOOTH_RATE: bool = true;
+        let scroll = settings.output == GyroOutput::Scroll;
+        self.output_rate = settings.output_rate;
+        let output = self.enabled && !self.ratcheting;
         let mut delta_position = MouseMovement::zero();
-        let dt = dt / motions.len() as u32;
-        for (i, frame) in motions.iter().cloned().enumerate() {
+        let mut max_speed: f64 = 0.;
+        let frame_dt: Duration = samples.iter().map(|&(_, dt)| dt).sum();
+        let natural_sens = settings.natural_sens(mouse_settings);
+        let sign = vec2(
+            if self.invert.0 { -1. } else { 1. },
+            if self.invert.1 { -1. } else { 1. },
+        );
+        for (i, &(frame, dt)) in samples.iter().enumerate() {
             let frame = self.calibration.calibrate(frame);
+            max_speed = max_speed.max(frame.rotation_speed.as_vec().magnitude());
             let delta = space_mapper::map_input(
                 &frame,
                 dt,
                 self.sensor_fusion.deref_mut(),
                 self.space_mapper.deref_mut(),
             );
-            let offset = self.gyromouse.process(&settings.gyro, delta, dt);
+            let offset = MouseMovement::from_vec_deg(
+                self.gyromouse
+                    .process(settings, delta, dt)
+                    .to_vec_deg()
+                    .mul_element_wise(sign)
+                    * natural_sens,
+            );
             delta_position += offset;
-            if self.enabled && !SMOOTH_RATE {
+            if output && !SMOOTH_RATE && !scroll {
                 if i > 0 {
                     std::thread::sleep(dt);
                 }
-                mouse.mouse_move_relative(&settings.mouse, offset);
+                mouse.mouse_move_relative(mouse_settings, offset);
+            }
+        }
+        if !self.trackball || self.ratcheting {
+            self.trackball_speed = Vector2::zero();
+        } else if max_speed > TRACKBALL_REST_SPEED && !frame_dt.is_zero() {
+            self.trackball_speed = delta_position.to_vec_deg() / frame_dt.as_secs_f64();
+        } else {
+            // Slow real movement still goes through on top of the coast
+            self.trackball_speed *= (-settings.trackball_decay * frame_dt.as_secs_f64()).exp();
+            delta_position +=
+                MouseMovement::from_vec_deg(self.trackball_speed * frame_dt.as_secs_f64());
+        }
+        if self.ratcheting {
+            // Drop the motion done while ratcheting so it doesn't leak
+            // through the smoothing once released.
+            self.gyromouse.reset();
+            self.scroll_accumulator = Vector2::zero();
+            self.pending = MouseMovement::zero();
+        } else if output && scroll {
+            // Tilting up scrolls up, which is negative for enigo
+            let delta = delta_position.to_vec_deg().mul_element_wise(vec2(1., -1.));
+            let ticks =
+                accumulate_scroll(&mut self.scroll_accumulator, delta * settings.scroll_sens);
+            if ticks.x != 0 {
+                mouse.scroll(ticks.x, Axis::Horizontal)?;
+            }
+            if ticks.y != 0 {
+                mouse.scroll(ticks.y, Axis::Vertical)?;
+            }
+        } else if output && SMOOTH_RATE {
+            if self.output_rate > 0. {
+                self.pending += delta_position;
+            } else {
+                mouse.mouse_move_relative(mouse_settings, delta_position);
+            }
+        }
+        buttons.key(
+            VirtualKey::GyroMove,
+            self.enabled && max_speed > settings.motion_threshold,
+            now,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        calibration::BetterCalibration,
+        mapping::Action,
+        mouse::test::{press_recording_mouse, recording_mouse},
+    };
+
+    #[test]
+    fn large_first_stick_dt_is_bounded() {
+        let push = |settings: Settings, dt| {
+            let (mouse, moves) = recording_mouse();
+            let mut engine =
+                Engine::new(settings, Buttons::new(), Calibration::empty(), mouse).unwrap();
+            engine.handle_right_stick(vec2(1., 0.), Instant::now(), dt);
+            let x: i32 = moves.borrow().iter().map(|m| m.x).sum();
+            x
+        };
+        let startup = Duration::from_secs(2);
+
+        let bounded = push(Settings::default(), startup);
+        assert!(bounded > 0);
+        assert_eq!(
+            bounded,
+            push(Settings::default(), Duration::from_millis(50))
+        );
+
+        let mut settings = Settings::default();
+        settings.stick.max_frame_time = Duration::from_millis(10);
+        assert_eq!(
+            push(settings, startup),
+            push(Settings::default(), Duration::from_millis(10))
+        );
+    }
+
+    fn new_engine(buttons: Buttons) -> Engine {
+        Engine::new(
+            Settings::default(),
+            buttons,
+            Calibration::empty(),
+            Mouse::dry_run(),
+        )
+        .unwrap()
+    }
+
+    /// Engine with the gyro in local space, so that `turn` moves along x,
+    /// and its recorded mouse moves.
+    fn gyro_engine(
+        buttons: Buttons,
+        mut settings: Settings,
+    ) -> (Engine, Rc<RefCell<Vec<Vector2<i32>>>>) {
+        settings.gyro.space = GyroSpace::Local;
+        let (mouse, moves) = recording_mouse();
+        let engine = Engine::new(settings, buttons, Calibration::empty(), mouse).unwrap();
+        (engine, moves)
+    }
+
+    /// Yaw the controller at `speed` °/s during `dt`.
+    fn turn_at(engine: &mut Engine, speed: f64, now: Instant, dt: Duration) {
+        engine
+            .apply_motion(
+                RotationSpeed {
+                    x: 0.,
+                    y: speed,
+                    z: 0.,
+                },
+                Acceleration {
+                    x: 0.,
+                    y: 1.,
+                    z: 0.,
+                },
+                now,
+                dt,
+            )
+            .unwrap();
+    }
+
+    /// Yaw the controller at 100°/s during `dt`.
+    fn turn(engine: &mut Engine, now: Instant, dt: Duration) {
+        turn_at(engine, 100., now, dt);
+    }
+
+    #[test]
+    fn dpad_scroll() {
+        let mut dpad = DpadScroll::default();
+        let t0 = Instant::now();
+        assert!(!dpad.key(JoyKey::S, true));
+
+        assert!(dpad.key(JoyKey::Up, true));
+        dpad.key(JoyKey::Right, true);
+        assert_eq!(dpad.tick(t0), vec2(1, -1));
+        assert_eq!(dpad.tick(t0 + DPAD_SCROLL_INTERVAL / 2), vec2(0, 0));
+
+        // Opposite directions cancel out
+        dpad.key(JoyKey::Left, true);
+        assert_eq!(dpad.tick(t0 + DPAD_SCROLL_INTERVAL), vec2(0, -1));
+
+        dpad.key(JoyKey::Up, false);
+        dpad.key(JoyKey::Right, false);
+        dpad.key(JoyKey::Left, false);
+        assert_eq!(dpad.tick(t0 + DPAD_SCROLL_INTERVAL), vec2(0, 0));
+        dpad.key(JoyKey::Down, true);
+        assert_eq!(dpad.tick(t0 + DPAD_SCROLL_INTERVAL), vec2(0, 1));
+    }
+
+    #[test]
+    fn dpad_mode_bypasses_bindings() {
+        let mut buttons = Buttons::new();
+        buttons
+            .get(JoyKey::Up, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::GyroOff(ClickType::Press)));
+        let now = Instant::now();
+
+        let mut engine = new_engine(buttons.clone());
+        engine.key(JoyKey::Up, true, now);
+        engine.apply_actions(now).unwrap();
+        assert!(!engine.gyro.enabled);
+
+        let mut engine = new_engine(buttons);
+        engine.settings.dpad_mode = DpadMode::Scroll;
+        engine.key(JoyKey::Up, true, now);
+        engine.apply_actions(now).unwrap();
+        assert!(engine.gyro.enabled);
+    }
+
+    #[test]
+    fn gyro_default() {
+        let mut settings = Settings::default();
+        settings.gyro.default_enabled = false;
+        let engine = Engine::new(
+            settings,
+            Buttons::new(),
+            Calibration::empty(),
+            Mouse::dry_run(),
+        )
+        .unwrap();
+        assert!(!engine.gyro.enabled);
+    }
+
+    #[test]
+    fn touchpad_fingers() {
+        let mut touchpad = Touchpad::default();
+        assert!(touchpad.update(0, true));
+        // The second finger doesn't take over the cursor
+        assert!(!touchpad.update(1, true));
+        assert!(touchpad.update(0, true));
+        assert!(!touchpad.update(1, true));
+
+        assert!(!touchpad.update(0, false));
+        assert!(touchpad.update(1, true));
+        assert!(touchpad.touching());
+        touchpad.update(1, false);
+        assert!(!touchpad.touching());
+    }
+
+    #[test]
+    fn touch_key() {
+        let mut buttons = Buttons::new();
+        buttons
+            .get(VirtualKey::Touch, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::GyroOff(ClickType::Toggle)));
+        let mut engine = new_engine(buttons);
+        let now = Instant::now();
+
+        engine.touch(0, Some(vec2(0.2, 0.2)), now);
+        engine.touch(1, Some(vec2(0.8, 0.8)), now);
+        engine.touch(0, None, now);
+        engine.apply_actions(now).unwrap();
+        // Pressed once for both fingers
+        assert!(!engine.gyro.enabled);
+
+        engine.touch(1, None, now);
+        engine.touch(0, Some(vec2(0.5, 0.5)), now);
+        engine.apply_actions(now).unwrap();
+        assert!(engine.gyro.enabled);
+    }
+
+    #[test]
+    fn orientation_keys() {
+        use cgmath::vec3;
+
+        let settings = MotionStickSettings::default();
+        let mut buttons = Buttons::new();
+        buttons
+            .get(VirtualKey::LeanLeft, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::GyroOff(ClickType::Press)));
+        let mut keys = OrientationKeys::default();
+        let now = Instant::now();
+        let mut pressed = |up: Vector3<f64>| {
+            keys.update(up, &settings, &mut buttons, now);
+            [
+                keys.lean_left,
+                keys.lean_right,
+                keys.face_up,
+                keys.face_down,
+            ]
+        };
+        let roll = |deg: f64| {
+            let rad = Rad::from(Deg(deg)).0;
+            vec3(rad.sin(), rad.cos(), 0.)
+        };
+
+        assert_eq!(pressed(roll(0.)), [false, false, true, false]);
+        assert_eq!(pressed(roll(10.)), [false, false, true, false]);
+        assert_eq!(pressed(roll(17.)), [true, false, true, false]);
+        // Kept until 5° past the threshold
+        assert_eq!(pressed(roll(12.)), [true, false, true, false]);
+        assert_eq!(pressed(roll(8.)), [false, false, true, false]);
+        assert_eq!(pressed(roll(-30.)), [false, true, false, false]);
+        assert_eq!(pressed(vec3(0., -2., 0.1)), [false, false, false, true]);
+        // Upright, facing the player
+        assert_eq!(pressed(vec3(0., 0., 1.)), [false, false, false, false]);
+        assert_eq!(pressed(Vector3::zero()), [false, false, false, false]);
+
+        // Pressed once, when leaning left past the threshold
+        assert!(matches!(
+            buttons.tick(now).collect::<Vec<_>>()[..],
+            [ExtAction::GyroOff(ClickType::Press)]
+        ));
+    }
+
+    #[test]
+    fn scroll_accumulation() {
+        let mut acc = Vector2::zero();
+        assert_eq!(accumulate_scroll(&mut acc, vec2(0.3, -0.3)), vec2(0, 0));
+        assert_eq!(accumulate_scroll(&mut acc, vec2(0.3, -0.3)), vec2(1, -1));
+        assert!((acc - vec2(-0.4, 0.4)).magnitude() < 1e-9);
+        assert_eq!(accumulate_scroll(&mut acc, vec2(0.3, -0.3)), vec2(0, 0));
+        assert_eq!(accumulate_scroll(&mut acc, vec2(0.3, -0.3)), vec2(0, 0));
+        assert_eq!(accumulate_scroll(&mut acc, vec2(0.4, -0.4)), vec2(1, -1));
+    }
+
+    #[test]
+    fn session_state_round_trip() {
+        let new_engine = || new_engine(Buttons::new());
+
+        let mut engine = new_engine();
+        engine.gyro.enabled = false;
+        engine.buttons.set_current_layers(vec![0, 2]);
+
+        let mut reconnected = new_engine();
+        reconnected.restore_session_state(engine.session_state());
+        assert!(!reconnected.gyro.enabled);
+        assert_eq!(reconnected.buttons.current_layers(), &[0, 2]);
+    }
+
+    #[test]
+    fn switch_profile() {
+        let mut settings = Settings::default();
+        let mut buttons = Buttons::new();
+        let errors = crate::config::parse_file(
+            "HOME = SWITCH_PROFILE driving\n[driving]\nGYRO_SENS = 2",
+            &mut settings,
+            &mut buttons,
+        );
+        assert!(errors.is_empty());
+        let mut engine = Engine::new(
+            settings.clone(),
+            buttons.clone(),
+            Calibration::empty(),
+            Mouse::dry_run(),
+        )
+        .unwrap();
+        assert_eq!(engine.profile, 0);
+        let now = Instant::now();
+        engine.key(JoyKey::Home, true, now);
+        engine.apply_actions(now).unwrap();
+        assert_eq!(engine.profile, 1);
+        assert_eq!(engine.settings.gyro.sens.x, 2.);
+
+        engine.reload(settings, buttons);
+        assert_eq!(engine.profile, 1);
+        assert_eq!(engine.settings.gyro.sens.x, 2.);
+        engine.reload(Settings::default(), Buttons::new());
+        assert_eq!(engine.profile, 0);
+    }
+
+    #[test]
+    fn reload_keeps_state() {
+        let mut engine = new_engine(Buttons::new());
+        engine.gyro.enabled = false;
+        engine.buttons.set_current_layers(vec![0, 2]);
+        let mut calibrator = BetterCalibration::default();
+        let now = Instant::now();
+        let motion = Motion {
+            rotation_speed: RotationSpeed {
+                x: 1.,
+                y: 2.,
+                z: 3.,
+            },
+            acceleration: Acceleration {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+        };
+        for _ in 0..3 {
+            calibrator.push(motion, now, Duration::ZERO);
+        }
+        engine.set_calibration(calibrator.finish());
+        let calibrated = engine.gyro.calibration.calibrate(motion).rotation_speed;
+        assert_ne!(calibrated, motion.rotation_speed);
+
+        let settings = Settings {
+            trigger_threshold: 0.2,
+            right_stick_mode: StickMode::Flick,
+            ..Default::default()
+        };
+        let mut buttons = Buttons::new();
+        buttons.hold_delay = Duration::from_millis(300);
+        engine.reload(settings, buttons);
+
+        assert!(!engine.gyro.enabled);
+        assert_eq!(engine.buttons.current_layers(), &[0, 2]);
+        assert_eq!(
+            engine.gyro.calibration.calibrate(motion).rotation_speed,
+            calibrated
+        );
+        assert_eq!(engine.settings.trigger_threshold, 0.2);
+        assert_eq!(engine.buttons.hold_delay, Duration::from_millis(300));
+    }
+
+    #[test]
+    fn trigger_hysteresis() {
+        let mut engine = new_engine(Buttons::new());
+        let now = Instant::now();
+        let mut pull = |left, right| {
+            engine.handle_triggers(left, right, now);
+            [engine.triggers[0].pulled, engine.triggers[1].pulled]
+        };
+
+        assert_eq!(pull(0.49, 0.), [false, false]);
+        assert_eq!(pull(0.51, 0.6), [true, true]);
+        assert_eq!(pull(0.47, 0.49), [true, true]);
+        assert_eq!(pull(0.51, 0.44), [true, false]);
+        assert_eq!(pull(0.49, 0.49), [true, false]);
+        assert_eq!(pull(0., 0.), [false, false]);
+    }
+
+    #[test]
+    fn layer_gyro_settings_stack() {
+        use crate::config::types::{GyroSetting, InvertMode};
+
+        let mut buttons = Buttons::new();
+        buttons.add_layer_gyro_setting(1, GyroSetting::Sensitivity(4., None));
+        buttons.add_layer_gyro_setting(2, GyroSetting::InvertX(InvertMode::Inverted));
+        buttons.add_layer_gyro_setting(3, GyroSetting::Sensitivity(1., None));
+        let (mut engine, moves) = gyro_engine(buttons, Settings::default());
+        let now = Instant::now();
+        let turn_x = |engine: &mut Engine, layers: Vec<u8>| {
+            engine.buttons.set_current_layers(layers);
+            turn(engine, now, Duration::from_millis(10));
+            moves.borrow_mut().pop().unwrap().x
+        };
+
+        let x = turn_x(&mut engine, vec![0]);
+        let fast = turn_x(&mut engine, vec![0, 1]);
+        assert!(fast.abs() > x.abs());
+        // Both layers apply
+        assert_eq!(turn_x(&mut engine, vec![0, 1, 2]), -fast);
+        // The last activated layer wins
+        assert_eq!(turn_x(&mut engine, vec![0, 1, 3]), x);
+        assert_eq!(turn_x(&mut engine, vec![0, 3, 1]), fast);
+        assert_eq!(turn_x(&mut engine, vec![0]), x);
+    }
+
+    #[test]
+    fn gyro_invert() {
+        let mut buttons = Buttons::new();
+        let layer = buttons.get(JoyKey::S, 0);
+        layer
+            .on_down
+            .push(Action::Ext(ExtAction::GyroInvertX(ClickType::Press)));
+        layer
+            .on_up
+            .push(Action::Ext(ExtAction::GyroInvertX(ClickType::Release)));
+        buttons
+            .get(JoyKey::E, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::GyroInvertY(ClickType::Toggle)));
+        let (mut engine, moves) = gyro_engine(buttons, Settings::default());
+        let now = Instant::now();
+        let turn_x = |engine: &mut Engine| {
+            turn(engine, now, Duration::from_millis(10));
+            moves.borrow_mut().pop().unwrap().x
+        };
+
+        let x = turn_x(&mut engine);
+        engine.key(JoyKey::S, true, now);
+        engine.apply_actions(now).unwrap();
+        assert_eq!(engine.gyro.invert, (true, false));
+        assert_eq!(turn_x(&mut engine), -x);
+        engine.key(JoyKey::S, false, now);
+        engine.apply_actions(now).unwrap();
+        assert_eq!(engine.gyro.invert, (false, false));
+
+        engine.key(JoyKey::E, true, now);
+        engine.key(JoyKey::E, false, now);
+        engine.apply_actions(now).unwrap();
+        assert_eq!(engine.gyro.invert, (false, true));
+    }
+
+    #[test]
+    fn invert_y_toggle() {
+        let mut buttons = Buttons::new();
+        let layer = buttons.get(JoyKey::S, 0);
+        layer
+            .on_down
+            .push(Action::Ext(ExtAction::InvertY(ClickType::Press)));
+        layer
+            .on_up
+            .push(Action::Ext(ExtAction::InvertY(ClickType::Release)));
+        buttons
+            .get(JoyKey::E, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::InvertY(ClickType::Toggle)));
+        let mut engine = new_engine(buttons);
+        let now = Instant::now();
+
+        // Hold
+        engine.key(JoyKey::S, true, now);
+        engine.apply_actions(now).unwrap();
+        assert!(engine.settings.mouse.invert_y);
+        engine.key(JoyKey::S, false, now);
+        engine.apply_actions(now).unwrap();
+        assert!(!engine.settings.mouse.invert_y);
+
+        // Latch
+        engine.key(JoyKey::E, true, now);
+        engine.key(JoyKey::E, false, now);
+        engine.apply_actions(now).unwrap();
+        assert!(engine.settings.mouse.invert_y);
+        engine.key(JoyKey::E, true, now);
+        engine.key(JoyKey::E, false, now);
+        engine.apply_actions(now).unwrap();
+        assert!(!engine.settings.mouse.invert_y);
+    }
+
+    #[test]
+    fn invert_y_toggle_config() {
+        let mut settings = Settings::default();
+        let mut buttons = Buttons::new();
+        let errors = crate::config::parse_file(
+            "S = INVERT_Y_TOGGLE\nE = ^INVERT_Y_TOGGLE",
+            &mut settings,
+            &mut buttons,
+        );
+        assert!(errors.is_empty());
+        let mut engine =
+            Engine::new(settings, buttons, Calibration::empty(), Mouse::dry_run()).unwrap();
+        engine.profiles[0].settings.mouse.invert_y = true;
+        engine.settings.mouse.invert_y = true;
+        let now = Instant::now();
+
+        // Holding flips the configured inversion
+        engine.key(JoyKey::S, true, now);
+        engine.apply_actions(now).unwrap();
+        assert!(!engine.settings.mouse.invert_y);
+        engine.key(JoyKey::S, false, now);
+        engine.apply_actions(now).unwrap();
+        assert!(engine.settings.mouse.invert_y);
+
+        engine.key(JoyKey::E, true, now);
+        engine.key(JoyKey::E, false, now);
+        engine.apply_actions(now).unwrap();
+        assert!(!engine.settings.mouse.invert_y);
+    }
+
+    #[test]
+    fn toggle_active() {
+        let mut buttons = Buttons::new();
+        let layer = buttons.get(JoyKey::Home, 0);
+        layer
+            .on_down
+            .push(Action::Ext(ExtAction::ToggleActive(ClickType::Press)));
+        layer
+            .on_up
+            .push(Action::Ext(ExtAction::ToggleActive(ClickType::Release)));
+        buttons
+            .get(JoyKey::S, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::GyroOff(ClickType::Toggle)));
+        let mut engine = new_engine(buttons);
+        let now = Instant::now();
+
+        engine.key(JoyKey::Home, true, now);
+        engine.key(JoyKey::Home, false, now);
+        engine.apply_actions(now).unwrap();
+        assert!(engine.paused);
+
+        engine.key(JoyKey::S, true, now);
+        engine.key(JoyKey::S, false, now);
+        engine.apply_actions(now).unwrap();
+        assert!(engine.gyro.enabled);
+
+        engine.key(JoyKey::Home, true, now);
+        engine.key(JoyKey::Home, false, now);
+        engine.apply_actions(now).unwrap();
+        assert!(!engine.paused);
+    }
+
+    #[test]
+    fn gyro_hold_released_while_paused() {
+        let mut buttons = Buttons::new();
+        buttons
+            .get(JoyKey::Home, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::ToggleActive(ClickType::Toggle)));
+        let layer = buttons.get(JoyKey::S, 0);
+        layer
+            .on_down
+            .push(Action::Ext(ExtAction::GyroOff(ClickType::Press)));
+        layer
+            .on_up
+            .push(Action::Ext(ExtAction::GyroOff(ClickType::Release)));
+        let mut engine = new_engine(buttons);
+        let now = Instant::now();
+
+        engine.key(JoyKey::S, true, now);
+        engine.apply_actions(now).unwrap();
+        assert!(!engine.gyro.enabled);
+
+        engine.key(JoyKey::Home, true, now);
+        engine.key(JoyKey::Home, false, now);
+        engine.key(JoyKey::S, false, now);
+        engine.apply_actions(now).unwrap();
+        assert!(engine.paused);
+        assert!(engine.gyro.enabled);
+    }
+
+    #[test]
+    fn muted_area_stick_keeps_following() {
+        let settings = Settings {
+            left_stick_mode: StickMode::MouseArea,
+            ..Default::default()
+        };
+        let (mouse, moves) = recording_mouse();
+        let mut engine =
+            Engine::new(settings, Buttons::new(), Calibration::empty(), mouse).unwrap();
+        let now = Instant::now();
+        let dt = Duration::from_millis(10);
+
+        // Another controller owns the cursor
+        engine.set_area_mouse_enabled(false);
+        engine.handle_left_stick(vec2(0.5, 0.), now, dt);
+        assert!(moves.borrow().is_empty());
+
+        // Only the movement since then is output once it owns the cursor
+        engine.set_area_mouse_enabled(true);
+        engine.handle_left_stick(vec2(0.5, 0.), now, dt);
+        assert!(moves.borrow().is_empty());
+        engine.handle_left_stick(vec2(0.6, 0.), now, dt);
+        let radius = engine.settings.stick.area.screen_radius as i32;
+        assert_eq!(*moves.borrow(), vec![vec2(radius / 10, 0)]);
+    }
+
+    #[test]
+    fn gyro_output_rate() {
+        let mut settings = Settings::default();
+        settings.gyro.output_rate = 10.;
+        let (mut engine, moves) = gyro_engine(Buttons::new(), settings);
+
+        let dt = Duration::from_millis(10);
+        let mut now = Instant::now();
+        let mut counts = Vec::new();
+        for _ in 0..25 {
+            now += dt;
+            turn(&mut engine, now, dt);
+            engine.flush_gyro(now);
+            counts.push(moves.borrow().len());
+        }
+        // One output every 100ms, carrying the movement of all the frames
+        // since the last one
+        assert_eq!(counts[0], 1);
+        assert_eq!(counts[9], 1);
+        assert_eq!(counts[10], 2);
+        assert_eq!(counts[24], 3);
+        assert_eq!(moves.borrow()[1].x.abs(), 10);
+    }
+
+    #[test]
+    fn gyro_move_needs_gyro_enabled() {
+        let mut buttons = Buttons::new();
+        buttons
+            .get(VirtualKey::GyroMove, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::KeyPress(
+                Key::Unicode('a'),
+                ClickType::Press,
+            )));
+        let mut settings = Settings::default();
+        settings.gyro.default_enabled = false;
+        let (mouse, presses) = press_recording_mouse();
+        let mut engine = Engine::new(settings, buttons, Calibration::empty(), mouse).unwrap();
+        let dt = Duration::from_millis(10);
+        let mut now = Instant::now();
+        let mut step = |engine: &mut Engine| {
+            now += dt;
+            turn(engine, now, dt);
+            engine.apply_actions(now).unwrap();
+        };
+
+        step(&mut engine);
+        assert!(presses.borrow().is_empty());
+
+        engine.gyro.enabled = true;
+        step(&mut engine);
+        assert_eq!(*presses.borrow(), vec!["Press Unicode('a')"]);
+    }
+
+    #[test]
+    fn uneven_sample_timing() {
+        let turning = Motion {
+            rotation_speed: RotationSpeed {
+                x: 0.,
+                y: 1000.,
+                z: 0.,
+            },
+            acceleration: Acceleration {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+        };
+        let still = Motion {
+            rotation_speed: RotationSpeed {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            ..turning
+        };
+        let ms = Duration::from_millis;
+        let mut turned = Vec::new();
+        for timed in [false, true] {
+            let (mut engine, moves) = gyro_engine(Buttons::new(), Settings::default());
+            let now = Instant::now();
+            if timed {
+                engine
+                    .handle_timed_motion_frame(&[(turning, ms(30)), (still, ms(10))], now)
+                    .unwrap();
+            } else {
+                engine
+                    .handle_motion_frame(&[turning, still], now, ms(40))
+                    .unwrap();
             }
+            turned.push(moves.borrow().iter().map(|m| m.x).sum::<i32>().abs());
         }
-        if self.enabled && SMOOTH_RATE {
-            mouse.mouse_move_relative(&settings.mouse, delta_position);
+        // The turning sample lasted 30ms, not half of the 40ms frame
+        assert_eq!(turned[1] * 2, turned[0] * 3);
+    }
+
+    #[test]
+    fn gyro_ratchet() {
+        let run = |ratchet: bool| {
+            let mut settings = Settings::default();
+            settings.gyro.smooth_threshold = 1000.;
+            settings.gyro.smooth_time = Duration::from_secs(1);
+            let mut buttons = Buttons::new();
+            let layer = buttons.get(JoyKey::R, 0);
+            layer
+                .on_down
+                .push(Action::Ext(ExtAction::GyroRatchet(ClickType::Press)));
+            layer
+                .on_up
+                .push(Action::Ext(ExtAction::GyroRatchet(ClickType::Release)));
+            let (mut engine, moves) = gyro_engine(buttons, settings);
+
+            let dt = Duration::from_millis(10);
+            let mut now = Instant::now();
+
+            if ratchet {
+                engine.key(JoyKey::R, true, now);
+                engine.apply_actions(now).unwrap();
+            }
+            for _ in 0..20 {
+                now += dt;
+                turn(&mut engine, now, dt);
+            }
+            if ratchet {
+                engine.key(JoyKey::R, false, now);
+                engine.apply_actions(now).unwrap();
+            }
+            for _ in 0..20 {
+                now += dt;
+                turn_at(&mut engine, 0., now, dt);
+            }
+            let moved = !moves.borrow().is_empty();
+            moved
+        };
+
+        assert!(run(false));
+        assert!(!run(true));
+    }
+
+    #[test]
+    fn gyro_trackball() {
+        let run = |trackball: bool, sens: f64, speeds: &[f64]| {
+            let mut settings = Settings::default();
+            settings.gyro.sens = vec2(sens, sens);
+            let mut buttons = Buttons::new();
+            buttons
+                .get(JoyKey::R, 0)
+                .on_down
+                .push(Action::Ext(ExtAction::GyroTrackBall(ClickType::Toggle)));
+            let (mut engine, moves) = gyro_engine(buttons, settings);
+
+            let dt = Duration::from_millis(10);
+            let mut now = Instant::now();
+            if trackball {
+                engine.key(JoyKey::R, true, now);
+                engine.apply_actions(now).unwrap();
+            }
+            for &speed in speeds {
+                now += dt;
+                turn_at(&mut engine, speed, now, dt);
+            }
+            let x: Vec<i32> = moves.borrow().iter().map(|m| m.x).collect();
+            x
+        };
+
+        let still = [100., 100., 100., 0., 0., 0.];
+        assert_eq!(run(false, 1., &still), [-1, -1, -1]);
+        // 1°/frame decaying by 1% per frame, with the sub pixel remainders
+        // carried over
+        assert_eq!(run(true, 1., &still), [-1, -1, -1, -1, -1, -1]);
+
+        // Movement below the rest speed is added to the coast
+        let slow = [100., 100., 100., -0.5, -0.5, -0.5];
+        let total = |x: Vec<i32>| x.iter().sum::<i32>();
+        assert!(total(run(true, 100., &slow)) > total(run(true, 100., &still)));
+    }
+
+    #[test]
+    fn key_toggle() {
+        let mut buttons = Buttons::new();
+        buttons
+            .get(JoyKey::S, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::KeyPress(
+                Key::Unicode('a'),
+                ClickType::Toggle,
+            )));
+        buttons
+            .get(JoyKey::E, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::MousePress(
+                Button::Left,
+                ClickType::Toggle,
+            )));
+        let (mouse, presses) = press_recording_mouse();
+        let mut engine =
+            Engine::new(Settings::default(), buttons, Calibration::empty(), mouse).unwrap();
+
+        let now = Instant::now();
+        for key in [JoyKey::S, JoyKey::E, JoyKey::S, JoyKey::E] {
+            engine.key(key, true, now);
+            engine.key(key, false, now);
+            engine.apply_actions(now).unwrap();
         }
+        assert_eq!(
+            *presses.borrow(),
+            [
+                "Press Unicode('a')",
+                "Press Left",
+                "Release Unicode('a')",
+                "Release Left"
+            ]
+        );
+    }
+
+    #[test]
+    fn release_toggled_keys() {
+        let mut buttons = Buttons::new();
+        buttons
+            .get(JoyKey::S, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::MousePress(
+                Button::Left,
+                ClickType::Toggle,
+            )));
+        let (mouse, presses) = press_recording_mouse();
+        let mut engine = Engine::new(
+            Settings::default(),
+            buttons.clone(),
+            Calibration::empty(),
+            mouse,
+        )
+        .unwrap();
+        let toggle = |engine: &mut Engine| {
+            let now = Instant::now();
+            engine.key(JoyKey::S, true, now);
+            engine.key(JoyKey::S, false, now);
+            engine.apply_actions(now).unwrap();
+        };
+
+        toggle(&mut engine);
+        engine.reload(Settings::default(), buttons);
+        assert_eq!(*presses.borrow(), ["Press Left", "Release Left"]);
+
+        presses.borrow_mut().clear();
+        toggle(&mut engine);
+        drop(engine);
+        assert_eq!(*presses.borrow(), ["Press Left", "Release Left"]);
+    }
+
+    #[test]
+    fn release_held_keys_on_reload() {
+        let mut buttons = Buttons::new();
+        let binding = buttons.get(JoyKey::S, 0);
+        binding.on_down.push(Action::Ext(ExtAction::KeyPress(
+            Key::Unicode('a'),
+            ClickType::Press,
+        )));
+        binding.on_up.push(Action::Ext(ExtAction::KeyPress(
+            Key::Unicode('a'),
+            ClickType::Release,
+        )));
+        let (mouse, presses) = press_recording_mouse();
+        let mut engine = Engine::new(
+            Settings::default(),
+            buttons.clone(),
+            Calibration::empty(),
+            mouse,
+        )
+        .unwrap();
+        let now = Instant::now();
+        engine.key(JoyKey::S, true, now);
+        engine.apply_actions(now).unwrap();
+        engine.reload(Settings::default(), buttons);
+        assert_eq!(
+            *presses.borrow(),
+            ["Press Unicode('a')", "Release Unicode('a')"]
+        );
+
+        // The new bindings see the key up, nothing left to release
+        engine.key(JoyKey::S, false, now);
+        engine.apply_actions(now).unwrap();
+        drop(engine);
+        assert_eq!(presses.borrow().len(), 2);
+    }
+
+    #[test]
+    fn connection_bindings() {
+        let mut buttons = Buttons::new();
+        buttons
+            .get(VirtualKey::Connect, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::GyroOff(ClickType::Toggle)));
+        buttons
+            .get(VirtualKey::Disconnect, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::GyroOn(ClickType::Toggle)));
+        let mut engine = new_engine(buttons);
+        let now = Instant::now();
+
+        engine.connected(now).unwrap();
+        assert!(!engine.gyro.enabled);
+        engine.disconnected(now).unwrap();
+        assert!(engine.gyro.enabled);
+    }
+
+    #[test]
+    fn gyro_toggle_rumble() {
+        let mut buttons = Buttons::new();
+        buttons
+            .get(JoyKey::S, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::GyroOff(ClickType::Toggle)));
+        let mut engine = new_engine(buttons);
+        let now = Instant::now();
+        let toggle = |engine: &mut Engine| {
+            engine.key(JoyKey::S, true, now);
+            engine.key(JoyKey::S, false, now);
+            engine.apply_actions(now).unwrap();
+            engine.take_rumble()
+        };
+
+        assert_eq!(toggle(&mut engine), None);
+
+        assert!(!engine.gyro.enabled);
+
+        engine.settings.gyro.toggle_rumble = true;
+        assert_eq!(toggle(&mut engine), Some(GYRO_ON_RUMBLE));
+        assert_eq!(engine.take_rumble(), None);
+        assert_eq!(toggle(&mut engine), Some(GYRO_OFF_RUMBLE));
     }
 }
@@ -0,0 +1,121 @@
+//! `flick-calibrate` command, measuring `REAL_WORLD_CALIBRATION`.
+//!
+//! The gyro turns the in-game camera at a fixed number of mouse counts per
+//! degree until the camera did a full turn, which gives the counts needed
+//! for 360° in game. The backends provide the calibrated motion samples.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use cgmath::vec2;
+use hid_gamepad_types::Motion;
+
+use crate::{mouse::Mouse, opts::FlickCalibrate};
+
+/// Accumulated yaw of the controller, lying flat.
+#[derive(Debug)]
+struct TurnMeasure {
+    counts_per_degree: f64,
+    counts: f64,
+}
+
+impl TurnMeasure {
+    fn new(counts_per_degree: f64) -> Self {
+        Self {
+            counts_per_degree,
+            counts: 0.,
+        }
+    }
+
+    /// Horizontal mouse counts to emit for this sample.
+    fn push(&mut self, motion: Motion, dt: Duration) -> f64 {
+        // Same direction as GYRO_SPACE = LOCAL
+        let counts = -motion.rotation_speed.y * dt.as_secs_f64() * self.counts_per_degree;
+        self.counts += counts;
+        counts
+    }
+
+    /// `REAL_WORLD_CALIBRATION` giving a 1:1 turn with `IN_GAME_SENS = 1`,
+    /// `None` if the controller didn't turn.
+    fn real_world_calibration(&self) -> Option<f64> {
+        let value = self.counts.abs() / 360.;
+        (value > 0.).then_some(value)
+    }
+}
+
+/// Interactive measure, with `next` returning the calibrated motion samples
+/// received since the last call and the time each one covers.
+pub fn run(
+    opts: &FlickCalibrate,
+    mouse: &mut Mouse,
+    mut next: impl FnMut() -> anyhow::Result<Vec<(Motion, Duration)>>,
+) -> anyhow::Result<()> {
+    println!(
+        "Lay the controller flat and turn it until the game camera did exactly one full turn, \
+         then press Enter..."
+    );
+    let done = enter_pressed();
+    let mut measure = TurnMeasure::new(opts.counts_per_degree);
+    while !done.load(Ordering::Relaxed) {
+        for (motion, dt) in next()? {
+            let counts = measure.push(motion, dt);
+            mouse.mouse_move_relative_pixel(vec2(counts, 0.), 0.);
+        }
+    }
+    match measure.real_world_calibration() {
+        Some(value) => println!(
+            "Add REAL_WORLD_CALIBRATION = {:.3} and IN_GAME_SENS = 1 to your configuration",
+            value
+        ),
+        None => println!("The controller didn't turn"),
+    }
+    Ok(())
+}
+
+/// Set once Enter is pressed in the terminal.
+fn enter_pressed() -> Arc<AtomicBool> {
+    let done = Arc::new(AtomicBool::new(false));
+    let flag = done.clone();
+    std::thread::spawn(move || {
+        let _ = std::io::stdin().read_line(&mut String::new());
+        flag.store(true, Ordering::Relaxed);
+    });
+    done
+}
+
+#[cfg(test)]
+mod test {
+    use hid_gamepad_types::{Acceleration, RotationSpeed};
+
+    use super::*;
+
+    #[test]
+    fn full_turn() {
+        let motion = |yaw| Motion {
+            rotation_speed: RotationSpeed {
+                x: 0.,
+                y: yaw,
+                z: 0.,
+            },
+            acceleration: Acceleration {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+        };
+        let mut measure = TurnMeasure::new(10.);
+        assert_eq!(measure.real_world_calibration(), None);
+
+        // 720° of controller turn for a full in-game turn
+        let dt = Duration::from_millis(125);
+        for _ in 0..90 {
+            assert_eq!(measure.push(motion(64.), dt), -80.);
+        }
+        assert_eq!(measure.real_world_calibration(), Some(20.));
+    }
+}
@@ -1,7 +1,13 @@
 use cgmath::{vec2, ElementWise, InnerSpace, Vector2, Zero};
 use std::{collections::VecDeque, time::Duration};
 
-use crate::{config::settings::GyroSettings, mouse::MouseMovement};
+use crate::{
+    config::{
+        settings::GyroSettings,
+        types::{GyroAccelCurve, GyroSmoothMode},
+    },
+    mouse::MouseMovement,
+};
 
 #[derive(Debug, Default)]
 pub struct GyroMouse {
@@ -99,6 +105,11 @@ impl GyroMouse {
         )
     }
 
+    /// Forget the smoothing history.
+    pub fn reset(&mut self) {
+        self.smooth_buffer.clear();
+    }
+
     fn tiered_smooth(
         &mut self,
         settings: &GyroSettings,
@@ -117,14 +128,32 @@ impl GyroMouse {
 
     fn smooth(&mut self, settings: &GyroSettings, rot: Vector2<f64>, dt: Duration) -> Vector2<f64> {
         self.smooth_buffer.push_front(rot);
-        while dt * self.smooth_buffer.len() as u32 > settings.smooth_time {
+        // Keep at least the current sample when it alone is longer than the
+        // window, at low poll rates
+        while self.smooth_buffer.len() > 1
+            && (dt * self.smooth_buffer.len() as u32 > settings.smooth_time
+                || self.smooth_buffer.len() > settings.smooth_max_samples)
+        {
             self.smooth_buffer.pop_back();
         }
         let sum = self
             .smooth_buffer
             .iter()
             .fold(Vector2::zero(), |acc, x| acc + x);
-        sum / self.smooth_buffer.len() as f64
+        let len = self.smooth_buffer.len() as f64;
+        let average = sum / len;
+        match settings.smooth_mode {
+            GyroSmoothMode::Average => average,
+            GyroSmoothMode::Predictive => {
+                // The average lags by half the window, extrapolate the last
+                // two samples over that duration.
+                let slope = match (self.smooth_buffer.front(), self.smooth_buffer.get(1)) {
+                    (Some(last), Some(previous)) => last - previous,
+                    _ => Vector2::zero(),
+                };
+                average + slope * (len - 1.) / 2. * settings.smooth_prediction
+            }
+        }
     }
 
     fn tight(&mut self, settings: &GyroSettings, rot: Vector2<f64>) -> Vector2<f64> {
@@ -144,6 +173,7 @@ impl GyroMouse {
                 / (settings.fast_threshold - settings.slow_threshold))
                 .max(0.)
                 .min(1.);
+            let factor = accel_curve(settings.accel_curve, factor);
             settings.slow_sens * (1. - factor) + settings.fast_sens * factor
         } else {
             settings.sens
@@ -156,3 +186,103 @@ impl GyroMouse {
         vec2(x, y)
     }
 }
+
+/// Map a linear 0..1 blend factor to the selected curve, keeping both ends.
+fn accel_curve(curve: GyroAccelCurve, factor: f64) -> f64 {
+    match curve {
+        GyroAccelCurve::Linear => factor,
+        GyroAccelCurve::Smoothstep => factor * factor * (3. - 2. * factor),
+        GyroAccelCurve::Exponential => {
+            const STEEPNESS: f64 = 3.;
+            ((STEEPNESS * factor).exp() - 1.) / (STEEPNESS.exp() - 1.)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sens_at(curve: GyroAccelCurve, speed: f64) -> f64 {
+        let settings = GyroSettings {
+            slow_sens: vec2(1., 1.),
+            slow_threshold: 0.,
+            fast_sens: vec2(3., 3.),
+            fast_threshold: 10.,
+            accel_curve: curve,
+            ..Default::default()
+        };
+        GyroMouse::default().get_sens(&settings, vec2(speed, 0.)).x
+    }
+
+    #[test]
+    fn predictive_smoothing_lag() {
+        let dt = Duration::from_millis(10);
+        // Last output of a smoothed ramp, which ends at 100
+        let ramp_end = |mode| {
+            let settings = GyroSettings {
+                smooth_time: Duration::from_millis(100),
+                smooth_mode: mode,
+                ..Default::default()
+            };
+            let mut gyro = GyroMouse::default();
+            (1..=100)
+                .map(|i| gyro.smooth(&settings, vec2(i as f64, 0.), dt).x)
+                .last()
+                .unwrap()
+        };
+
+        let average = ramp_end(GyroSmoothMode::Average);
+        let predictive = ramp_end(GyroSmoothMode::Predictive);
+        assert!((average - 95.5).abs() < 1e-9);
+        assert!((predictive - 100.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn smoothing_sample_cap() {
+        // 1kHz samples, the 100ms window would hold 100 of them
+        let dt = Duration::from_millis(1);
+        let settings = GyroSettings {
+            smooth_time: Duration::from_millis(100),
+            smooth_max_samples: 10,
+            ..Default::default()
+        };
+        let mut gyro = GyroMouse::default();
+        let mut last = 0.;
+        for i in 1..=100 {
+            last = gyro.smooth(&settings, vec2(i as f64, 0.), dt).x;
+        }
+        assert_eq!(gyro.smooth_buffer.len(), 10);
+        // Average of 91 to 100
+        assert!((last - 95.5).abs() < 1e-9);
+
+        // The time bound wins when tighter
+        let settings = GyroSettings {
+            smooth_time: Duration::from_millis(5),
+            ..settings
+        };
+        gyro.smooth(&settings, Vector2::zero(), dt);
+        assert_eq!(gyro.smooth_buffer.len(), 5);
+
+        // A 20Hz frame is longer than the whole window
+        let slow = gyro.smooth(&settings, vec2(3., 0.), Duration::from_millis(50));
+        assert_eq!(slow, vec2(3., 0.));
+    }
+
+    #[test]
+    fn accel_curves() {
+        use GyroAccelCurve::*;
+
+        assert!((sens_at(Linear, 5.) - 2.).abs() < 1e-9);
+        assert!((sens_at(Smoothstep, 5.) - 2.).abs() < 1e-9);
+        assert!(sens_at(Exponential, 5.) < 1.5);
+
+        // Smoothstep eases in, so it is slower than linear below the midpoint
+        assert!(sens_at(Smoothstep, 2.5) < sens_at(Linear, 2.5));
+
+        for curve in [Linear, Smoothstep, Exponential] {
+            assert!((sens_at(curve, 0.) - 1.).abs() < 1e-9);
+            assert!((sens_at(curve, 10.) - 3.).abs() < 1e-9);
+        }
+    }
+}
@@ -3,15 +3,20 @@
 use std::time::{Duration, Instant};
 
 use cgmath::{vec2, AbsDiffEq, Angle, Deg, ElementWise, InnerSpace, Rad, Vector2, Zero};
-use enigo::{Axis, Mouse as _};
+use enigo::Axis;
 
 use crate::{
-    config::{settings::Settings, types::RingMode},
+    config::{
+        settings::Settings,
+        types::{RingMode, StickResponseCurve},
+    },
     mapping::{Buttons, VirtualKey},
     mouse::{Mouse, MouseMovement},
 };
 
 pub trait Stick {
+    /// `stick` is the position in the unit circle, with +x to the right and
+    /// +y to the top. Backends convert from their device convention.
     fn handle(
         &mut self,
         stick: Vector2<f64>,
@@ -73,13 +78,45 @@ impl StickSide {
     }
 }
 
+/// Exponential smoothing of the stick position, shared by the stick modes.
+pub struct StickSmoother {
+    /// Time constant of the smoothing, zero disables it.
+    time: Duration,
+    last: Option<Vector2<f64>>,
+}
+
+impl StickSmoother {
+    pub fn new(time: Duration) -> Self {
+        Self { time, last: None }
+    }
+
+    pub fn smooth(&mut self, stick: Vector2<f64>, dt: Duration) -> Vector2<f64> {
+        if self.time.is_zero() {
+            return stick;
+        }
+        let smoothed = match self.last {
+            Some(last) => {
+                let factor = 1. - (-dt.as_secs_f64() / self.time.as_secs_f64()).exp();
+                last + (stick - last) * factor
+            }
+            None => stick,
+        };
+        self.last = Some(smoothed);
+        smoothed
+    }
+}
+
 pub struct CameraStick {
     current_speed: f64,
+    smoother: StickSmoother,
 }
 
 impl CameraStick {
-    pub fn new() -> Self {
-        CameraStick { current_speed: 0. }
+    pub fn new(smoother: StickSmoother) -> Self {
+        CameraStick {
+            current_speed: 0.,
+            smoother,
+        }
     }
 }
 
@@ -95,20 +132,20 @@ impl Stick for CameraStick {
         dt: Duration,
     ) {
         // TODO: check settings semantic
+        let stick = self.smoother.smooth(stick, dt);
         let s = &settings.stick;
         let amp = stick.magnitude();
-        let amp_zones = (amp - s.deadzone) / (s.fullzone - s.deadzone);
-        if amp_zones >= 1. {
+        let amp_clamped = s.amp_zones(amp, side);
+        if s.aim.acceleration && amp_clamped >= 1. {
             self.current_speed = (self.current_speed + s.aim.acceleration_rate * dt.as_secs_f64())
                 .min(s.aim.acceleration_cap);
         } else {
             self.current_speed = 0.;
         }
-        let amp_clamped = amp_zones.max(0.).min(1.);
-        let amp_exp = amp_clamped.powf(s.aim.power);
+        let amp_exp = response_curve(s.aim.response_curve, amp_clamped);
         if stick.magnitude2() > 0. {
             let mut offset = stick.normalize_to(amp_exp)
-                * s.aim.sens_dps
+                * s.aim.sens_at(amp_clamped)
                 * ((1. + self.current_speed) * dt.as_secs_f64());
             offset.mul_assign_element_wise(
                 match side {
@@ -124,6 +161,17 @@ impl Stick for CameraStick {
     }
 }
 
+/// Map the 0..1 amplitude to the selected curve, keeping both ends.
+fn response_curve(curve: StickResponseCurve, amp: f64) -> f64 {
+    match curve {
+        StickResponseCurve::Linear => amp,
+        StickResponseCurve::Power(exponent) => amp.powf(exponent),
+        // Two parabolas meeting at the knee with the same slope
+        StickResponseCurve::SCurve { knee } if amp <= knee => amp * amp / knee,
+        StickResponseCurve::SCurve { knee } => 1. - (1. - amp).powi(2) / (1. - knee),
+    }
+}
+
 #[derive(Debug)]
 enum FlickStickState {
     Center,
@@ -134,6 +182,8 @@ enum FlickStickState {
     },
     Rotating {
         old_rotation: Deg<f64>,
+        /// Rotation of the previous frame, used to unwrap the next one.
+        last_delta: Deg<f64>,
     },
 }
 
@@ -168,7 +218,7 @@ impl Stick for FlickStick {
     fn handle(
         &mut self,
         stick: Vector2<f64>,
-        _side: StickSide,
+        side: StickSide,
         settings: &Settings,
         _bindings: &mut Buttons,
         mouse: &mut Mouse,
@@ -178,14 +228,16 @@ impl Stick for FlickStick {
         let s = &settings.stick;
         let offset = match self.state {
             FlickStickState::Center | FlickStickState::Rotating { .. }
-                if stick.magnitude() < s.fullzone =>
+                if stick.magnitude() < s.fullzone(side) =>
             {
                 self.state = FlickStickState::Center;
                 None
             }
             FlickStickState::Center => {
-                let target = stick.angle(Vector2::unit_y()).into();
-                self.state = if self.do_flick {
+                let target: Deg<f64> = stick.angle(Vector2::unit_y()).into();
+                // Nothing to flick when pushing forward, within
+                // FLICK_DEADZONE_ANGLE
+                self.state = if self.do_flick && target.0.abs() > s.flick.forward_deadzone_arc.0 {
                     FlickStickState::Flicking {
                         flick_start: now,
                         last: Deg(0.),
@@ -194,6 +246,7 @@ impl Stick for FlickStick {
                 } else {
                     FlickStickState::Rotating {
                         old_rotation: target,
+                        last_delta: Deg(0.),
                     }
                 };
                 None
@@ -204,13 +257,16 @@ impl Stick for FlickStick {
                 target,
             } => {
                 let elapsed = now.duration_since(flick_start).as_secs_f64();
-                let max = s.flick.flick_time.as_secs_f64() * target.0.abs() / 180.;
-                let dt_factor = elapsed / max;
+                let max = s.flick.flick_time.as_secs_f64()
+                    * (target.0.abs() / 180.).powf(1. + s.flick.exponent);
+                // A zero FLICK_TIME flicks instantly
+                let dt_factor = if max > 0. { elapsed / max } else { 1. };
                 let current_angle = target * dt_factor.min(1.);
                 let delta = current_angle - *last;
-                if dt_factor > 1. {
+                if dt_factor >= 1. {
                     self.state = FlickStickState::Rotating {
                         old_rotation: current_angle,
+                        last_delta: Deg(0.),
                     };
                 } else {
                     *last = current_angle;
@@ -219,12 +275,24 @@ impl Stick for FlickStick {
             }
             FlickStickState::Rotating {
                 ref mut old_rotation,
+                ref mut last_delta,
             } => {
                 if self.do_rotate {
-                    let angle = stick.angle(Vector2::unit_y()).into();
-                    let delta = angle - *old_rotation;
+                    let angle: Deg<f64> = stick.angle(Vector2::unit_y()).into();
+                    let shortest = (angle - *old_rotation).normalize_signed();
+                    // Take the turn closest to the previous one instead of
+                    // the shortest one, so a fast spin of more than half a
+                    // turn per frame doesn't go backward. Unless it's too far
+                    // from the previous one, which is a reversal.
+                    let correction = (shortest - *last_delta).normalize_signed();
+                    let delta = if correction.0.abs() <= MAX_SPIN_CORRECTION.0 {
+                        *last_delta + correction
+                    } else {
+                        shortest
+                    };
                     *old_rotation = angle;
-                    Some(delta.normalize_signed())
+                    *last_delta = delta;
+                    Some(delta)
                 } else {
                     None
                 }
@@ -236,17 +304,17 @@ impl Stick for FlickStick {
     }
 }
 
+/// Largest change from the rotation of the previous frame still considered
+/// part of the same spin by the flick stick.
+const MAX_SPIN_CORRECTION: Deg<f64> = Deg(90.);
+
 pub struct ButtonStick {
-    angle: Deg<f64>,
     ring_mode: RingMode,
 }
 
 impl ButtonStick {
     pub fn new(ring_mode: RingMode) -> Self {
-        Self {
-            angle: Deg(30.),
-            ring_mode,
-        }
+        Self { ring_mode }
     }
 }
 
@@ -263,13 +331,15 @@ impl Stick for ButtonStick {
     ) {
         let settings = &settings.stick;
         let amp = stick.magnitude();
-        let amp_zones = (amp - settings.deadzone) / (settings.fullzone - settings.deadzone);
-        let amp_clamped = amp_zones.max(0.).min(1.);
-
-        if amp_clamped > 0. {
-            let stick = stick.normalize_to(amp_clamped);
+        let amp_clamped = settings.amp_zones(amp, side);
+        let active = match settings.button.threshold {
+            Some(threshold) => amp > threshold,
+            None => amp_clamped > 0.,
+        };
 
-            let epsilon = Rad::from(Deg(90.) - self.angle).0;
+        if active {
+            // Each direction covers its quadrant plus half of the diagonal zones
+            let epsilon = Rad::from(Deg(45.) + settings.button.diagonal_tolerance / 2.).0;
 
             let angle_r = stick.angle(Vector2::unit_x());
             let angle_l = stick.angle(-Vector2::unit_x());
@@ -301,22 +371,30 @@ pub struct AreaStick {
     snap: bool,
     last_location: Vector2<i32>,
     last_offset: Vector2<f64>,
+    smoother: StickSmoother,
+    /// Smoothing of the cursor offset from the center, gliding toward the
+    /// ring in snap mode.
+    offset_smoother: StickSmoother,
 }
 
 impl AreaStick {
-    pub fn area() -> Self {
+    pub fn area(smoother: StickSmoother, offset_smoother: StickSmoother) -> Self {
         Self {
             snap: false,
             last_location: Vector2::zero(),
             last_offset: Vector2::zero(),
+            smoother,
+            offset_smoother,
         }
     }
 
-    pub fn ring() -> Self {
+    pub fn ring(smoother: StickSmoother, offset_smoother: StickSmoother) -> Self {
         Self {
             snap: true,
             last_location: Vector2::zero(),
             last_offset: Vector2::zero(),
+            smoother,
+            offset_smoother,
         }
     }
 }
@@ -325,16 +403,17 @@ impl Stick for AreaStick {
     fn handle(
         &mut self,
         stick: Vector2<f64>,
-        _side: StickSide,
+        side: StickSide,
         settings: &Settings,
         _bindings: &mut Buttons,
         mouse: &mut Mouse,
         _now: Instant,
-        _dt: Duration,
+        dt: Duration,
     ) {
+        let stick = self.smoother.smooth(stick, dt);
         let radius = settings.stick.area.screen_radius as f64;
         let offset = if self.snap {
-            if stick.magnitude() > settings.stick.deadzone {
+            if stick.magnitude() > settings.stick.deadzone(side) {
                 stick.normalize_to(radius)
             } else {
                 Vector2::zero()
@@ -343,6 +422,9 @@ impl Stick for AreaStick {
             stick * radius
         }
         .mul_element_wise(vec2(1., -1.));
+        // Smoothing the offset rather than its delta keeps the total
+        // displacement exact once the stick stops.
+        let offset = self.offset_smoother.smooth(offset, dt);
         let center = settings.stick.area.screen_resolution / 2;
         let location = center.cast::<i32>().unwrap() + offset.cast::<i32>().unwrap();
         if self.snap {
@@ -350,21 +432,30 @@ impl Stick for AreaStick {
                 mouse.mouse_move_absolute_pixel(location);
             }
         } else {
-            mouse.mouse_move_relative_pixel(offset.sub_element_wise(self.last_offset));
+            // No threshold, the cursor must follow the stick position exactly
+            mouse.mouse_move_relative_pixel(offset.sub_element_wise(self.last_offset), 0.);
+            self.last_offset = offset;
         }
         self.last_location = location;
-        self.last_offset = offset;
     }
 }
 
-pub enum ScrollStick {
+enum ScrollState {
     Center,
     Scrolling { last: Deg<f64>, acc: f64 },
 }
 
+pub struct ScrollStick {
+    state: ScrollState,
+    smoother: StickSmoother,
+}
+
 impl ScrollStick {
-    pub fn new() -> Self {
-        Self::Center
+    pub fn new(smoother: StickSmoother) -> Self {
+        Self {
+            state: ScrollState::Center,
+            smoother,
+        }
     }
 }
 
@@ -372,32 +463,517 @@ impl Stick for ScrollStick {
     fn handle(
         &mut self,
         stick: Vector2<f64>,
-        _side: StickSide,
+        side: StickSide,
         settings: &Settings,
         _bindings: &mut Buttons,
         mouse: &mut Mouse,
         _now: Instant,
-        _dt: Duration,
+        dt: Duration,
     ) {
+        let stick = self.smoother.smooth(stick, dt);
         let angle = vec2(0., 1.).angle(stick).into();
-        match self {
-            _ if stick.magnitude() < settings.stick.deadzone => *self = Self::Center,
-            ScrollStick::Center => {
-                *self = ScrollStick::Scrolling {
+        match &mut self.state {
+            _ if stick.magnitude() < settings.stick.deadzone(side) => {
+                self.state = ScrollState::Center
+            }
+            ScrollState::Center => {
+                self.state = ScrollState::Scrolling {
                     last: angle,
                     acc: 0.,
                 }
             }
-            ScrollStick::Scrolling { last, acc } => {
+            ScrollState::Scrolling { last, acc } => {
                 let delta = (angle - *last).normalize_signed() / settings.stick.scroll.sens + *acc;
                 let delta_rounded = delta.round();
                 *acc = delta - delta_rounded;
-                mouse
-                    .enigo()
-                    .scroll(delta_rounded as i32, Axis::Vertical)
-                    .unwrap();
+                if let Err(e) = mouse.scroll(delta_rounded as i32, Axis::Vertical) {
+                    eprintln!("Error scrolling: {}", e);
+                }
                 *last = angle;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mouse::test::recording_mouse;
+
+    #[test]
+    fn flick_uses_side_fullzone() {
+        let mut settings = Settings::default();
+        settings.stick.fullzone = 0.9;
+        settings.stick.left_fullzone = Some(0.8);
+        let mut bindings = Buttons::new();
+        let mut mouse = Mouse::dry_run();
+        let now = Instant::now();
+        // Not straight forward, which has nothing to flick
+        let stick = vec2(0.6, 0.6);
+
+        for (side, flicks) in [(StickSide::Left, true), (StickSide::Right, false)] {
+            let mut flick = FlickStick::new(true, true);
+            flick.handle(
+                stick,
+                side,
+                &settings,
+                &mut bindings,
+                &mut mouse,
+                now,
+                Duration::ZERO,
+            );
+            assert_eq!(
+                matches!(flick.state, FlickStickState::Flicking { .. }),
+                flicks
+            );
+        }
+    }
+
+    #[test]
+    fn flick_time_exponent() {
+        let mut settings = Settings::default();
+        settings.stick.flick.flick_time = Duration::from_millis(100);
+        let now = Instant::now();
+        // Quarter turn to the right
+        let stick = vec2(1., 0.);
+
+        let mut flicking = |exponent, elapsed| {
+            settings.stick.flick.exponent = exponent;
+            let mut flick = FlickStick::new(true, true);
+            for t in [now, now + Duration::from_millis(elapsed)] {
+                flick.handle(
+                    stick,
+                    StickSide::Right,
+                    &settings,
+                    &mut Buttons::new(),
+                    &mut Mouse::dry_run(),
+                    t,
+                    Duration::ZERO,
+                );
+            }
+            matches!(flick.state, FlickStickState::Flicking { .. })
+        };
+        // Half of FLICK_TIME
+        assert!(flicking(0., 45));
+        assert!(!flicking(0., 55));
+        // A quarter
+        assert!(flicking(1., 20));
+        assert!(!flicking(1., 30));
+        // Always FLICK_TIME
+        assert!(flicking(-1., 95));
+        assert!(!flicking(-1., 105));
+    }
+
+    #[test]
+    fn flick_forward_deadzone() {
+        let mut settings = Settings::default();
+        settings.stick.flick.forward_deadzone_arc = Deg(10.);
+        let flicks = |angle: Deg<f64>| {
+            let mut flick = FlickStick::new(true, true);
+            let (sin, cos) = angle.0.to_radians().sin_cos();
+            flick.handle(
+                vec2(sin, cos),
+                StickSide::Right,
+                &settings,
+                &mut Buttons::new(),
+                &mut Mouse::dry_run(),
+                Instant::now(),
+                Duration::ZERO,
+            );
+            matches!(flick.state, FlickStickState::Flicking { .. })
+        };
+        assert!(!flicks(Deg(0.)));
+        assert!(!flicks(Deg(9.)));
+        assert!(!flicks(Deg(-9.)));
+        assert!(flicks(Deg(11.)));
+        assert!(flicks(Deg(-11.)));
+    }
+
+    /// Number of directions of a `NO_MOUSE` left stick pressed at `angle`
+    /// from the right and `amp` tilt.
+    fn pressed_directions(settings: &Settings, angle: Deg<f64>, amp: f64) -> usize {
+        use crate::{
+            mapping::{Action, ExtAction},
+            ClickType,
+        };
+
+        let now = Instant::now();
+        let mut bindings = Buttons::new();
+        for (key, c) in [(VirtualKey::LRight, 'd'), (VirtualKey::LUp, 'w')] {
+            bindings
+                .get(key, 0)
+                .on_down
+                .push(Action::Ext(ExtAction::KeyPress(
+                    enigo::Key::Unicode(c),
+                    ClickType::Press,
+                )));
+        }
+        let angle = Rad::from(angle).0;
+        ButtonStick::new(RingMode::Outer).handle(
+            vec2(angle.cos(), angle.sin()) * amp,
+            StickSide::Left,
+            settings,
+            &mut bindings,
+            &mut Mouse::dry_run(),
+            now,
+            Duration::ZERO,
+        );
+        let count = bindings.tick(now).count();
+        count
+    }
+
+    #[test]
+    fn wasd_diagonal_tolerance() {
+        let mut settings = Settings::default();
+        settings.stick.deadzone = 0.;
+
+        // Default tolerance presses both keys between 30° and 60°
+        assert_eq!(pressed_directions(&settings, Deg(20.), 0.5), 1);
+        assert_eq!(pressed_directions(&settings, Deg(35.), 0.5), 2);
+
+        settings.stick.button.diagonal_tolerance = Deg(10.);
+        assert_eq!(pressed_directions(&settings, Deg(20.), 0.5), 1);
+        assert_eq!(pressed_directions(&settings, Deg(35.), 0.5), 1);
+        assert_eq!(pressed_directions(&settings, Deg(45.), 0.5), 2);
+    }
+
+    #[test]
+    fn button_threshold() {
+        let mut settings = Settings::default();
+        settings.stick.deadzone = 0.15;
+        assert_eq!(pressed_directions(&settings, Deg(0.), 0.1), 0);
+        assert_eq!(pressed_directions(&settings, Deg(0.), 0.3), 1);
+
+        settings.stick.button.threshold = Some(0.6);
+        assert_eq!(pressed_directions(&settings, Deg(0.), 0.3), 0);
+        assert_eq!(pressed_directions(&settings, Deg(0.), 0.59), 0);
+        assert_eq!(pressed_directions(&settings, Deg(0.), 0.7), 1);
+        assert_eq!(pressed_directions(&settings, Deg(0.), 1.), 1);
+    }
+
+    #[test]
+    fn deadzone_curve() {
+        use crate::config::{settings::StickSettings, types::DeadzoneCurve};
+
+        let mut settings = Settings::default().stick;
+        settings.deadzone = 0.2;
+        settings.fullzone = 0.8;
+        let ramp = |settings: &StickSettings, amp| settings.amp_zones(amp, StickSide::Left);
+
+        for curve in [
+            DeadzoneCurve::Linear,
+            DeadzoneCurve::Smoothstep,
+            DeadzoneCurve::Exponential,
+        ] {
+            settings.deadzone_curve = curve;
+            assert_eq!(ramp(&settings, 0.1), 0.);
+            assert_eq!(ramp(&settings, 0.2), 0.);
+            assert!((ramp(&settings, 0.8) - 1.).abs() < 1e-9);
+            assert_eq!(ramp(&settings, 1.), 1.);
+        }
+
+        // Just out of the deadzone, the curves start much slower than linear
+        let edge = 0.2 + 0.6 * 0.05;
+        settings.deadzone_curve = DeadzoneCurve::Linear;
+        let linear = ramp(&settings, edge);
+        assert!((linear - 0.05).abs() < 1e-9);
+        settings.deadzone_curve = DeadzoneCurve::Smoothstep;
+        assert!(ramp(&settings, edge) < linear / 5.);
+        settings.deadzone_curve = DeadzoneCurve::Exponential;
+        assert!(ramp(&settings, edge) < linear / 2.);
+
+        // Smoothstep is symmetrical around the middle
+        settings.deadzone_curve = DeadzoneCurve::Smoothstep;
+        assert!((ramp(&settings, 0.5) - 0.5).abs() < 1e-9);
+    }
+
+    /// Relative mouse moves of `stick` pushed fully to the right, after a
+    /// frame at rest.
+    fn moves_after_push(stick: &mut dyn Stick) -> Vector2<i32> {
+        let settings = Settings::default();
+        let (mut mouse, moves) = recording_mouse();
+        let now = Instant::now();
+        let dt = Duration::from_millis(10);
+        for pos in [Vector2::zero(), vec2(1., 0.)] {
+            stick.handle(
+                pos,
+                StickSide::Right,
+                &settings,
+                &mut Buttons::new(),
+                &mut mouse,
+                now,
+                dt,
+            );
+        }
+        let sum = moves.borrow().iter().sum();
+        sum
+    }
+
+    #[test]
+    fn zero_flick_time() {
+        let mut settings = Settings::default();
+        settings.stick.flick.flick_time = Duration::ZERO;
+        let (mut mouse, moves) = recording_mouse();
+        let now = Instant::now();
+        let mut flick = FlickStick::new(true, true);
+        let mut push = |flick: &mut FlickStick, stick| {
+            flick.handle(
+                stick,
+                StickSide::Right,
+                &settings,
+                &mut Buttons::new(),
+                &mut mouse,
+                now,
+                Duration::ZERO,
+            )
+        };
+
+        for _ in 0..3 {
+            push(&mut flick, vec2(1., 0.));
+        }
+        assert_eq!(*moves.borrow(), [vec2(90, 0)]);
+        assert!(matches!(flick.state, FlickStickState::Rotating { .. }));
+
+        moves.borrow_mut().clear();
+        push(&mut flick, Vector2::zero());
+        push(&mut flick, vec2(0., 1.));
+        push(&mut flick, vec2(0., 1.));
+        assert!(moves.borrow().is_empty());
+        assert!(matches!(flick.state, FlickStickState::Rotating { .. }));
+    }
+
+    /// Moves of a rotating flick stick turned by `steps` degrees per frame.
+    fn spin(steps: &[f64]) -> Vec<i32> {
+        let settings = Settings::default();
+        let (mut mouse, moves) = recording_mouse();
+        let now = Instant::now();
+        let mut flick = FlickStick::new(false, true);
+        let mut angle = 0f64;
+        for step in std::iter::once(&0.).chain(steps) {
+            angle += step;
+            let (sin, cos) = angle.to_radians().sin_cos();
+            flick.handle(
+                vec2(sin, cos),
+                StickSide::Right,
+                &settings,
+                &mut Buttons::new(),
+                &mut mouse,
+                now,
+                Duration::ZERO,
+            );
+        }
+        let moves = moves.borrow();
+        moves.iter().map(|m| m.x).collect()
+    }
+
+    #[test]
+    fn fast_spin() {
+        // Speeds up to more than half a turn per frame, crossing ±180° on
+        // the way
+        let steps = [20., 60., 120., 170., 200., 250., 300., 250.];
+        let moves = spin(&steps);
+        assert_eq!(moves.len(), steps.len());
+        assert!(moves.iter().all(|x| x.signum() == moves[0].signum()));
+        let total: i32 = moves.iter().sum();
+        assert_eq!(total.abs(), steps.iter().sum::<f64>() as i32);
+    }
+
+    #[test]
+    fn spin_reversal() {
+        let moves = spin(&[100., 170., -20.]);
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[2], -moves[1] * 20 / 170);
+    }
+
+    #[test]
+    fn smoother() {
+        let dt = Duration::from_millis(10);
+        let mut off = StickSmoother::new(Duration::ZERO);
+        let mut on = StickSmoother::new(Duration::from_millis(10));
+        assert_eq!(off.smooth(vec2(1., 0.), dt), vec2(1., 0.));
+        assert_eq!(on.smooth(vec2(1., 0.), dt), vec2(1., 0.));
+        off.smooth(Vector2::zero(), dt);
+        let smoothed = on.smooth(Vector2::zero(), dt);
+        assert!((smoothed.x - (-1f64).exp()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn aim_sens_blend() {
+        let mut settings = Settings::default();
+        settings.stick.deadzone = 0.;
+        settings.stick.fullzone = 1.;
+        let move_at = |settings: &Settings, tilt| {
+            let (mut mouse, moves) = recording_mouse();
+            CameraStick::new(StickSmoother::new(Duration::ZERO)).handle(
+                vec2(tilt, 0.),
+                StickSide::Right,
+                settings,
+                &mut Buttons::new(),
+                &mut mouse,
+                Instant::now(),
+                Duration::from_secs(1),
+            );
+            let x = moves.borrow().iter().map(|m| m.x).sum::<i32>();
+            x
+        };
+
+        // STICK_SENS alone
+        assert_eq!(move_at(&settings, 0.5), 180);
+        assert_eq!(move_at(&settings, 1.), 360);
+
+        settings.stick.aim.slow_dps = Some(100.);
+        settings.stick.aim.fast_dps = Some(400.);
+        // Half way between both sensitivities, for half the tilt
+        assert_eq!(move_at(&settings, 0.5), 125);
+        assert_eq!(move_at(&settings, 1.), 400);
+    }
+
+    #[test]
+    fn aim_acceleration_off() {
+        let mut settings = Settings::default();
+        settings.stick.deadzone = 0.;
+        settings.stick.fullzone = 1.;
+        settings.stick.aim.acceleration_rate = 10.;
+        let frames = |settings: &Settings| {
+            let (mut mouse, moves) = recording_mouse();
+            let mut stick = CameraStick::new(StickSmoother::new(Duration::ZERO));
+            for tilt in [1., 1., 1., 0.5] {
+                stick.handle(
+                    vec2(tilt, 0.),
+                    StickSide::Right,
+                    settings,
+                    &mut Buttons::new(),
+                    &mut mouse,
+                    Instant::now(),
+                    Duration::from_millis(100),
+                );
+            }
+            let x: Vec<i32> = moves.borrow().iter().map(|m| m.x).collect();
+            x
+        };
+
+        assert_eq!(frames(&settings), [72, 108, 144, 18]);
+        settings.stick.aim.acceleration = false;
+        assert_eq!(frames(&settings), [36, 36, 36, 18]);
+    }
+
+    #[test]
+    fn aim_smoothing() {
+        let smooth = StickSmoother::new(Duration::from_millis(100));
+        assert_eq!(moves_after_push(&mut CameraStick::new(smooth)).x, 0);
+        let raw = StickSmoother::new(Duration::ZERO);
+        assert!(moves_after_push(&mut CameraStick::new(raw)).x > 0);
+    }
+
+    #[test]
+    fn response_curves() {
+        use StickResponseCurve::*;
+
+        let s_curve = SCurve { knee: 0.5 };
+        for curve in [Linear, Power(2.), s_curve] {
+            assert_eq!(response_curve(curve, 0.), 0.);
+            assert_eq!(response_curve(curve, 1.), 1.);
+        }
+        assert_eq!(response_curve(Linear, 0.25), 0.25);
+        assert_eq!(response_curve(Power(2.), 0.5), 0.25);
+        assert_eq!(response_curve(s_curve, 0.25), 0.125);
+        assert_eq!(response_curve(s_curve, 0.5), 0.5);
+        assert_eq!(response_curve(s_curve, 0.75), 0.875);
+        // Slower than linear below the knee, faster above
+        let knee = SCurve { knee: 0.3 };
+        assert!(response_curve(knee, 0.2) < 0.2);
+        assert!(response_curve(knee, 0.4) > 0.4);
+    }
+
+    #[test]
+    fn area_smoothing() {
+        let smooth = StickSmoother::new(Duration::from_millis(100));
+        // 1 - e^-0.1 of the 50 pixel radius
+        let raw = || StickSmoother::new(Duration::ZERO);
+        assert_eq!(moves_after_push(&mut AreaStick::area(smooth, raw())).x, 5);
+        assert_eq!(moves_after_push(&mut AreaStick::area(raw(), raw())).x, 50);
+    }
+
+    #[test]
+    fn area_rel_smoothing() {
+        let settings = Settings::default();
+        let (mut mouse, moves) = recording_mouse();
+        let mut stick = AreaStick::area(
+            StickSmoother::new(Duration::ZERO),
+            StickSmoother::new(Duration::from_millis(50)),
+        );
+        let now = Instant::now();
+        let dt = Duration::from_millis(10);
+        stick.handle(
+            Vector2::zero(),
+            StickSide::Right,
+            &settings,
+            &mut Buttons::new(),
+            &mut mouse,
+            now,
+            dt,
+        );
+        for _ in 0..100 {
+            stick.handle(
+                vec2(1., 0.),
+                StickSide::Right,
+                &settings,
+                &mut Buttons::new(),
+                &mut mouse,
+                now,
+                dt,
+            );
+        }
+
+        // The step to the 50 pixel radius ramps over several frames
+        let moves = moves.borrow();
+        assert!(moves.len() > 5);
+        assert!(moves.iter().all(|m| m.x < 10));
+        assert!(moves.windows(2).all(|w| w[1].x <= w[0].x + 1));
+        assert_eq!(moves.iter().sum::<Vector2<i32>>(), vec2(50, 0));
+    }
+
+    #[test]
+    fn ring_smoothing() {
+        let settings = Settings::default();
+        let mut mouse = Mouse::dry_run();
+        let mut stick = AreaStick::ring(
+            StickSmoother::new(Duration::ZERO),
+            StickSmoother::new(Duration::from_millis(50)),
+        );
+        let now = Instant::now();
+        let dt = Duration::from_millis(10);
+        let mut locations = Vec::new();
+        let positions = std::iter::once(Vector2::zero()).chain(vec![vec2(1., 0.); 100]);
+        for stick_pos in positions {
+            stick.handle(
+                stick_pos,
+                StickSide::Right,
+                &settings,
+                &mut Buttons::new(),
+                &mut mouse,
+                now,
+                dt,
+            );
+            locations.push(stick.last_location.x);
+        }
+
+        // Glides from the center to the 50 pixel radius
+        assert_eq!(locations[0], 960);
+        assert!(locations[1] < 980);
+        assert!(locations.windows(2).all(|w| w[0] <= w[1]));
+        assert!(locations[100] >= 1009);
+    }
+
+    #[test]
+    fn scroll_smoothing() {
+        for (time, scrolling) in [(100, false), (0, true)] {
+            let mut stick = ScrollStick::new(StickSmoother::new(Duration::from_millis(time)));
+            moves_after_push(&mut stick);
+            assert_eq!(
+                matches!(stick.state, ScrollState::Scrolling { .. }),
+                scrolling
+            );
+        }
+    }
+}
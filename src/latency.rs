@@ -0,0 +1,153 @@
+//! `latency-test` command, measuring the time from a button event to the
+//! emitted key press through `Engine`.
+
+use std::{
+    cell::Cell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use enigo::{Direction, Key};
+use hid_gamepad_types::JoyKey;
+
+use crate::{
+    calibration::Calibration,
+    config::{self, settings::Settings},
+    engine::Engine,
+    mapping::Buttons,
+    mouse::{Mouse, MouseSink},
+    opts::LatencyTest,
+};
+
+/// Width of the histogram bars, in characters.
+const BAR_WIDTH: usize = 50;
+
+/// Records when the last key event was emitted.
+#[derive(Debug)]
+struct TimingSink(Rc<Cell<Option<Instant>>>);
+
+impl MouseSink for TimingSink {
+    fn key(&mut self, _key: Key, _direction: Direction) -> anyhow::Result<()> {
+        self.0.set(Some(Instant::now()));
+        Ok(())
+    }
+}
+
+pub fn run(opts: &LatencyTest) -> anyhow::Result<()> {
+    let mut settings = Settings::default();
+    let mut bindings = Buttons::new();
+    let errors = config::parse_file("S = a", &mut settings, &mut bindings);
+    assert!(errors.is_empty());
+
+    let emitted = Rc::new(Cell::new(None));
+    let mouse = Mouse::with_sink(Box::new(TimingSink(emitted.clone())));
+    let mut engine = Engine::new(settings, bindings, Calibration::empty(), mouse)?;
+
+    let mut samples = Vec::with_capacity(opts.iterations as usize);
+    for _ in 0..opts.iterations {
+        for pressed in [true, false] {
+            emitted.set(None);
+            let start = Instant::now();
+            engine.key(JoyKey::S, pressed, start);
+            engine.apply_actions(start)?;
+            let end = emitted.get().expect("the binding should emit a key event");
+            samples.push(end.duration_since(start));
+        }
+    }
+
+    let stats = match Stats::new(&samples) {
+        Some(stats) => stats,
+        None => {
+            println!("No sample");
+            return Ok(());
+        }
+    };
+    println!(
+        "{} button events: min {:?}, avg {:?}, max {:?}",
+        samples.len(),
+        stats.min,
+        stats.avg,
+        stats.max
+    );
+    let buckets = histogram(&samples);
+    let biggest = buckets.iter().map(|&(_, count)| count).max().unwrap_or(1);
+    for (upper, count) in buckets {
+        println!(
+            "< {:>10?} {:>8} {}",
+            upper,
+            count,
+            "#".repeat(count * BAR_WIDTH / biggest)
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+struct Stats {
+    min: Duration,
+    avg: Duration,
+    max: Duration,
+}
+
+impl Stats {
+    fn new(samples: &[Duration]) -> Option<Self> {
+        Some(Self {
+            min: *samples.iter().min()?,
+            avg: samples.iter().sum::<Duration>() / samples.len() as u32,
+            max: *samples.iter().max()?,
+        })
+    }
+}
+
+/// Count of samples per power of two nanoseconds, as (upper bound, count),
+/// from the smallest to the biggest non-empty bucket.
+fn histogram(samples: &[Duration]) -> Vec<(Duration, usize)> {
+    let mut counts = [0; 64];
+    for sample in samples {
+        let nanos = sample.as_nanos().min(u64::MAX as u128) as u64;
+        counts[(64 - nanos.leading_zeros()).min(63) as usize] += 1;
+    }
+    match (
+        counts.iter().position(|&c| c > 0),
+        counts.iter().rposition(|&c| c > 0),
+    ) {
+        (Some(first), Some(last)) => (first..=last)
+            .map(|i| (Duration::from_nanos(1 << i), counts[i]))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stats() {
+        let ns = Duration::from_nanos;
+        assert_eq!(Stats::new(&[]), None);
+        assert_eq!(
+            Stats::new(&[ns(300), ns(100), ns(200)]),
+            Some(Stats {
+                min: ns(100),
+                avg: ns(200),
+                max: ns(300)
+            })
+        );
+    }
+
+    #[test]
+    fn power_of_two_buckets() {
+        let ns = Duration::from_nanos;
+        assert_eq!(
+            histogram(&[ns(5), ns(7), ns(40)]),
+            [(ns(8), 2), (ns(16), 0), (ns(32), 0), (ns(64), 1)]
+        );
+        assert!(histogram(&[]).is_empty());
+    }
+
+    #[test]
+    fn engine_emits_keys() {
+        run(&LatencyTest { iterations: 3 }).unwrap();
+    }
+}
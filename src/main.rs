@@ -2,17 +2,29 @@
 
 mod backend;
 mod calibration;
+mod cheatsheet;
 mod config;
 mod engine;
+mod flick_calibration;
 mod gyromouse;
 mod joystick;
+mod latency;
 mod mapping;
 mod motion_stick;
 mod mouse;
 mod opts;
+mod reload;
 mod space_mapper;
+mod trace;
+mod trigger;
 
-use std::{fs::File, io::Read};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+#[cfg(windows)]
+use std::io::Read;
 
 use anyhow::{bail, Context};
 use backend::Backend;
@@ -44,7 +56,8 @@ fn main() {
     .authors(env!("CARGO_PKG_AUTHORS").replace(":", ", "))
     .homepage(env!("CARGO_PKG_REPOSITORY")));
 
-    if let Err(e) = do_main() {
+    let result = do_main();
+    if let Err(e) = &result {
         eprintln!("Error: {:?}", e);
     }
 
@@ -53,42 +66,49 @@ fn main() {
     let _ = std::io::stdin()
         .read(&mut [0u8])
         .expect("can't wait for end of program");
+
+    if result.is_err() {
+        std::process::exit(1);
+    }
 }
 
 fn do_main() -> anyhow::Result<()> {
     let opts = Opts::parse();
 
+    // Doesn't need a controller
+    match &opts.cmd {
+        Some(opts::Cmd::LatencyTest(l)) => return latency::run(l),
+        Some(opts::Cmd::Cheatsheet(c)) => return cheatsheet(&c.mapping_file),
+        Some(opts::Cmd::Validate(v)) => return validate(&v.mapping_file),
+        _ => {}
+    }
+
     #[allow(unreachable_patterns)]
     let mut backend: Box<dyn Backend> = match opts.backend {
         #[cfg(feature = "sdl2")]
         Some(opts::Backend::Sdl) | None => Box::new(backend::sdl::SDLBackend::new()?),
         #[cfg(feature = "hidapi")]
         Some(opts::Backend::Hid) | None => Box::new(backend::hidapi::HidapiBackend::new()?),
+        #[cfg(feature = "gilrs")]
+        Some(opts::Backend::Gilrs) | None => Box::new(backend::gilrs::GilrsBackend::new()?),
         Some(_) | None => {
             bail!("A backend must be enabled");
         }
     };
 
-    let mut settings = Settings::default();
-    let mut bindings = Buttons::new();
+    let settings = Settings::default();
+    let bindings = Buttons::new();
 
     match opts.cmd {
-        Some(opts::Cmd::Validate(v)) => {
-            // TODO: factor this code with run
-            let mut content_file = File::open(&v.mapping_file)
-                .with_context(|| format!("opening config file {:?}", v.mapping_file))?;
-            let content = {
-                let mut buf = String::new();
-                content_file.read_to_string(&mut buf)?;
-                buf
-            };
-            let errors = config::parse_file(&content, &mut settings, &mut bindings);
-            print_errors(errors, &content);
-            Ok(())
-        }
-        Some(opts::Cmd::FlickCalibrate) => todo!(),
+        Some(opts::Cmd::FlickCalibrate(f)) => backend.flick_calibrate(&f),
         Some(opts::Cmd::Run(r)) => run(r, backend, settings, bindings),
         Some(opts::Cmd::List) => backend.list_devices(),
+        Some(opts::Cmd::Calibrate) => backend.calibrate(),
+        Some(opts::Cmd::LatencyTest(_))
+        | Some(opts::Cmd::Cheatsheet(_))
+        | Some(opts::Cmd::Validate(_)) => {
+            unreachable!("handled before the backend creation")
+        }
         None => {
             let default = {
                 let mut path = std::env::current_exe()?;
@@ -101,6 +121,15 @@ fn do_main() -> anyhow::Result<()> {
             run(
                 Run {
                     mapping_file: default,
+                    dry_run: false,
+                    no_gyro: false,
+                    watch: false,
+                    no_escape_quit: false,
+                    trace_file: None,
+                    controller_mappings: None,
+                    recalibrate: false,
+                    combine_joycons: false,
+                    poll_rate: 1000,
                 },
                 backend,
                 settings,
@@ -115,19 +144,80 @@ fn run(
     mut settings: Settings,
     mut bindings: Buttons,
 ) -> anyhow::Result<()> {
-    let mut content_file = File::open(&r.mapping_file)
-        .with_context(|| format!("opening config file {:?}", r.mapping_file))?;
-    let content = {
-        let mut buf = String::new();
-        content_file.read_to_string(&mut buf)?;
-        buf
-    };
-    let errors = config::parse_file(&content, &mut settings, &mut bindings);
-    print_errors(errors, &content);
+    let source = read_config(&r.mapping_file)?;
+    let errors = config::parse_source(&source, &mut settings, &mut bindings);
+    print_errors(errors, &source);
+    reload::install();
     backend.run(r, settings, bindings)
 }
 
-fn print_errors(errors: Vec<nom::Err<ErrorTree<&str>>>, content: &str) {
+fn read_config(path: &Path) -> anyhow::Result<config::Source> {
+    config::Source::read(path)
+}
+
+/// Validate a configuration file, or every `.txt` file in a directory tree.
+fn validate(path: &Path) -> anyhow::Result<()> {
+    let files = if path.is_dir() {
+        let mut files = Vec::new();
+        config_files(path, &mut files)?;
+        files
+    } else {
+        vec![path.to_owned()]
+    };
+
+    let mut failed = 0;
+    for file in &files {
+        // Keep going, to report every broken file
+        let source = match read_config(file) {
+            Ok(source) => source,
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}:", file.display());
+                eprintln!("Error: {:?}", e);
+                continue;
+            }
+        };
+        let errors = config::parse_source(&source, &mut Settings::default(), &mut Buttons::new());
+        if !errors.is_empty() {
+            failed += 1;
+            eprintln!("{}:", file.display());
+            print_errors(errors, &source);
+        }
+    }
+    println!("{} passed, {} failed", files.len() - failed, failed);
+    if failed > 0 {
+        bail!("{} invalid configuration file(s)", failed);
+    }
+    Ok(())
+}
+
+fn cheatsheet(path: &Path) -> anyhow::Result<()> {
+    let source = read_config(path)?;
+    let mut bindings = Buttons::new();
+    let errors = config::parse_source(&source, &mut Settings::default(), &mut bindings);
+    print_errors(errors, &source);
+    print!("{}", cheatsheet::cheatsheet(&bindings));
+    Ok(())
+}
+
+fn config_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("reading directory {:?}", dir))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            config_files(&path, files)?;
+        } else if path.extension().filter(|ext| *ext == "txt").is_some() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn print_errors(errors: Vec<nom::Err<ErrorTree<&str>>>, source: &config::Source) {
+    let content = source.content.as_str();
     for error in errors {
         match error {
             nom::Err::Incomplete(_) => todo!(),
@@ -139,7 +229,12 @@ fn print_errors(errors: Vec<nom::Err<ErrorTree<&str>>>, content: &str) {
                     content,
                     &location.map_locations(|l| {
                         let line = content.lines().nth(l.line - 1).expect("should not fail");
-                        format!("line {} column {} (\"{}\")", l.line, l.column, line)
+                        format!(
+                            "{} column {} (\"{}\")",
+                            source.location(l.line),
+                            l.column,
+                            line
+                        )
                     }),
                 );
             }
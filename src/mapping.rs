@@ -4,7 +4,10 @@ use hid_gamepad_types::JoyKey;
 use std::{collections::HashMap, fmt::Display, time::Duration};
 use std::{convert::TryInto, time::Instant};
 
-use crate::ClickType;
+use crate::{
+    config::types::{ButtonsSetting, GyroSetting},
+    ClickType,
+};
 
 #[derive(Debug, Copy, Clone)]
 pub enum Action {
@@ -14,6 +17,7 @@ pub enum Action {
 
 #[derive(Debug, Copy, Clone)]
 pub enum ExtAction {
+    /// Bound by `NONE`, does nothing but hides the bindings of lower layers.
     None,
     KeyPress(Key, ClickType),
     MousePress(Button, ClickType),
@@ -21,6 +25,14 @@ pub enum ExtAction {
     GamepadKeyPress(virtual_gamepad::Key, ClickType),
     GyroOn(ClickType),
     GyroOff(ClickType),
+    GyroInvertX(ClickType),
+    GyroInvertY(ClickType),
+    InvertY(ClickType),
+    ToggleActive(ClickType),
+    GyroRatchet(ClickType),
+    GyroTrackBall(ClickType),
+    /// Index in the profiles of the configuration, 0 being the unnamed part.
+    SwitchProfile(usize),
 }
 
 impl Display for ExtAction {
@@ -32,6 +44,33 @@ impl Display for ExtAction {
             ExtAction::GamepadKeyPress(k, t) => write!(f, "{:?} {:?}", t, k),
             ExtAction::GyroOn(t) => write!(f, "{:?} gyro on", t),
             ExtAction::GyroOff(t) => write!(f, "{:?} gyro off", t),
+            ExtAction::GyroInvertX(t) => write!(f, "{:?} gyro invert x", t),
+            ExtAction::GyroInvertY(t) => write!(f, "{:?} gyro invert y", t),
+            ExtAction::InvertY(t) => write!(f, "{:?} invert y", t),
+            ExtAction::ToggleActive(t) => write!(f, "{:?} toggle active", t),
+            ExtAction::GyroRatchet(t) => write!(f, "{:?} gyro ratchet", t),
+            ExtAction::GyroTrackBall(t) => write!(f, "{:?} gyro trackball", t),
+            ExtAction::SwitchProfile(i) => write!(f, "switch to profile {}", i),
+        }
+    }
+}
+
+impl ExtAction {
+    pub fn click_type(self) -> Option<ClickType> {
+        match self {
+            ExtAction::None | ExtAction::SwitchProfile(_) => None,
+            ExtAction::KeyPress(_, t)
+            | ExtAction::MousePress(_, t)
+            | ExtAction::GyroOn(t)
+            | ExtAction::GyroOff(t)
+            | ExtAction::GyroInvertX(t)
+            | ExtAction::GyroInvertY(t)
+            | ExtAction::InvertY(t)
+            | ExtAction::ToggleActive(t)
+            | ExtAction::GyroRatchet(t)
+            | ExtAction::GyroTrackBall(t) => Some(t),
+            #[cfg(feature = "vgamepad")]
+            ExtAction::GamepadKeyPress(_, t) => Some(t),
         }
     }
 }
@@ -73,6 +112,14 @@ pub struct Layer {
     pub on_double_click: Vec<Action>,
     pub on_hold_down: Vec<Action>,
     pub on_hold_up: Vec<Action>,
+    /// Alternate with `on_turbo_up` every half `Buttons::turbo_period` once
+    /// held.
+    pub on_turbo_down: Vec<Action>,
+    pub on_turbo_up: Vec<Action>,
+    /// Overrides `Buttons::hold_delay` for this binding.
+    pub hold_delay: Option<Duration>,
+    /// Time held before the turbo actions start, the hold delay by default.
+    pub turbo_delay: Option<Duration>,
 }
 
 impl Layer {
@@ -82,6 +129,7 @@ impl Layer {
             + self.on_click.len()
             + self.on_hold_down.len()
             + self.on_hold_up.len()
+            + self.on_turbo_down.len()
             + self.on_double_click.len()
             > 0
     }
@@ -89,25 +137,57 @@ impl Layer {
     fn is_simple_click(&self) -> bool {
         self.on_hold_down.is_empty()
             && self.on_hold_up.is_empty()
+            && self.on_turbo_down.is_empty()
             && self.on_double_click.is_empty()
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 struct KeyState {
     status: KeyStatus,
-    last_update: Instant,
+    /// Time of the last press or release, if any.
+    last_update: Option<Instant>,
+    /// Time of the last turbo press or release, if they started.
+    last_turbo: Option<Instant>,
+    /// Whether the turbo actions are in their pressed half.
+    turbo_down: bool,
+}
+
+impl KeyState {
+    /// Time since the last press or release, or forever if there was none.
+    fn elapsed(&self, now: Instant) -> Duration {
+        self.last_update
+            .map_or(Duration::MAX, |last| now.saturating_duration_since(last))
+    }
 }
 
 impl Default for KeyState {
     fn default() -> Self {
         KeyState {
             status: KeyStatus::Up,
-            last_update: Instant::now(),
+            last_update: None,
+            last_turbo: None,
+            turbo_down: false,
         }
     }
 }
 
+/// Binding of two keys pressed together, like `A+B`.
+#[derive(Debug, Clone)]
+struct Simul {
+    keys: [MapKey; 2],
+    binding: Layer,
+    state: KeyState,
+}
+
+/// Something with its own press state.
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Key(MapKey),
+    /// Index in `Buttons::simul`.
+    Simul(usize),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Enum)]
 pub enum VirtualKey {
     LUp,
@@ -125,6 +205,23 @@ pub enum VirtualKey {
     MLeft,
     MRight,
     MRing,
+    /// Pressed while the controller rotates faster than `GYRO_MOTION_THRESHOLD`.
+    GyroMove,
+    /// Controller orientation, see `LEAN_THRESHOLD` and `FACE_THRESHOLD`.
+    LeanLeft,
+    LeanRight,
+    FaceUp,
+    FaceDown,
+    /// Full pull of the analog triggers, see `ZL_MODE` and `ZR_MODE`.
+    ZLFull,
+    ZRFull,
+    /// Tapped when the controller connects and disconnects.
+    Connect,
+    Disconnect,
+    /// Pressed while a finger is on the touchpad.
+    Touch,
+    /// Click of the touchpad.
+    TouchpadClick,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -141,6 +238,15 @@ impl MapKey {
     }
 }
 
+impl Display for MapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapKey::Physical(k) => write!(f, "{:?}", k),
+            MapKey::Virtual(k) => write!(f, "{:?}", k),
+        }
+    }
+}
+
 const JOYKEY_SIZE: usize = <JoyKey as Enum>::LENGTH;
 const VIRTKEY_SIZE: usize = <VirtualKey as Enum>::LENGTH;
 const MAP_KEY_SIZE: usize = JOYKEY_SIZE + VIRTKEY_SIZE;
@@ -187,11 +293,26 @@ pub struct Buttons {
     bindings: EnumMap<MapKey, HashMap<u8, Layer>>,
     state: EnumMap<MapKey, KeyState>,
     current_layers: Vec<u8>,
+    /// Gyro settings applied while a layer is active.
+    layer_gyro: HashMap<u8, Vec<GyroSetting>>,
+    simul: Vec<Simul>,
+    /// Presses of keys used in a simultaneous binding, waiting for the other
+    /// key during `sim_press_window`.
+    simul_pending: Vec<(MapKey, Instant)>,
+    /// Keys that triggered a simultaneous binding and are still down.
+    simul_held: Vec<MapKey>,
 
     ext_actions: Vec<ExtAction>,
 
     pub hold_delay: Duration,
     pub double_click_interval: Duration,
+    /// Duration of a press and release cycle of turbo bindings.
+    pub turbo_period: Duration,
+    /// Presses this soon after a release of the same key are ignored.
+    pub debounce_time: Duration,
+    /// Both keys of a simultaneous binding must be pressed within this
+    /// window, else they act on their own.
+    pub sim_press_window: Duration,
 }
 
 impl Buttons {
@@ -200,9 +321,26 @@ impl Buttons {
             bindings: EnumMap::default(),
             state: EnumMap::default(),
             current_layers: vec![0],
+            layer_gyro: HashMap::new(),
+            simul: Vec::new(),
+            simul_pending: Vec::new(),
+            simul_held: Vec::new(),
             ext_actions: Vec::new(),
             hold_delay: Duration::from_millis(100),
             double_click_interval: Duration::from_millis(200),
+            turbo_period: Duration::from_millis(80),
+            debounce_time: Duration::ZERO,
+            sim_press_window: Duration::from_millis(50),
+        }
+    }
+
+    pub fn apply(&mut self, setting: ButtonsSetting) {
+        match setting {
+            ButtonsSetting::DebounceTime(d) => self.debounce_time = d,
+            ButtonsSetting::SimPressWindow(d) => self.sim_press_window = d,
+            ButtonsSetting::TurboPeriod(d) => self.turbo_period = d,
+            ButtonsSetting::HoldPressTime(d) => self.hold_delay = d,
+            ButtonsSetting::DblPressWindow(d) => self.double_click_interval = d,
         }
     }
 
@@ -210,49 +348,198 @@ impl Buttons {
         *self = Self::new();
     }
 
+    pub fn current_layers(&self) -> &[u8] {
+        &self.current_layers
+    }
+
+    pub fn set_current_layers(&mut self, layers: Vec<u8>) {
+        self.current_layers = layers;
+    }
+
+    pub fn add_layer_gyro_setting(&mut self, layer: u8, setting: GyroSetting) {
+        self.layer_gyro.entry(layer).or_default().push(setting);
+    }
+
+    /// Gyro settings of the active layers, in the order they were activated
+    /// so the most recent ones win.
+    pub fn layer_gyro_settings(&self) -> impl Iterator<Item = &GyroSetting> {
+        self.current_layers
+            .iter()
+            .filter_map(move |layer| self.layer_gyro.get(layer))
+            .flatten()
+    }
+
+    /// Every binding, as (key, layer, binding).
+    pub fn bindings(&self) -> impl Iterator<Item = (MapKey, u8, &Layer)> {
+        self.bindings.iter().flat_map(|(key, layers)| {
+            layers
+                .iter()
+                .map(move |(&layer, binding)| (key, layer, binding))
+        })
+    }
+
+    /// Bindings of keys pressed together.
+    pub fn simul_bindings(&self) -> impl Iterator<Item = ([MapKey; 2], &Layer)> {
+        self.simul.iter().map(|simul| (simul.keys, &simul.binding))
+    }
+
     pub fn get(&mut self, key: impl Into<MapKey>, layer: u8) -> &mut Layer {
         self.bindings[key.into()].entry(layer).or_default()
     }
 
+    /// Binding of `k1` and `k2` pressed together.
+    pub fn get_simul(&mut self, k1: impl Into<MapKey>, k2: impl Into<MapKey>) -> &mut Layer {
+        let keys = [k1.into(), k2.into()];
+        let i = match self.simul_index(keys[0], keys[1]) {
+            Some(i) => i,
+            None => {
+                self.simul.push(Simul {
+                    keys,
+                    binding: Layer::default(),
+                    state: KeyState::default(),
+                });
+                self.simul.len() - 1
+            }
+        };
+        &mut self.simul[i].binding
+    }
+
+    fn simul_index(&self, k1: MapKey, k2: MapKey) -> Option<usize> {
+        self.simul
+            .iter()
+            .position(|s| s.keys == [k1, k2] || s.keys == [k2, k1])
+    }
+
     pub fn tick(&mut self, now: Instant) -> impl Iterator<Item = ExtAction> + '_ {
+        self.flush_simul_pending(now);
         for key in (0..<MapKey as Enum>::LENGTH).map(<MapKey as Enum>::from_usize) {
-            let binding = self.find_binding(key);
-            match self.state[key].status {
-                KeyStatus::Down => {
-                    if !binding.on_hold_down.is_empty()
-                        && now.duration_since(self.state[key].last_update) >= self.hold_delay
-                    {
-                        Self::actions(
-                            &binding.on_hold_down,
-                            &mut self.current_layers,
-                            &mut self.ext_actions,
-                        );
-                        self.state[key].status = KeyStatus::Hold;
-                    }
+            self.tick_target(Target::Key(key), now);
+        }
+        for i in 0..self.simul.len() {
+            self.tick_target(Target::Simul(i), now);
+        }
+        self.ext_actions.drain(..)
+    }
+
+    fn tick_target(&mut self, target: Target, now: Instant) {
+        let binding = self.target_binding(target);
+        let mut state = *self.target_state(target);
+        let hold_delay = binding.hold_delay.unwrap_or(self.hold_delay);
+        if state.status.is_down() && !binding.on_turbo_down.is_empty() {
+            let turbo_delay = binding.turbo_delay.unwrap_or(hold_delay);
+            let toggle = match state.last_turbo {
+                None => state.elapsed(now) >= turbo_delay,
+                Some(last) => now.duration_since(last) >= self.turbo_period / 2,
+            };
+            if toggle {
+                Self::actions(
+                    if state.turbo_down {
+                        &binding.on_turbo_up
+                    } else {
+                        &binding.on_turbo_down
+                    },
+                    &mut self.current_layers,
+                    &mut self.ext_actions,
+                );
+                state.turbo_down = !state.turbo_down;
+                state.last_turbo = Some(now);
+            }
+        }
+        match state.status {
+            KeyStatus::Down => {
+                if !(binding.on_hold_down.is_empty() && binding.on_turbo_down.is_empty())
+                    && state.elapsed(now) >= hold_delay
+                {
+                    Self::actions(
+                        &binding.on_hold_down,
+                        &mut self.current_layers,
+                        &mut self.ext_actions,
+                    );
+                    state.status = KeyStatus::Hold;
                 }
-                KeyStatus::DoubleUp => {
-                    if now.duration_since(self.state[key].last_update) >= self.double_click_interval
-                    {
-                        Self::maybe_clicks(
-                            &binding,
-                            &mut self.current_layers,
-                            &mut self.ext_actions,
-                        );
-                        self.state[key].status = KeyStatus::Up;
-                    }
+            }
+            KeyStatus::DoubleUp => {
+                if state.elapsed(now) >= self.double_click_interval {
+                    Self::maybe_clicks(&binding, &mut self.current_layers, &mut self.ext_actions);
+                    state.status = KeyStatus::Up;
                 }
-                _ => (),
             }
+            _ => (),
         }
-        self.ext_actions.drain(..)
+        *self.target_state(target) = state;
     }
 
     pub fn key_down(&mut self, key: impl Into<MapKey>, now: Instant) {
         let key = key.into();
-        if self.state[key].status.is_down() {
+        self.flush_simul_pending(now);
+        if !self.simul.iter().any(|s| s.keys.contains(&key)) {
+            self.press(Target::Key(key), now);
+            return;
+        }
+        if self.state[key].status.is_down()
+            || self.simul_held.contains(&key)
+            || self.simul_pending.iter().any(|&(k, _)| k == key)
+        {
+            return;
+        }
+        let partner = self
+            .simul_pending
+            .iter()
+            .position(|&(k, _)| self.simul_index(k, key).is_some());
+        match partner {
+            Some(p) => {
+                let (other, _) = self.simul_pending.remove(p);
+                let i = self
+                    .simul_index(other, key)
+                    .expect("partner has a simultaneous binding");
+                self.simul_held.extend([other, key]);
+                self.press(Target::Simul(i), now);
+            }
+            None => self.simul_pending.push((key, now)),
+        }
+    }
+
+    pub fn key_up(&mut self, key: impl Into<MapKey>, now: Instant) {
+        let key = key.into();
+        self.flush_simul_pending(now);
+        if let Some(p) = self.simul_pending.iter().position(|&(k, _)| k == key) {
+            // Released before the other key came, it was a press of this key alone
+            let (_, pressed_at) = self.simul_pending.remove(p);
+            self.press(Target::Key(key), pressed_at);
+        } else if let Some(p) = self.simul_held.iter().position(|&k| k == key) {
+            // The first of the two keys to go up releases the binding
+            self.simul_held.remove(p);
+            if let Some(i) = self
+                .simul
+                .iter()
+                .position(|s| s.keys.contains(&key) && s.state.status.is_down())
+            {
+                self.release(Target::Simul(i), now);
+            }
             return;
         }
-        let binding = self.find_binding(key);
+        self.release(Target::Key(key), now);
+    }
+
+    /// Press alone the keys that waited too long for their simultaneous
+    /// partner, at the time they went down.
+    fn flush_simul_pending(&mut self, now: Instant) {
+        let window = self.sim_press_window;
+        let (expired, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.simul_pending)
+            .into_iter()
+            .partition(|&(_, pressed_at)| now.duration_since(pressed_at) >= window);
+        self.simul_pending = pending;
+        for (key, pressed_at) in expired {
+            self.press(Target::Key(key), pressed_at);
+        }
+    }
+
+    fn press(&mut self, target: Target, now: Instant) {
+        let mut state = *self.target_state(target);
+        if state.status.is_down() || state.elapsed(now) < self.debounce_time {
+            return;
+        }
+        let binding = self.target_binding(target);
         Self::actions(
             &binding.on_down,
             &mut self.current_layers,
@@ -261,10 +548,8 @@ impl Buttons {
         if binding.is_simple_click() {
             Self::maybe_clicks(&binding, &mut self.current_layers, &mut self.ext_actions);
         }
-        self.state[key].status = match self.state[key].status {
-            KeyStatus::DoubleUp
-                if now.duration_since(self.state[key].last_update) < self.double_click_interval =>
-            {
+        state.status = match state.status {
+            KeyStatus::DoubleUp if state.elapsed(now) < self.double_click_interval => {
                 KeyStatus::DoubleDown
             }
             KeyStatus::DoubleUp => {
@@ -274,27 +559,39 @@ impl Buttons {
             KeyStatus::Up => KeyStatus::Down,
             _ => unreachable!(),
         };
-        self.state[key].last_update = now;
+        state.last_update = Some(now);
+        *self.target_state(target) = state;
     }
 
-    pub fn key_up(&mut self, key: impl Into<MapKey>, now: Instant) {
-        let key = key.into();
-        if self.state[key].status.is_up() {
+    fn release(&mut self, target: Target, now: Instant) {
+        let mut state = *self.target_state(target);
+        if state.status.is_up() {
             return;
         }
-        let binding = self.find_binding(key);
+        let binding = self.target_binding(target);
         Self::actions(
             &binding.on_up,
             &mut self.current_layers,
             &mut self.ext_actions,
         );
+        // Don't leave a turbo key pressed
+        if state.turbo_down {
+            Self::actions(
+                &binding.on_turbo_up,
+                &mut self.current_layers,
+                &mut self.ext_actions,
+            );
+            state.turbo_down = false;
+        }
+        state.last_turbo = None;
         let mut new_status = KeyStatus::Up;
         if !binding.is_simple_click() {
-            if binding.on_hold_up.is_empty()
-                || now.duration_since(self.state[key].last_update) < self.hold_delay
+            // Turbo bindings don't click once held
+            if (binding.on_hold_up.is_empty() && binding.on_turbo_down.is_empty())
+                || state.elapsed(now) < binding.hold_delay.unwrap_or(self.hold_delay)
             {
                 if !binding.on_double_click.is_empty() {
-                    match self.state[key].status {
+                    match state.status {
                         KeyStatus::DoubleDown => {
                             Self::actions(
                                 &binding.on_double_click,
@@ -319,8 +616,23 @@ impl Buttons {
                 );
             }
         }
-        self.state[key].status = new_status;
-        self.state[key].last_update = now;
+        state.status = new_status;
+        state.last_update = Some(now);
+        *self.target_state(target) = state;
+    }
+
+    fn target_state(&mut self, target: Target) -> &mut KeyState {
+        match target {
+            Target::Key(key) => &mut self.state[key],
+            Target::Simul(i) => &mut self.simul[i].state,
+        }
+    }
+
+    fn target_binding(&self, target: Target) -> Layer {
+        match target {
+            Target::Key(key) => self.find_binding(key),
+            Target::Simul(i) => self.simul[i].binding.clone(),
+        }
     }
 
     pub fn key(&mut self, key: impl Into<MapKey>, pressed: bool, now: Instant) {
@@ -444,4 +756,24 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn debounce() {
+        let mut mapping = Buttons::new();
+        mapping.debounce_time = Duration::from_millis(20);
+        mapping
+            .get(JoyKey::S, 0)
+            .on_down
+            .push(Action::Ext(ExtAction::KeyPress(Key::Alt, ClickType::Press)));
+
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        let mut presses = 0;
+        for (i, t) in [ms(0), ms(5), ms(10), ms(40), ms(45)].iter().enumerate() {
+            mapping.key_down(JoyKey::S, t0 + *t);
+            mapping.key_up(JoyKey::S, t0 + *t + ms(1));
+            presses += mapping.tick(t0 + *t).count();
+            assert_eq!(presses, if i < 3 { 1 } else { 2 });
+        }
+    }
 }
@@ -1,7 +1,10 @@
-use cgmath::{vec2, ElementWise, InnerSpace, Rad};
+use cgmath::{vec2, ElementWise, InnerSpace, Rad, Vector2, Vector3, Zero};
 
 use crate::{
-    config::settings::Settings,
+    config::{
+        settings::Settings,
+        types::{TiltAxis, TiltSource},
+    },
     joystick::{Stick, StickSide},
 };
 
@@ -27,24 +30,111 @@ impl MotionStick {
         now: std::time::Instant,
         dt: std::time::Duration,
     ) {
-        let up_vector = up_vector.normalize();
-        let mut stick = vec2(-up_vector.x.asin(), up_vector.z.asin())
+        let mut stick = tilt_stick(up_vector, settings.stick.motion.axis_map)
             .mul_element_wise(settings.stick.motion.axis.cast().expect("cannot fail"));
 
-        //let deadzone = Rad::from(settings.stick.motion.deadzone).0;
-        let deadzone = 0.;
+        let deadzone = Rad::from(settings.stick.motion.deadzone).0;
         let fullzone = Rad::from(settings.stick.motion.fullzone).0;
         let amp = stick.magnitude();
         let amp_zones = (amp - deadzone) / (fullzone - deadzone);
         let amp_clamped = amp_zones.max(0.).min(1.);
-        if amp_clamped > 0. {
-            stick = stick.normalize_to(amp_clamped);
-        }
+        stick = if amp_clamped > 0. {
+            stick.normalize_to(amp_clamped)
+        } else {
+            Vector2::zero()
+        };
 
-        // TODO: Fix motion stick deadzone usage
-        // `stick.handle` will apply its own deadzone setting to our calibrated input,
-        // thinking it's a raw value.
+        // Already rescaled between the deadzones, `StickSide::Motion` makes
+        // the stick modes skip their own.
         self.stick
             .handle(stick, StickSide::Motion, settings, bindings, mouse, now, dt)
     }
 }
+
+/// Stick position from the controller tilt, in radians.
+fn tilt_stick(up_vector: Vector3<f64>, axis_map: Vector2<TiltSource>) -> Vector2<f64> {
+    let up_vector = up_vector.normalize();
+    let tilt = |source: TiltSource| {
+        let angle = match source.axis {
+            TiltAxis::Roll => -up_vector.x.asin(),
+            TiltAxis::Pitch => up_vector.z.asin(),
+        };
+        if source.inverted {
+            -angle
+        } else {
+            angle
+        }
+    };
+    vec2(tilt(axis_map.x), tilt(axis_map.y))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use cgmath::{vec3, Deg};
+
+    use super::*;
+    use crate::{
+        mapping::{Action, Buttons, ExtAction, VirtualKey},
+        mouse::Mouse,
+        ClickType,
+    };
+
+    fn source(axis: TiltAxis, inverted: bool) -> TiltSource {
+        TiltSource { axis, inverted }
+    }
+
+    #[test]
+    fn axis_map() {
+        use TiltAxis::*;
+
+        // Rolled to the right
+        let up = vec3(-0.6, 0.8, 0.);
+        let roll = 0.6f64.asin();
+
+        let standard = vec2(source(Roll, false), source(Pitch, false));
+        assert!((tilt_stick(up, standard) - vec2(roll, 0.)).magnitude() < 1e-9);
+
+        let swapped = vec2(source(Pitch, false), source(Roll, true));
+        assert!((tilt_stick(up, swapped) - vec2(0., -roll)).magnitude() < 1e-9);
+
+        // Pitched forward
+        let up = vec3(0., 0.8, 0.6);
+        assert!((tilt_stick(up, swapped) - vec2(roll, 0.)).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn single_deadzone() {
+        let mut settings = Settings::default();
+        // Would swallow the first half of the tilt if applied again
+        settings.stick.deadzone = 0.5;
+        let rolled_right = |angle: f64| {
+            let now = Instant::now();
+            let mut bindings = Buttons::new();
+            bindings
+                .get(VirtualKey::MRight, 0)
+                .on_down
+                .push(Action::Ext(ExtAction::KeyPress(
+                    enigo::Key::Unicode('d'),
+                    ClickType::Press,
+                )));
+            let angle = Rad::from(Deg(angle)).0;
+            MotionStick::new(&settings).handle(
+                vec3(-angle.sin(), angle.cos(), 0.),
+                &settings,
+                &mut bindings,
+                &mut Mouse::dry_run(),
+                now,
+                Duration::ZERO,
+            );
+            let pressed = bindings.tick(now).count() > 0;
+            pressed
+        };
+
+        // MOTION_DEADZONE_INNER is 15° and MOTION_DEADZONE_OUTER 45°
+        assert!(!rolled_right(10.));
+        assert!(rolled_right(20.));
+        assert!(rolled_right(60.));
+    }
+}
@@ -1,9 +1,9 @@
-use std::ops::AddAssign;
+use std::{fmt::Debug, ops::AddAssign, time::Instant};
 
 use cgmath::{vec2, Deg, Vector2, Zero};
-use enigo::{Coordinate, Enigo, Mouse as _};
+use enigo::{Axis, Button, Coordinate, Direction, Enigo, Key, Mouse as _};
 
-use crate::config::settings::MouseSettings;
+use crate::{config::settings::MouseSettings, mapping::ExtAction};
 
 #[derive(Debug, Clone, Copy)]
 pub struct MouseMovement {
@@ -27,6 +27,9 @@ impl MouseMovement {
             y: Deg(vec.y),
         }
     }
+    pub fn to_vec_deg(self) -> Vector2<f64> {
+        vec2(self.x.0, self.y.0)
+    }
 }
 
 impl AddAssign for MouseMovement {
@@ -36,48 +39,356 @@ impl AddAssign for MouseMovement {
     }
 }
 
+/// Destination of the generated keyboard and mouse events.
+///
+/// Events are dropped by default, so a sink only implements what it uses.
+pub trait MouseSink: Debug {
+    fn move_relative(&mut self, _offset: Vector2<i32>) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn move_absolute(&mut self, _location: Vector2<i32>) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn scroll(&mut self, _length: i32, _axis: Axis) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn key(&mut self, _key: Key, _direction: Direction) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn button(&mut self, _button: Button, _direction: Direction) -> anyhow::Result<()> {
+        Ok(())
+    }
+    /// Action of a binding with no keyboard or mouse output, like a gyro
+    /// toggle.
+    fn action(&mut self, _action: &ExtAction) {}
+}
+
+impl MouseSink for Enigo {
+    fn move_relative(&mut self, offset: Vector2<i32>) -> anyhow::Result<()> {
+        Ok(self.move_mouse(offset.x, offset.y, Coordinate::Rel)?)
+    }
+
+    fn move_absolute(&mut self, location: Vector2<i32>) -> anyhow::Result<()> {
+        Ok(self.move_mouse(location.x, location.y, Coordinate::Abs)?)
+    }
+
+    fn scroll(&mut self, length: i32, axis: Axis) -> anyhow::Result<()> {
+        Ok(enigo::Mouse::scroll(self, length, axis)?)
+    }
+
+    fn key(&mut self, key: Key, direction: Direction) -> anyhow::Result<()> {
+        Ok(enigo::Keyboard::key(self, key, direction)?)
+    }
+
+    fn button(&mut self, button: Button, direction: Direction) -> anyhow::Result<()> {
+        Ok(enigo::Mouse::button(self, button, direction)?)
+    }
+}
+
+/// Prints every event with its timing instead of sending it to the OS.
+#[derive(Debug)]
+pub struct PrintSink {
+    start: Instant,
+}
+
+impl PrintSink {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    fn print(&self, event: std::fmt::Arguments) {
+        println!("[{:>10.3}s] {}", self.start.elapsed().as_secs_f64(), event);
+    }
+}
+
+impl MouseSink for PrintSink {
+    fn move_relative(&mut self, offset: Vector2<i32>) -> anyhow::Result<()> {
+        self.print(format_args!("move by {} {}", offset.x, offset.y));
+        Ok(())
+    }
+
+    fn move_absolute(&mut self, location: Vector2<i32>) -> anyhow::Result<()> {
+        self.print(format_args!("move to {} {}", location.x, location.y));
+        Ok(())
+    }
+
+    fn scroll(&mut self, length: i32, axis: Axis) -> anyhow::Result<()> {
+        self.print(format_args!("scroll {:?} {}", axis, length));
+        Ok(())
+    }
+
+    fn key(&mut self, key: Key, direction: Direction) -> anyhow::Result<()> {
+        self.print(format_args!("{:?} {:?}", direction, key));
+        Ok(())
+    }
+
+    fn button(&mut self, button: Button, direction: Direction) -> anyhow::Result<()> {
+        self.print(format_args!("{:?} {:?}", direction, button));
+        Ok(())
+    }
+
+    fn action(&mut self, action: &ExtAction) {
+        self.print(format_args!("{}", action));
+    }
+}
+
+/// Drops every event.
+#[derive(Debug)]
+struct NullSink;
+
+impl MouseSink for NullSink {}
+
 #[derive(Debug)]
 pub struct Mouse {
-    enigo: Enigo,
+    sink: Box<dyn MouseSink>,
     error_accumulator: Vector2<f64>,
+    /// Multiplier applied by the OS to relative movements, countered with
+    /// `COUNTER_OS_MOUSE_SPEED`.
+    os_speed: f64,
 }
 
 impl Mouse {
     pub fn new() -> anyhow::Result<Self> {
-        Ok(Mouse {
-            enigo: Enigo::new(&enigo::Settings::default())?,
+        let mut mouse = Self::with_sink(Box::new(Enigo::new(&enigo::Settings::default())?));
+        mouse.os_speed = os::pointer_speed();
+        if os::pointer_acceleration() {
+            eprintln!(
+                "Warning: the OS pointer acceleration (\"Enhance pointer precision\") is \
+                 enabled, mouse movements won't match the configured sensitivity"
+            );
+        }
+        Ok(mouse)
+    }
+
+    /// Print the events instead of emitting them.
+    pub fn dry_run() -> Self {
+        Self::with_sink(Box::new(PrintSink::new()))
+    }
+
+    /// Drop the events, for an output that is suppressed.
+    pub fn muted() -> Self {
+        Self::with_sink(Box::new(NullSink))
+    }
+
+    pub fn with_sink(sink: Box<dyn MouseSink>) -> Self {
+        Mouse {
+            sink,
             error_accumulator: Vector2::zero(),
-        })
+            os_speed: 1.,
+        }
     }
 
     // mouse movement is pixel perfect, so we keep track of the error.
     pub fn mouse_move_relative(&mut self, settings: &MouseSettings, offset: MouseMovement) {
-        let offset_pixel =
+        let mut offset_pixel =
             vec2(offset.x.0, -offset.y.0) * settings.real_world_calibration * settings.in_game_sens;
-        self.mouse_move_relative_pixel(offset_pixel);
+        if settings.invert_y {
+            offset_pixel.y = -offset_pixel.y;
+        }
+        if settings.counter_os_speed {
+            offset_pixel /= self.os_speed;
+        }
+        self.mouse_move_relative_pixel(offset_pixel, settings.min_move);
     }
 
-    pub fn mouse_move_relative_pixel(&mut self, offset: Vector2<f64>) {
+    /// Movements smaller than `min_move` pixels coming from a near zero input
+    /// are kept in the accumulator instead of being emitted, to avoid jitter
+    /// at rest.
+    pub fn mouse_move_relative_pixel(&mut self, offset: Vector2<f64>, min_move: f64) {
         let sum = offset + self.error_accumulator;
         let rounded = vec2(sum.x.round(), sum.y.round());
+        let max = |v: Vector2<f64>| v.x.abs().max(v.y.abs());
+        if max(offset) < min_move && max(rounded) < min_move {
+            self.error_accumulator = sum;
+            return;
+        }
         self.error_accumulator = sum - rounded;
         if let Some(rounded) = rounded.cast::<i32>() {
             if rounded != Vector2::zero() {
                 // In enigo, +y is toward the bottom
-                self.enigo
-                    .move_mouse(rounded.x, rounded.y, Coordinate::Rel)
-                    .unwrap();
+                self.sink.move_relative(rounded).unwrap();
             }
         }
     }
 
     pub fn mouse_move_absolute_pixel(&mut self, offset: Vector2<i32>) {
-        self.enigo
-            .move_mouse(offset.x, offset.y, Coordinate::Abs)
-            .unwrap();
+        self.sink.move_absolute(offset).unwrap();
+    }
+
+    pub fn scroll(&mut self, length: i32, axis: Axis) -> anyhow::Result<()> {
+        self.sink.scroll(length, axis)
+    }
+
+    pub fn key(&mut self, key: Key, direction: Direction) -> anyhow::Result<()> {
+        self.sink.key(key, direction)
+    }
+
+    pub fn button(&mut self, button: Button, direction: Direction) -> anyhow::Result<()> {
+        self.sink.button(button, direction)
+    }
+
+    pub fn action(&mut self, action: &ExtAction) {
+        self.sink.action(action)
     }
+}
+
+#[cfg(windows)]
+mod os {
+    use std::{
+        convert::TryFrom,
+        os::raw::{c_int, c_uint, c_void},
+    };
+
+    const SPI_GETMOUSE: c_uint = 0x0003;
+    const SPI_GETMOUSESPEED: c_uint = 0x0070;
+
+    /// Multiplier of each pointer speed of the control panel, from 1 to 20.
+    const SPEED_FACTORS: [f64; 20] = [
+        1. / 32.,
+        1. / 16.,
+        1. / 8.,
+        2. / 8.,
+        3. / 8.,
+        4. / 8.,
+        5. / 8.,
+        6. / 8.,
+        7. / 8.,
+        1.,
+        1.25,
+        1.5,
+        1.75,
+        2.,
+        2.25,
+        2.5,
+        2.75,
+        3.,
+        3.25,
+        3.5,
+    ];
+
+    #[link(name = "user32")]
+    extern "system" {
+        fn SystemParametersInfoW(
+            action: c_uint,
+            param: c_uint,
+            value: *mut c_void,
+            win_ini: c_uint,
+        ) -> c_int;
+    }
+
+    pub fn pointer_speed() -> f64 {
+        let mut speed: c_int = 10;
+        let ok = unsafe {
+            SystemParametersInfoW(SPI_GETMOUSESPEED, 0, &mut speed as *mut _ as *mut c_void, 0)
+        };
+        match usize::try_from(speed - 1) {
+            Ok(i) if ok != 0 && i < SPEED_FACTORS.len() => SPEED_FACTORS[i],
+            _ => 1.,
+        }
+    }
+
+    /// Whether "Enhance pointer precision" is enabled.
+    pub fn pointer_acceleration() -> bool {
+        // Two thresholds and the acceleration
+        let mut params: [c_int; 3] = [0; 3];
+        let ok = unsafe {
+            SystemParametersInfoW(SPI_GETMOUSE, 0, params.as_mut_ptr() as *mut c_void, 0)
+        };
+        ok != 0 && params[2] != 0
+    }
+}
+
+/// Other OSes don't scale the relative movements sent by enigo.
+#[cfg(not(windows))]
+mod os {
+    pub fn pointer_speed() -> f64 {
+        1.
+    }
+
+    pub fn pointer_acceleration() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    /// Records relative moves so tests can inspect them.
+    #[derive(Debug, Default)]
+    pub struct MoveRecorder(pub Rc<RefCell<Vec<Vector2<i32>>>>);
+
+    impl MouseSink for MoveRecorder {
+        fn move_relative(&mut self, offset: Vector2<i32>) -> anyhow::Result<()> {
+            self.0.borrow_mut().push(offset);
+            Ok(())
+        }
+    }
+
+    /// A mouse recording its relative moves.
+    pub fn recording_mouse() -> (Mouse, Rc<RefCell<Vec<Vector2<i32>>>>) {
+        let moves = Rc::new(RefCell::new(Vec::new()));
+        (
+            Mouse::with_sink(Box::new(MoveRecorder(moves.clone()))),
+            moves,
+        )
+    }
+
+    /// Records key and mouse button events as text, like `Press Return`.
+    #[derive(Debug, Default)]
+    pub struct PressRecorder(pub Rc<RefCell<Vec<String>>>);
+
+    impl MouseSink for PressRecorder {
+        fn key(&mut self, key: Key, direction: Direction) -> anyhow::Result<()> {
+            self.0
+                .borrow_mut()
+                .push(format!("{:?} {:?}", direction, key));
+            Ok(())
+        }
+
+        fn button(&mut self, button: Button, direction: Direction) -> anyhow::Result<()> {
+            self.0
+                .borrow_mut()
+                .push(format!("{:?} {:?}", direction, button));
+            Ok(())
+        }
+    }
+
+    /// A mouse recording its key and mouse button events.
+    pub fn press_recording_mouse() -> (Mouse, Rc<RefCell<Vec<String>>>) {
+        let presses = Rc::new(RefCell::new(Vec::new()));
+        (
+            Mouse::with_sink(Box::new(PressRecorder(presses.clone()))),
+            presses,
+        )
+    }
+
+    #[test]
+    fn min_move_keeps_accumulation() {
+        let (mut mouse, moves) = recording_mouse();
+
+        mouse.mouse_move_relative_pixel(vec2(0.6, 0.), 2.);
+        mouse.mouse_move_relative_pixel(vec2(0.6, 0.), 2.);
+        assert!(moves.borrow().is_empty());
+
+        mouse.mouse_move_relative_pixel(vec2(0.6, 0.), 2.);
+        assert_eq!(*moves.borrow(), vec![vec2(2, 0)]);
+    }
+
+    #[test]
+    fn counter_os_speed() {
+        let (mut mouse, moves) = recording_mouse();
+        mouse.os_speed = 2.;
+        let mut settings = MouseSettings::default();
+        let offset = MouseMovement::new(Deg(10.), Deg(0.));
 
-    pub fn enigo(&mut self) -> &mut Enigo {
-        &mut self.enigo
+        mouse.mouse_move_relative(&settings, offset);
+        settings.counter_os_speed = true;
+        mouse.mouse_move_relative(&settings, offset);
+        assert_eq!(*moves.borrow(), vec![vec2(10, 0), vec2(5, 0)]);
     }
 }
@@ -1,4 +1,4 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{path::PathBuf, str::FromStr, time::Duration};
 
 use clap::{Parser, ValueEnum};
 
@@ -22,25 +22,99 @@ pub enum Backend {
     Sdl,
     #[cfg(feature = "hidapi")]
     Hid,
+    #[cfg(feature = "gilrs")]
+    Gilrs,
 }
 
 #[derive(Debug, Parser)]
 pub enum Cmd {
-    /// Validate the syntax of a configuration file.
+    /// Validate the syntax of a configuration file, or of every `.txt` file in
+    /// a directory.
     Validate(Run),
     /// Compute the value of REAL_WORLD_CALIBRATION.
-    #[command(hide = true)]
-    FlickCalibrate,
+    FlickCalibrate(FlickCalibrate),
     /// Run the program using the specified configuration file.
     Run(Run),
+    /// Measure the gyro and analog triggers calibration of a controller.
+    Calibrate,
     /// List connected gamepads.
     List,
+    /// Measure the time from a button event to the emitted key press,
+    /// without a controller.
+    LatencyTest(LatencyTest),
+    /// Print a summary of the bindings of a configuration file, by layer.
+    Cheatsheet(Cheatsheet),
 }
 
 #[derive(Debug, Parser)]
 pub struct Run {
     /// Configuration file to use.
     pub mapping_file: PathBuf,
+    /// Print the generated keyboard and mouse events instead of sending them.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Disable the motion sensors, only remapping buttons and sticks.
+    #[arg(long)]
+    pub no_gyro: bool,
+    /// Reload the configuration file when it changes.
+    #[arg(long)]
+    pub watch: bool,
+    /// Don't quit when Escape is pressed.
+    #[arg(long)]
+    pub no_escape_quit: bool,
+    /// Write every input event and emitted action to a JSONL file, for bug
+    /// reports.
+    #[arg(long)]
+    pub trace_file: Option<PathBuf>,
+    /// SDL controller mappings to load, in the `gamecontrollerdb.txt` format.
+    ///
+    /// Loaded again with the configuration on `SIGHUP`, to fix the layout of
+    /// a misdetected controller without restarting.
+    #[arg(long)]
+    pub controller_mappings: Option<PathBuf>,
+    /// Calibrate the gyro again when a controller reconnects, instead of
+    /// reusing its previous calibration.
+    #[arg(long)]
+    pub recalibrate: bool,
+    /// Use a left and a right JoyCon as a single controller, with the
+    /// hidapi backend. A lone half is used alone.
+    #[arg(long)]
+    pub combine_joycons: bool,
+    /// Rate of the main loop, in Hz.
+    ///
+    /// Each frame reads the controller and moves the mouse, so a lower rate
+    /// uses less CPU but adds up to a frame of latency. Only for the SDL and
+    /// gilrs backends, hidapi follows the controller reports.
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    pub poll_rate: u32,
+}
+
+impl Run {
+    /// Time between two frames of the main loop.
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.poll_rate
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct FlickCalibrate {
+    /// Mouse counts sent per degree of controller rotation during the
+    /// measure. Raise it if the in-game turn needs too many controller turns.
+    #[arg(long, default_value_t = 10.)]
+    pub counts_per_degree: f64,
+}
+
+#[derive(Debug, Parser)]
+pub struct LatencyTest {
+    /// Number of button presses, each followed by a release.
+    #[arg(long, default_value_t = 10000)]
+    pub iterations: u32,
+}
+
+#[derive(Debug, Parser)]
+pub struct Cheatsheet {
+    /// Configuration file to summarize.
+    pub mapping_file: PathBuf,
 }
 
 impl FromStr for Backend {
@@ -52,6 +126,8 @@ impl FromStr for Backend {
             "sdl" => Ok(Backend::Sdl),
             #[cfg(feature = "hidapi")]
             "hid" => Ok(Backend::Hid),
+            #[cfg(feature = "gilrs")]
+            "gilrs" => Ok(Backend::Gilrs),
             _ => Err(format!("unknown backend: {}", s)),
         }
     }
@@ -0,0 +1,209 @@
+//! Configuration reload on `SIGHUP`, or when the file changes with
+//! `run --watch`.
+//!
+//! The signal handler only sets a flag, the backends poll it in their run
+//! loop with `requested` and reload from there.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{config, config::settings::Settings, mapping::Buttons};
+
+#[cfg(unix)]
+mod imp {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use libc::c_int;
+
+    static RELOAD: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_sighup(_: c_int) {
+        RELOAD.store(true, Ordering::Relaxed);
+    }
+
+    /// Reload the configuration when receiving `SIGHUP`.
+    pub fn install() {
+        // Safety: the handler only touches an atomic, which is signal safe.
+        unsafe {
+            libc::signal(
+                libc::SIGHUP,
+                on_sighup as extern "C" fn(c_int) as libc::sighandler_t,
+            );
+        }
+    }
+
+    /// Whether a reload was asked since the last call.
+    pub fn requested() -> bool {
+        RELOAD.swap(false, Ordering::Relaxed)
+    }
+}
+
+// No equivalent signal on Windows.
+#[cfg(not(unix))]
+mod imp {
+    pub fn install() {}
+
+    pub fn requested() -> bool {
+        false
+    }
+}
+
+pub use imp::{install, requested};
+
+/// Interval between two checks of the configuration file with `--watch`.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Polls the modification time of the configuration file and of the files
+/// it includes.
+#[derive(Debug)]
+pub struct Watcher {
+    path: PathBuf,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    next_check: Instant,
+}
+
+impl Watcher {
+    pub fn new(path: &Path, now: Instant) -> Self {
+        Self {
+            path: path.to_owned(),
+            files: watched_files(path),
+            next_check: now + WATCH_INTERVAL,
+        }
+    }
+
+    /// Whether one of the files changed since the last call, checking at most
+    /// once per `WATCH_INTERVAL`.
+    pub fn changed(&mut self, now: Instant) -> bool {
+        if now < self.next_check {
+            return false;
+        }
+        self.next_check = now + WATCH_INTERVAL;
+        // A missing file is reported by the reload once it's back
+        let changed = self.files.iter().any(|(path, last)| {
+            let modified = modified(path);
+            modified.is_some() && modified != *last
+        });
+        if changed {
+            // The includes may have changed too
+            self.files = watched_files(&self.path);
+        }
+        changed
+    }
+}
+
+/// `path` and its includes, with their modification time.
+fn watched_files(path: &Path) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let files = match config::Source::read(path) {
+        Ok(source) => source.files().to_vec(),
+        // Watch at least the main file until it's fixed
+        Err(_) => vec![path.to_owned()],
+    };
+    files
+        .into_iter()
+        .map(|file| {
+            let modified = modified(&file);
+            (file, modified)
+        })
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Parse the configuration file again, printing the errors.
+///
+/// Returns `None` if it's invalid, so the caller can keep the previous one.
+pub fn load(path: &Path) -> Option<(Settings, Buttons)> {
+    let source = match crate::read_config(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            eprintln!("Keeping the previous configuration");
+            return None;
+        }
+    };
+    let mut settings = Settings::default();
+    let mut bindings = Buttons::new();
+    let errors = config::parse_source(&source, &mut settings, &mut bindings);
+    if !errors.is_empty() {
+        crate::print_errors(errors, &source);
+        eprintln!("Keeping the previous configuration");
+        return None;
+    }
+    println!("Configuration reloaded from {:?}", path);
+    Some((settings, bindings))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keep_invalid_config() {
+        let path = std::env::temp_dir().join(format!("gyromouse-reload-{}", std::process::id()));
+        std::fs::write(&path, "GYRO_SENS = 2").unwrap();
+        let loaded = load(&path);
+        std::fs::write(&path, "GYRO_SENS = two").unwrap();
+        let invalid = load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap().0.gyro.sens.x, 2.);
+        assert!(invalid.is_none());
+        assert!(load(&path).is_none());
+    }
+
+    #[test]
+    fn watch() {
+        let path = std::env::temp_dir().join(format!("gyromouse-watch-{}", std::process::id()));
+        std::fs::write(&path, "GYRO_SENS = 2").unwrap();
+        let now = Instant::now();
+        let mut watcher = Watcher::new(&path, now);
+        let touch = |secs| {
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::now() + Duration::from_secs(secs))
+                .unwrap();
+        };
+
+        assert!(!watcher.changed(now + WATCH_INTERVAL));
+        touch(10);
+        // Not checked again yet
+        assert!(!watcher.changed(now + WATCH_INTERVAL));
+        assert!(watcher.changed(now + WATCH_INTERVAL * 2));
+        assert!(!watcher.changed(now + WATCH_INTERVAL * 3));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed(now + WATCH_INTERVAL * 4));
+        std::fs::write(&path, "GYRO_SENS = 3").unwrap();
+        touch(20);
+        assert!(watcher.changed(now + WATCH_INTERVAL * 5));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn watch_includes() {
+        let dir = std::env::temp_dir().join(format!("gyromouse-watch-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.txt");
+        let included = dir.join("gyro.txt");
+        std::fs::write(&path, "INCLUDE gyro.txt").unwrap();
+        std::fs::write(&included, "GYRO_SENS = 2").unwrap();
+        let now = Instant::now();
+        let mut watcher = Watcher::new(&path, now);
+
+        std::fs::File::options()
+            .write(true)
+            .open(&included)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        let changed = watcher.changed(now + WATCH_INTERVAL);
+        let unchanged = watcher.changed(now + WATCH_INTERVAL * 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(changed);
+        assert!(!unchanged);
+    }
+}
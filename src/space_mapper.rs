@@ -12,6 +12,13 @@ pub fn map_input(
     let up_vector = sensor_fusion.compute_up_vector(motion, dt);
     mapper.map(motion.rotation_speed, up_vector)
 }
+
+/// Accelerations below this, in g, don't tell where the gravity is.
+///
+/// Happens in free fall or with a glitching sensor, and would make the up
+/// vector NaN for the rest of the session.
+const MIN_ACCELERATION: f64 = 0.01;
+
 pub trait SensorFusion {
     fn up_vector(&self) -> Vector3<f64>;
     fn compute_up_vector(&mut self, motion: &Motion, dt: Duration) -> Vector3<f64>;
@@ -25,14 +32,17 @@ pub trait SpaceMapper {
 #[derive(Debug, Copy, Clone)]
 pub struct SimpleFusion {
     up_vector: Vector3<f64>,
-    correction_factor: f64,
+    /// Time constant of the pull of the up vector toward the acceleration,
+    /// so the correction is the same whatever the sample rate.
+    correction_time: Duration,
 }
 
 impl SimpleFusion {
     pub fn new() -> Self {
         Self {
             up_vector: vec3(0., 1., 0.),
-            correction_factor: 0.02,
+            // Matches the previous 2% per sample at 200Hz
+            correction_time: Duration::from_millis(250),
         }
     }
 }
@@ -44,8 +54,11 @@ impl SensorFusion for SimpleFusion {
     fn compute_up_vector(&mut self, motion: &Motion, dt: Duration) -> Vector3<f64> {
         let rotation = Quaternion::from(motion.rotation_speed * dt).invert();
         self.up_vector = rotation.rotate_vector(self.up_vector);
-        // TODO: Make the correction rate depend on dt instead of fixed per tick.
-        self.up_vector += (motion.acceleration.as_vec() - self.up_vector) * self.correction_factor;
+        let acceleration = motion.acceleration.as_vec();
+        if acceleration.magnitude() >= MIN_ACCELERATION {
+            let factor = 1. - (-dt.as_secs_f64() / self.correction_time.as_secs_f64()).exp();
+            self.up_vector += (acceleration - self.up_vector) * factor;
+        }
         self.up_vector
     }
 }
@@ -58,7 +71,6 @@ pub struct AdaptativeFusion {
 }
 
 impl AdaptativeFusion {
-    #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
             shakiness: 0.,
@@ -75,7 +87,6 @@ impl SensorFusion for AdaptativeFusion {
     }
 
     // TODO: check http://gyrowiki.jibbsmart.com/blog:finding-gravity-with-sensor-fusion
-    // TODO: check normalize() with magnitude 0.
     fn compute_up_vector(&mut self, motion: &Motion, dt: Duration) -> Vector3<f64> {
         // settings
         let smoothing_half_time = 0.25;
@@ -106,6 +117,9 @@ impl SensorFusion for AdaptativeFusion {
         self.smooth_accel = acc.as_vec().lerp(self.smooth_accel, smooth_interpolator);
 
         let up_delta = acc.as_vec() - self.up_vector;
+        if acc.as_vec().magnitude() < MIN_ACCELERATION || up_delta.is_zero() {
+            return self.up_vector;
+        }
         let up_direction = up_delta.normalize();
         let shake_factor = normalize(
             self.shakiness,
@@ -201,3 +215,144 @@ impl SpaceMapper for PlayerSpace {
         )
     }
 }
+
+/// Like `WorldSpace`, but the yaw comes from rolling the controller around
+/// its forward axis, projected on the horizontal plane.
+#[derive(Default)]
+pub struct WorldLeanSpace;
+
+impl SpaceMapper for WorldLeanSpace {
+    fn map(&self, rot_speed: RotationSpeed, up_vector: Vector3<f64>) -> Vector2<f64> {
+        let flatness = up_vector.y.abs();
+        let upness = up_vector.z.abs();
+        let side_reduction = (flatness.max(upness) - 0.125).clamp(0., 1.);
+
+        let roll = vec3(0., 0., 1.) - up_vector * up_vector.z;
+        let roll_diff = if roll.magnitude2() > 0. {
+            -side_reduction * rot_speed.as_vec().dot(roll.normalize())
+        } else {
+            0.
+        };
+
+        let pitch = vec3(1., 0., 0.) - up_vector * up_vector.x;
+        let pitch_diff = if pitch.magnitude2() > 0. {
+            side_reduction * rot_speed.as_vec().dot(pitch.normalize())
+        } else {
+            0.
+        };
+        vec2(roll_diff, pitch_diff)
+    }
+}
+
+/// Like `PlayerSpace`, but the yaw comes from rolling the controller.
+pub struct PlayerLeanSpace {
+    roll_relax_factor: f64,
+}
+
+impl Default for PlayerLeanSpace {
+    fn default() -> Self {
+        Self {
+            roll_relax_factor: 1.15,
+        }
+    }
+}
+
+impl SpaceMapper for PlayerLeanSpace {
+    fn map(&self, rot_speed: RotationSpeed, up_vector: Vector3<f64>) -> Vector2<f64> {
+        // Forward axis, perpendicular to the pitch axis and to gravity
+        let world_roll = rot_speed.z * up_vector.y - rot_speed.y * up_vector.z;
+        vec2(
+            -world_roll.signum()
+                * (world_roll.abs() * self.roll_relax_factor)
+                    .min(vec2(rot_speed.y, rot_speed.z).magnitude()),
+            rot_speed.x,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hid_gamepad_types::Acceleration;
+
+    use super::*;
+
+    fn frame(acceleration: Vector3<f64>) -> Motion {
+        Motion {
+            rotation_speed: RotationSpeed {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            acceleration: Acceleration::from(acceleration),
+        }
+    }
+
+    #[test]
+    fn zero_acceleration() {
+        let dt = Duration::from_millis(10);
+        let fusions: [Box<dyn SensorFusion>; 2] = [
+            Box::new(SimpleFusion::new()),
+            Box::new(AdaptativeFusion::new()),
+        ];
+        for mut fusion in fusions {
+            for _ in 0..100 {
+                fusion.compute_up_vector(&frame(vec3(0., 1., 0.)), dt);
+            }
+            let before = fusion.up_vector();
+            let after = fusion.compute_up_vector(&frame(Vector3::zero()), dt);
+            assert_eq!(before, after);
+            assert!(after.x.is_finite() && after.y.is_finite() && after.z.is_finite());
+        }
+    }
+
+    #[test]
+    fn simple_fusion_sample_rate() {
+        // Half a second of the controller lying on its side, at 1000Hz and 60Hz
+        let up_after = |dt: Duration| {
+            let mut fusion = SimpleFusion::new();
+            let samples = (0.5 / dt.as_secs_f64()).round() as usize;
+            for _ in 0..samples {
+                fusion.compute_up_vector(&frame(vec3(1., 0., 0.)), dt);
+            }
+            fusion.up_vector()
+        };
+        let fast = up_after(Duration::from_millis(1));
+        let slow = up_after(Duration::from_micros(16_667));
+        // 1 - e^-2 of the way to the acceleration
+        assert!((fast.x - 0.865).abs() < 0.01, "{:?}", fast);
+        assert!((fast - slow).magnitude() < 0.01, "{:?} {:?}", fast, slow);
+    }
+
+    #[test]
+    fn lean() {
+        let flat = vec3(0., 1., 0.);
+        let roll_right = RotationSpeed {
+            x: 0.,
+            y: 0.,
+            z: -10.,
+        };
+        let turn_right = RotationSpeed {
+            x: 0.,
+            y: -10.,
+            z: 0.,
+        };
+        let mappers: [Box<dyn SpaceMapper>; 2] = [
+            Box::new(WorldLeanSpace),
+            Box::new(PlayerLeanSpace::default()),
+        ];
+        for mapper in mappers {
+            let out = mapper.map(roll_right, flat);
+            assert!(out.x > 0. && out.y == 0., "{:?}", out);
+            assert_eq!(mapper.map(turn_right, flat), Vector2::zero());
+        }
+        // Same speed as turning right in the turn space
+        assert_eq!(PlayerLeanSpace::default().map(roll_right, flat).x, 10.);
+        assert_eq!(PlayerSpace::default().map(turn_right, flat).x, 10.);
+
+        // Leaning right while pointing the controller up
+        let tilted = vec3(0., 1., 1.).normalize();
+        let roll_right = RotationSpeed::from(vec3(0., 1., -1.).normalize() * 10.);
+        let out = WorldLeanSpace.map(roll_right, tilted);
+        assert!(out.x > 0. && out.y.abs() < 1e-9, "{:?}", out);
+    }
+}
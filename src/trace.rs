@@ -0,0 +1,192 @@
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    rc::Rc,
+    time::Instant,
+};
+
+use anyhow::Context;
+use cgmath::Vector2;
+use hid_gamepad_types::{JoyKey, Motion};
+
+use crate::mapping::ExtAction;
+
+/// JSONL log of the inputs and emitted actions, written by `run --trace-file`.
+///
+/// Each line is an object with `t`, the time in seconds since the start of
+/// the trace, `controller` and `event`, plus fields depending on the event:
+///
+/// - `button`: `key`, `pressed`
+/// - `stick`: `side`, `x`, `y`
+/// - `triggers`: `left`, `right`
+/// - `motion`: `rotation_speed` and `acceleration` as `[x, y, z]`
+/// - `action`: `action`, a readable description of the action
+///
+/// Clones share the same file.
+#[derive(Clone)]
+pub struct Trace {
+    out: Rc<RefCell<LineWriter<File>>>,
+    start: Instant,
+    controller: String,
+}
+
+impl Trace {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path).with_context(|| format!("creating trace file {:?}", path))?;
+        Ok(Self {
+            out: Rc::new(RefCell::new(LineWriter::new(file))),
+            start: Instant::now(),
+            controller: String::new(),
+        })
+    }
+
+    /// Handle for the events of another controller, writing to the same file.
+    pub fn for_controller(&self, name: &str) -> Self {
+        Self {
+            controller: name.to_owned(),
+            ..self.clone()
+        }
+    }
+
+    pub fn button(&self, now: Instant, key: JoyKey, pressed: bool) {
+        self.write(
+            now,
+            "button",
+            format_args!(r#""key":"{:?}","pressed":{}"#, key, pressed),
+        );
+    }
+
+    pub fn stick(&self, now: Instant, side: &str, stick: Vector2<f64>) {
+        self.write(
+            now,
+            "stick",
+            format_args!(
+                r#""side":"{}","x":{},"y":{}"#,
+                side,
+                Number(stick.x),
+                Number(stick.y)
+            ),
+        );
+    }
+
+    pub fn triggers(&self, now: Instant, left: f64, right: f64) {
+        self.write(
+            now,
+            "triggers",
+            format_args!(r#""left":{},"right":{}"#, Number(left), Number(right)),
+        );
+    }
+
+    pub fn motion(&self, now: Instant, motion: &Motion) {
+        let rot = motion.rotation_speed;
+        let acc = motion.acceleration;
+        self.write(
+            now,
+            "motion",
+            format_args!(
+                r#""rotation_speed":[{},{},{}],"acceleration":[{},{},{}]"#,
+                Number(rot.x),
+                Number(rot.y),
+                Number(rot.z),
+                Number(acc.x),
+                Number(acc.y),
+                Number(acc.z)
+            ),
+        );
+    }
+
+    pub fn action(&self, now: Instant, action: &ExtAction) {
+        self.write(
+            now,
+            "action",
+            format_args!(r#""action":"{}""#, escape(action)),
+        );
+    }
+
+    fn write(&self, now: Instant, event: &str, fields: std::fmt::Arguments) {
+        let t = now.saturating_duration_since(self.start).as_secs_f64();
+        let result = writeln!(
+            self.out.borrow_mut(),
+            r#"{{"t":{},"controller":"{}","event":"{}",{}}}"#,
+            t,
+            escape(&self.controller),
+            event,
+            fields
+        );
+        if let Err(e) = result {
+            eprintln!("Warning: can't write the trace file: {}", e);
+        }
+    }
+}
+
+/// A JSON number, or `null` for NaN and infinities which JSON can't represent.
+struct Number(f64);
+
+impl Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_finite() {
+            write!(f, "{}", self.0)
+        } else {
+            f.write_str("null")
+        }
+    }
+}
+
+/// Escape a value for use inside a JSON string.
+fn escape(value: impl Display) -> String {
+    let mut out = String::new();
+    for c in value.to_string().chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ClickType;
+
+    use super::*;
+
+    #[test]
+    fn shared_file() {
+        let path = std::env::temp_dir().join(format!("gyromouse-trace-{}", std::process::id()));
+        let trace = Trace::create(&path).unwrap();
+        let now = trace.start;
+        trace
+            .for_controller("Pro \"Controller\"")
+            .button(now, JoyKey::S, true);
+        trace.action(now, &ExtAction::GyroOn(ClickType::Press));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"t":0,"controller":"Pro \"Controller\"","event":"button","key":"S","pressed":true}"#,
+                r#"{"t":0,"controller":"","event":"action","action":"Press gyro on"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn escape_json() {
+        assert_eq!(escape(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape("a\nb"), r#"a\u000ab"#);
+    }
+
+    #[test]
+    fn non_finite_numbers() {
+        assert_eq!(Number(-0.5).to_string(), "-0.5");
+        assert_eq!(Number(f64::NAN).to_string(), "null");
+        assert_eq!(Number(f64::NEG_INFINITY).to_string(), "null");
+    }
+}
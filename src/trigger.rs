@@ -0,0 +1,207 @@
+//! Soft and full pull of the analog triggers, following `ZL_MODE` and
+//! `ZR_MODE`.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::types::TriggerMode,
+    mapping::{Buttons, MapKey},
+};
+
+/// Distance below a threshold needed to release the matching key, so jitter
+/// around the threshold doesn't toggle it.
+const TRIGGER_HYSTERESIS: f64 = 0.05;
+
+/// Normalized value from which the trigger is fully pulled.
+const FULL_PULL: f64 = 0.99;
+
+/// In `MAY_SKIP` modes, a full pull this soon after the soft pull skips the
+/// soft binding.
+const TRIGGER_SKIP_DELAY: Duration = Duration::from_millis(150);
+
+/// State of one trigger, pressing its soft pull key (`ZL`/`ZR`) and its full
+/// pull key (`ZLF`/`ZRF`).
+#[derive(Debug)]
+pub struct Trigger {
+    soft_key: MapKey,
+    full_key: MapKey,
+    /// Past `TRIGGER_THRESHOLD`.
+    pub pulled: bool,
+    /// Past `FULL_PULL`.
+    full: bool,
+    pulled_at: Instant,
+    /// Whether this pull reached `FULL_PULL`.
+    reached_full: bool,
+    /// The soft binding is skipped for the rest of this pull.
+    skipped: bool,
+    soft_down: bool,
+}
+
+impl Trigger {
+    pub fn new(soft_key: impl Into<MapKey>, full_key: impl Into<MapKey>) -> Self {
+        Self {
+            soft_key: soft_key.into(),
+            full_key: full_key.into(),
+            pulled: false,
+            full: false,
+            pulled_at: Instant::now(),
+            reached_full: false,
+            skipped: false,
+            soft_down: false,
+        }
+    }
+
+    /// Update from the normalized trigger value.
+    pub fn update(
+        &mut self,
+        value: f64,
+        threshold: f64,
+        mode: TriggerMode,
+        buttons: &mut Buttons,
+        now: Instant,
+    ) {
+        use TriggerMode::*;
+
+        let was_pulled = self.pulled;
+        self.pulled = past(self.pulled, value, threshold);
+        self.full = past(self.full, value, FULL_PULL);
+        if self.pulled && !was_pulled {
+            self.pulled_at = now;
+            self.reached_full = false;
+            self.skipped = false;
+        }
+        let within_delay = now.duration_since(self.pulled_at) < TRIGGER_SKIP_DELAY;
+        if self.full && !self.reached_full {
+            self.reached_full = true;
+            self.skipped = match mode {
+                MustSkip | MustSkipR => true,
+                MaySkip | MaySkipR => within_delay,
+                NoFull | NoSkip | NoSkipExclusive => false,
+            };
+        }
+
+        let soft_down = match mode {
+            NoFull | NoSkip => self.pulled,
+            NoSkipExclusive => self.pulled && !self.full,
+            // Only known once released, see below
+            MustSkip => false,
+            MaySkip => self.pulled && !self.skipped && !within_delay,
+            MustSkipR | MaySkipR => self.pulled && !self.skipped,
+        };
+        let full_down = mode != NoFull && self.full;
+
+        // Released before the soft binding had a chance to fire
+        if was_pulled
+            && !self.pulled
+            && !self.reached_full
+            && !self.soft_down
+            && matches!(mode, MustSkip | MaySkip)
+        {
+            buttons.key(self.soft_key, true, now);
+        }
+        buttons.key(self.soft_key, soft_down, now);
+        buttons.key(self.full_key, full_down, now);
+        self.soft_down = soft_down;
+    }
+}
+
+fn past(down: bool, value: f64, threshold: f64) -> bool {
+    if down {
+        value > threshold - TRIGGER_HYSTERESIS
+    } else {
+        value > threshold
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use enigo::Key;
+    use hid_gamepad_types::JoyKey;
+
+    use crate::{
+        mapping::{Action, ExtAction, VirtualKey},
+        ClickType,
+    };
+
+    use super::*;
+
+    /// Drive a trigger through `curve`, as (time in ms, value), and return
+    /// the key events: uppercase for presses, `s` for soft and `f` for full.
+    fn events(mode: TriggerMode, curve: &[(u64, f64)]) -> String {
+        let mut buttons = Buttons::new();
+        for (key, c) in [
+            (MapKey::from(JoyKey::ZR), 's'),
+            (MapKey::from(VirtualKey::ZRFull), 'f'),
+        ] {
+            let layer = buttons.get(key, 0);
+            layer.on_down.push(Action::Ext(ExtAction::KeyPress(
+                Key::Unicode(c),
+                ClickType::Press,
+            )));
+            layer.on_up.push(Action::Ext(ExtAction::KeyPress(
+                Key::Unicode(c),
+                ClickType::Release,
+            )));
+        }
+        let mut trigger = Trigger::new(JoyKey::ZR, VirtualKey::ZRFull);
+        let t0 = Instant::now();
+        let mut out = String::new();
+        for &(ms, value) in curve {
+            let now = t0 + Duration::from_millis(ms);
+            trigger.update(value, 0.5, mode, &mut buttons, now);
+            for action in buttons.tick(now) {
+                out.push(match action {
+                    ExtAction::KeyPress(Key::Unicode(c), ClickType::Press) => {
+                        c.to_ascii_uppercase()
+                    }
+                    ExtAction::KeyPress(Key::Unicode(c), ClickType::Release) => c,
+                    _ => unreachable!(),
+                });
+            }
+        }
+        out
+    }
+
+    /// Soft pull released without reaching the full pull.
+    const SOFT: &[(u64, f64)] = &[(0, 0.), (10, 0.6), (300, 0.6), (310, 0.)];
+    /// Quick full pull.
+    const FAST_FULL: &[(u64, f64)] = &[(0, 0.), (10, 0.6), (50, 1.), (300, 1.), (310, 0.)];
+    /// Full pull after resting at the soft pull.
+    const SLOW_FULL: &[(u64, f64)] = &[(0, 0.), (10, 0.6), (200, 1.), (300, 1.), (310, 0.)];
+
+    #[test]
+    fn no_full() {
+        assert_eq!(events(TriggerMode::NoFull, SOFT), "Ss");
+        assert_eq!(events(TriggerMode::NoFull, FAST_FULL), "Ss");
+    }
+
+    #[test]
+    fn no_skip() {
+        assert_eq!(events(TriggerMode::NoSkip, SOFT), "Ss");
+        assert_eq!(events(TriggerMode::NoSkip, FAST_FULL), "SFsf");
+        assert_eq!(events(TriggerMode::NoSkipExclusive, FAST_FULL), "SsFf");
+    }
+
+    #[test]
+    fn must_skip() {
+        assert_eq!(events(TriggerMode::MustSkip, SOFT), "Ss");
+        assert_eq!(events(TriggerMode::MustSkip, SLOW_FULL), "Ff");
+        assert_eq!(events(TriggerMode::MustSkipR, SOFT), "Ss");
+        assert_eq!(events(TriggerMode::MustSkipR, SLOW_FULL), "SsFf");
+    }
+
+    #[test]
+    fn may_skip() {
+        assert_eq!(events(TriggerMode::MaySkip, SOFT), "Ss");
+        assert_eq!(events(TriggerMode::MaySkip, FAST_FULL), "Ff");
+        assert_eq!(events(TriggerMode::MaySkip, SLOW_FULL), "SFsf");
+        assert_eq!(events(TriggerMode::MaySkipR, FAST_FULL), "SsFf");
+        assert_eq!(events(TriggerMode::MaySkipR, SLOW_FULL), "SFsf");
+    }
+
+    #[test]
+    fn jitter_around_threshold() {
+        let curve = [(0, 0.), (10, 0.51), (20, 0.48), (30, 0.52), (40, 0.4)];
+        assert_eq!(events(TriggerMode::NoSkip, &curve), "Ss");
+    }
+}